@@ -1,7 +1,25 @@
-use ed25519_dalek::VerifyingKey;
+use base58::FromBase58;
+use ed25519_dalek::{Signature, SigningKey, VerifyingKey};
 use multibase;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::error::Error;
 
+// Ed25519 public key as a JSON Web Key (RFC 8037), `x` is the key in
+// base64url without padding
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Jwk {
+    pub kty: String,
+    pub crv: String,
+    pub x: String,
+}
+
+// Signing key derived from a seed of any length, the same seed always gives
+// the same key
+pub fn generate_from_seed(seed: &[u8]) -> SigningKey {
+    SigningKey::from_bytes(&Sha256::digest(seed).into())
+}
+
 pub fn encode_public_key_to_multibase(public_key: &VerifyingKey) -> Result<String, Box<dyn Error>> {
     let public_key_bytes: [u8; 32] = public_key.to_bytes();
 
@@ -15,8 +33,11 @@ pub fn encode_public_key_to_multibase(public_key: &VerifyingKey) -> Result<Strin
 
 pub fn decode_multibase_to_public_key(multibase_key: &str) -> Result<VerifyingKey, Box<dyn Error>> {
     let (base, decoded_bytes) = multibase::decode(multibase_key)?;
-    if base != multibase::Base::Base58Btc {
-        return Err("Expected base58btc encoding".into());
+    if !matches!(
+        base,
+        multibase::Base::Base58Btc | multibase::Base::Base64Url
+    ) {
+        return Err("Expected base58btc or base64url encoding".into());
     }
 
     if decoded_bytes.len() != 34 || decoded_bytes[0] != 0xed || decoded_bytes[1] != 0x01 {
@@ -31,10 +52,89 @@ pub fn decode_multibase_to_public_key(multibase_key: &str) -> Result<VerifyingKe
     Ok(public_key)
 }
 
+pub fn verifying_key_to_jwk(public_key: &VerifyingKey) -> Jwk {
+    Jwk {
+        kty: "OKP".to_string(),
+        crv: "Ed25519".to_string(),
+        x: multibase::Base::Base64Url.encode(public_key.to_bytes()),
+    }
+}
+
+pub fn jwk_to_verifying_key(jwk: &Jwk) -> Result<VerifyingKey, Box<dyn Error>> {
+    if jwk.kty != "OKP" || jwk.crv != "Ed25519" {
+        return Err(format!("Unsupported JWK {} {}", jwk.kty, jwk.crv).into());
+    }
+
+    let public_key_bytes: [u8; 32] = multibase::Base::Base64Url
+        .decode(&jwk.x)?
+        .try_into()
+        .map_err(|_| "Invalid public key length")?;
+    let public_key = VerifyingKey::from_bytes(&public_key_bytes)?;
+
+    Ok(public_key)
+}
+
+// Verify a detached base58 signature over a message, e.g. one produced by
+// another implementation, against a publicKeyMultibase
+pub fn verify_detached_signature(
+    multibase_key: &str,
+    base58_signature: &str,
+    message: &[u8],
+) -> Result<bool, Box<dyn Error>> {
+    let public_key = decode_multibase_to_public_key(multibase_key)?;
+    let signature_bytes = base58_signature
+        .from_base58()
+        .map_err(|_| "Signature is not base58 encoded")?;
+    let signature = Signature::from_slice(&signature_bytes)?;
+
+    Ok(public_key.verify_strict(message, &signature).is_ok())
+}
+
+// Verify many signatures at once, which is faster than one by one. A batch
+// only tells whether all of them are valid, so when one isn't each signature
+// is checked again on its own to find which.
+//
+// The batch equation is cofactored while `VerifyingKey::verify` is not, so the
+// two can disagree on signatures built from small-order points. Weak keys and
+// R values are rejected before batching and the fallback uses
+// `verify_strict`, which makes both paths give the same answer.
+pub fn verify_batch(items: &[(&[u8], Signature, VerifyingKey)]) -> Vec<bool> {
+    let strong: Vec<bool> = items
+        .iter()
+        .map(|(_, signature, key)| !key.is_weak() && !is_weak_point(signature.r_bytes()))
+        .collect();
+    let batch: Vec<&(&[u8], Signature, VerifyingKey)> = items
+        .iter()
+        .zip(strong.iter())
+        .filter(|(_, strong)| **strong)
+        .map(|(item, _)| item)
+        .collect();
+    let messages: Vec<&[u8]> = batch.iter().map(|(message, _, _)| *message).collect();
+    let signatures: Vec<Signature> = batch.iter().map(|(_, signature, _)| *signature).collect();
+    let keys: Vec<VerifyingKey> = batch.iter().map(|(_, _, key)| *key).collect();
+    if ed25519_dalek::verify_batch(&messages, &signatures, &keys).is_ok() {
+        return strong;
+    }
+
+    items
+        .iter()
+        .map(|(message, signature, key)| key.verify_strict(message, signature).is_ok())
+        .collect()
+}
+
+// R is an Edwards point like a public key, so the same small-order check
+// applies. Bytes that aren't a point at all are left to the verification.
+fn is_weak_point(bytes: &[u8; 32]) -> bool {
+    VerifyingKey::from_bytes(bytes).is_ok_and(|point| point.is_weak())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use ed25519_dalek::SigningKey;
+    use crate::fixtures::{sample_credential, sample_document};
+    use crate::{verify_vc_resolved, DidStorage, ProofOutcome, VerifiablePresentation};
+    use base58::ToBase58;
+    use ed25519_dalek::{Signer, SigningKey, Verifier};
     use rand::rngs::OsRng;
 
     // Test encoding a public key to publicKeyMultibase
@@ -129,4 +229,134 @@ mod tests {
         let result = decode_multibase_to_public_key(&invalid_multibase);
         assert!(result.is_err(), "Decoding invalid multicodec should fail");
     }
+
+    #[test]
+    fn test_verify_detached_signature() {
+        let signing_key = SigningKey::generate(&mut OsRng);
+        let multibase_key = encode_public_key_to_multibase(&signing_key.verifying_key()).unwrap();
+        let signature = signing_key.sign(b"hello").to_bytes().to_base58();
+
+        assert!(verify_detached_signature(&multibase_key, &signature, b"hello").unwrap());
+        assert!(!verify_detached_signature(&multibase_key, &signature, b"hell0").unwrap());
+
+        let other_key = SigningKey::generate(&mut OsRng).verifying_key();
+        let other_key = encode_public_key_to_multibase(&other_key).unwrap();
+        assert!(!verify_detached_signature(&other_key, &signature, b"hello").unwrap());
+
+        assert!(verify_detached_signature(&multibase_key, "0OIl", b"hello").is_err());
+    }
+
+    #[test]
+    fn test_jwk_round_trip() {
+        let verifying_key = SigningKey::generate(&mut OsRng).verifying_key();
+
+        let jwk = verifying_key_to_jwk(&verifying_key);
+        assert!(!jwk.x.ends_with('='));
+        assert_eq!(jwk_to_verifying_key(&jwk).unwrap(), verifying_key);
+
+        // The same key as base64url multibase
+        let multibase_key = format!(
+            "u{}",
+            multibase::Base::Base64Url
+                .encode([&[0xed, 0x01][..], &verifying_key.to_bytes()].concat())
+        );
+        assert_eq!(
+            decode_multibase_to_public_key(&multibase_key).unwrap(),
+            verifying_key
+        );
+    }
+
+    // Public key of RFC 8037 appendix A.2
+    #[test]
+    fn test_jwk_known_vector() {
+        let jwk: Jwk = serde_json::from_str(
+            r#"{"kty":"OKP","crv":"Ed25519","x":"11qYAYKxCrfVS_7TyWQHOg7hcvPapiMlrwIaaPcHURo"}"#,
+        )
+        .unwrap();
+        let expected: [u8; 32] = [
+            0xd7, 0x5a, 0x98, 0x01, 0x82, 0xb1, 0x0a, 0xb7, 0xd5, 0x4b, 0xfe, 0xd3, 0xc9, 0x64,
+            0x07, 0x3a, 0x0e, 0xe1, 0x72, 0xf3, 0xda, 0xa6, 0x23, 0x25, 0xaf, 0x02, 0x1a, 0x68,
+            0xf7, 0x07, 0x51, 0x1a,
+        ];
+
+        let verifying_key = jwk_to_verifying_key(&jwk).unwrap();
+        assert_eq!(verifying_key.to_bytes(), expected);
+        assert_eq!(verifying_key_to_jwk(&verifying_key), jwk);
+
+        let mut wrong_curve = jwk.clone();
+        wrong_curve.crv = "X25519".to_string();
+        assert!(jwk_to_verifying_key(&wrong_curve).is_err());
+    }
+
+    #[test]
+    fn test_verify_batch_agrees_with_single_verification() {
+        let signers: Vec<SigningKey> = (0..4).map(|_| SigningKey::generate(&mut OsRng)).collect();
+        let messages: Vec<Vec<u8>> = (0..4)
+            .map(|i| format!("message {}", i).into_bytes())
+            .collect();
+        let mut items: Vec<(&[u8], Signature, VerifyingKey)> = signers
+            .iter()
+            .zip(messages.iter())
+            .map(|(signer, message)| {
+                (
+                    message.as_slice(),
+                    signer.sign(message),
+                    signer.verifying_key(),
+                )
+            })
+            .collect();
+        assert_eq!(verify_batch(&items), vec![true; 4]);
+
+        // Signed by someone else, and signed over another message
+        items[1].2 = signers[0].verifying_key();
+        items[3].1 = signers[3].sign(b"something else");
+        let single: Vec<bool> = items
+            .iter()
+            .map(|(message, signature, key)| key.verify_strict(message, signature).is_ok())
+            .collect();
+        assert_eq!(single, vec![true, false, true, false]);
+        assert_eq!(verify_batch(&items), single);
+
+        assert!(verify_batch(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_verify_batch_rejects_small_order_keys() {
+        // The identity point as key and R, with s = 0, passes the
+        // cofactorless equation for any message
+        let mut identity = [0u8; 32];
+        identity[0] = 1;
+        let weak_key = VerifyingKey::from_bytes(&identity).unwrap();
+        let mut forged = [0u8; 64];
+        forged[..32].copy_from_slice(&identity);
+        let forged = Signature::from_bytes(&forged);
+        assert!(weak_key.verify(b"anything", &forged).is_ok());
+
+        let signer = SigningKey::generate(&mut OsRng);
+        let items: Vec<(&[u8], Signature, VerifyingKey)> = vec![
+            (b"anything", forged, weak_key),
+            (b"message", signer.sign(b"message"), signer.verifying_key()),
+        ];
+        assert_eq!(verify_batch(&items), vec![false, true]);
+
+        // Credentials and presentations are checked one by one the same way
+        let did = "did:example:weak";
+        let mut document = sample_document(did);
+        document.verification_method[0].public_key_base58 =
+            Some(encode_public_key_to_multibase(&weak_key).unwrap());
+        let mut storage = DidStorage::new();
+        storage.store(did.to_string(), document).unwrap();
+        let forged_value = multibase::encode(multibase::Base::Base58Btc, forged.to_bytes());
+
+        let mut vc = sample_credential(did, did, 700);
+        vc.proof.proof_value = Some(forged_value.clone());
+        assert!(!verify_vc_resolved(&vc, &storage).unwrap());
+
+        let mut vp = VerifiablePresentation::new(did, vec![vc], &signer).unwrap();
+        vp.proof.proof_value = Some(forged_value);
+        let report = vp.verify_full(&storage);
+        assert_eq!(report.holder_outcome, ProofOutcome::InvalidProof);
+        assert_eq!(report.credentials[0].outcome, ProofOutcome::InvalidProof);
+        assert!(!report.valid);
+    }
 }
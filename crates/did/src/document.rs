@@ -1,7 +1,68 @@
+use chrono::{DateTime, Utc};
+use ed25519_dalek::{SigningKey, VerifyingKey};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+
+use crate::verification_credential::{sign_payload, verify_payload};
+use crate::{decode_multibase_to_public_key, jwk_to_verifying_key, Jwk, Proof, DID};
+
+// Base context of DID documents, which must come first in @context
+pub static DID_CONTEXT: &str = "https://www.w3.org/ns/did/v1";
+
+// Contexts trusted by default, the W3C DID context and the security contexts
+// of the supported verification method types
+pub static TRUSTED_CONTEXTS: [&str; 6] = [
+    DID_CONTEXT,
+    "https://w3id.org/security/v1",
+    "https://w3id.org/security/v2",
+    "https://w3id.org/security/suites/ed25519-2020/v1",
+    "https://w3id.org/security/suites/ed25519-2018/v1",
+    "https://w3id.org/security/suites/jws-2020/v1",
+];
+
+// Which @context URLs ingested documents may use. Remote contexts change how
+// a JSON-LD processor reads the document, so unknown ones are rejected unless
+// the policy is permissive
+#[derive(Debug, Clone, PartialEq)]
+pub enum ContextPolicy {
+    Allowlist(Vec<String>),
+    Permissive,
+}
+
+impl Default for ContextPolicy {
+    fn default() -> Self {
+        ContextPolicy::Allowlist(TRUSTED_CONTEXTS.iter().map(|c| c.to_string()).collect())
+    }
+}
+
+impl ContextPolicy {
+    pub fn allows(&self, context: &str) -> bool {
+        match self {
+            ContextPolicy::Allowlist(contexts) => contexts.iter().any(|c| c == context),
+            ContextPolicy::Permissive => true,
+        }
+    }
+}
+
+impl TryFrom<String> for ContextPolicy {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "strict" => Ok(ContextPolicy::default()),
+            "permissive" => Ok(ContextPolicy::Permissive),
+            _ => Err(format!(
+                "Invalid context policy {}, expected strict or permissive",
+                value
+            )),
+        }
+    }
+}
 
 // Represents a verification method in the DID Document
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct VerificationMethod {
     pub id: String,
     #[serde(rename = "type")]
@@ -11,10 +72,37 @@ pub struct VerificationMethod {
     pub public_key_hex: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub public_key_base58: Option<String>,
+    #[serde(
+        rename = "publicKeyJwk",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub public_key_jwk: Option<Jwk>,
+    // When a rotated out key stops being accepted, RFC 3339
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires: Option<String>,
+}
+
+impl VerificationMethod {
+    // The public key, from the multibase or the JWK form
+    pub fn verifying_key(&self) -> Option<VerifyingKey> {
+        match (&self.public_key_base58, &self.public_key_jwk) {
+            (Some(multibase_key), _) => decode_multibase_to_public_key(multibase_key).ok(),
+            (None, Some(jwk)) => jwk_to_verifying_key(jwk).ok(),
+            (None, None) => None,
+        }
+    }
+
+    // Whether the method was rotated out and its grace period is over
+    pub fn is_expired(&self) -> bool {
+        self.expires.as_ref().is_some_and(|expires| {
+            DateTime::parse_from_rfc3339(expires).map_or(true, |expires| expires < Utc::now())
+        })
+    }
 }
 
 // Represents a service in the DID Document
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct Service {
     pub id: String,
     #[serde(rename = "type")]
@@ -23,34 +111,73 @@ pub struct Service {
     pub service_endpoint: String,
 }
 
+// Service type pointing at where an issuer's credential schemas live
+pub static CREDENTIAL_REGISTRY_TYPE: &str = "CredentialRegistry";
+
+impl Service {
+    // Service publishing the schema endpoint of an issuer
+    pub fn credential_registry(did: &str, schema_endpoint: &str) -> Self {
+        Service {
+            id: format!("{}#credential-registry", did),
+            type_: CREDENTIAL_REGISTRY_TYPE.to_string(),
+            service_endpoint: schema_endpoint.to_string(),
+        }
+    }
+}
+
 // Represents the DID Document
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct DidDocument {
     #[serde(rename = "@context")]
     pub context: Vec<String>,
     pub id: String,
-    #[serde(rename = "verificationMethod", skip_serializing_if = "Vec::is_empty")]
+    #[serde(
+        rename = "verificationMethod",
+        default,
+        skip_serializing_if = "Vec::is_empty"
+    )]
     pub verification_method: Vec<VerificationMethod>,
-    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub authentication: Vec<String>,
+    // Methods allowed to sign assertions such as credentials
+    #[serde(
+        rename = "assertionMethod",
+        default,
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub assertion_method: Vec<String>,
+    // Other identifiers of the same subject, e.g. another DID
+    #[serde(rename = "alsoKnownAs", default, skip_serializing_if = "Vec::is_empty")]
+    pub also_known_as: Vec<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub service: Option<Vec<Service>>,
+    // Signature of the document by one of its own authentication methods
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proof: Option<Proof>,
 }
 
 impl DidDocument {
     // Constructor for a minimal DID Document
     pub fn new(did: &str) -> Self {
         DidDocument {
-            context: vec!["https://www.w3.org/ns/did/v1".to_string()],
+            context: vec![DID_CONTEXT.to_string()],
             id: did.to_string(),
             verification_method: vec![],
             authentication: vec![],
+            assertion_method: vec![],
+            also_known_as: vec![],
             service: None,
+            proof: None,
         }
     }
 
-    // Add a verification method
+    // Add a verification method, along with the JSON-LD context defining its type
     pub fn add_verification_method(&mut self, vm: VerificationMethod) {
+        if let Some(context) = security_context(&vm.vc_type) {
+            if !self.context.iter().any(|c| c == context) {
+                self.context.push(context.to_string());
+            }
+        }
         self.verification_method.push(vm);
     }
 
@@ -59,19 +186,366 @@ impl DidDocument {
         self.authentication.push(auth_id.to_string());
     }
 
+    // Add an assertion method reference
+    pub fn add_assertion_method(&mut self, method_id: &str) {
+        self.assertion_method.push(method_id.to_string());
+    }
+
+    // Whether the method is listed under the verification relationship a
+    // proof purpose refers to, e.g. "assertionMethod"
+    pub fn has_relationship(&self, proof_purpose: &str, method_id: &str) -> bool {
+        let references = match proof_purpose {
+            "authentication" => &self.authentication,
+            "assertionMethod" => &self.assertion_method,
+            _ => return false,
+        };
+
+        references.iter().any(|reference| reference == method_id)
+    }
+
+    // Method ids listed under each verification relationship
+    pub fn relationships(&self) -> BTreeMap<&'static str, Vec<String>> {
+        BTreeMap::from([
+            ("assertionMethod", self.assertion_method.clone()),
+            ("authentication", self.authentication.clone()),
+        ])
+    }
+
     // Add a service
     pub fn add_service(&mut self, service: Service) {
-        if let Some(mut svs) = self.service.take() {
-            svs.push(service);
-        } else {
-            self.service = Some(vec![service]);
+        self.service.get_or_insert_with(Vec::new).push(service);
+    }
+
+    // Sort verification methods, authentication references, aliases and
+    // services so that documents with the same content always serialize
+    // identically
+    pub fn canonical_sort(&mut self) {
+        self.verification_method.sort_by(|a, b| a.id.cmp(&b.id));
+        self.authentication.sort();
+        self.assertion_method.sort();
+        self.also_known_as.sort();
+        if let Some(svs) = self.service.as_mut() {
+            svs.sort_by(|a, b| a.id.cmp(&b.id));
+        }
+    }
+
+    // Canonical form of the document, whoever built it: the base DID context
+    // first and every context once, verification methods and services
+    // sorted by id, relationship references and aliases sorted without
+    // duplicates
+    pub fn normalize(&mut self) {
+        let mut seen = HashSet::new();
+        self.context.retain(|context| seen.insert(context.clone()));
+        if let Some(base) = self.context.iter().position(|c| c == DID_CONTEXT) {
+            let base = self.context.remove(base);
+            self.context.insert(0, base);
+        }
+        self.canonical_sort();
+        self.authentication.dedup();
+        self.assertion_method.dedup();
+        self.also_known_as.dedup();
+    }
+
+    // Compact JSON with sorted keys, after normalizing the document, so
+    // documents with the same content always give the same bytes
+    pub fn to_canonical_json(&self) -> Result<String, serde_json::Error> {
+        let mut document = self.clone();
+        document.normalize();
+
+        // serde_json's Value keeps object keys sorted
+        Ok(serde_json::to_value(&document)?.to_string())
+    }
+
+    // SHA-256 multihash of the canonical JSON, multibase base58btc encoded.
+    // Stable across field order, usable as a version id
+    pub fn content_hash(&self) -> String {
+        let json = self
+            .to_canonical_json()
+            .expect("Failed to serialize document");
+        // Multihash header: sha2-256, 32 bytes
+        let mut multihash = vec![0x12, 0x20];
+        multihash.extend_from_slice(&Sha256::digest(json.as_bytes()));
+
+        multibase::encode(multibase::Base::Base58Btc, multihash)
+    }
+
+    // Compare two documents by content, ignoring the order of verification
+    // methods, authentication references, aliases and services, and repeated
+    // entries
+    pub fn semantically_eq(&self, other: &DidDocument) -> bool {
+        let (mut first, mut second) = (self.clone(), other.clone());
+        first.normalize();
+        second.normalize();
+
+        first.id == second.id
+            && first.context == second.context
+            && first.verification_method == second.verification_method
+            && first.authentication == second.authentication
+            && first.assertion_method == second.assertion_method
+            && first.also_known_as == second.also_known_as
+            && first.service.unwrap_or_default() == second.service.unwrap_or_default()
+    }
+
+    // Sign the document with one of its authentication methods, replacing
+    // any previous proof. The document is normalized first, so storing it
+    // keeps the proof valid
+    pub fn sign(&mut self, signer: &SigningKey, method_id: &str) -> Result<(), serde_json::Error> {
+        self.normalize();
+        self.proof = Some(Proof {
+            proof_type: "Ed25519Signature2020".to_string(),
+            created: Utc::now().to_rfc3339(),
+            proof_purpose: "authentication".to_string(),
+            verification_method: method_id.to_string(),
+            proof_value: None,
+            nonce: None,
+        });
+        let proof_value = sign_payload(self, signer)?;
+        if let Some(proof) = self.proof.as_mut() {
+            proof.proof_value = Some(proof_value);
+        }
+
+        Ok(())
+    }
+
+    // Whether the embedded proof was made by a key the document itself lists
+    // for the proof's purpose, and that hasn't expired
+    pub fn verify_proof(&self) -> bool {
+        let mut unsigned = self.clone();
+        let Some(proof_value) = unsigned
+            .proof
+            .as_mut()
+            .and_then(|proof| proof.proof_value.take())
+        else {
+            return false;
+        };
+        let Some(proof) = unsigned.proof.as_ref() else {
+            return false;
+        };
+        if !self.has_relationship(&proof.proof_purpose, &proof.verification_method) {
+            return false;
         }
+        let Some(vr_key) = self
+            .verification_method
+            .iter()
+            .find(|vm| vm.id == proof.verification_method && !vm.is_expired())
+            .and_then(VerificationMethod::verifying_key)
+        else {
+            return false;
+        };
+
+        verify_payload(&unsigned, &proof_value, &vr_key).unwrap_or(false)
     }
 
-    // Serialize to JSON string
+    // Find the endpoint of the service whose id fragment matches the name,
+    // e.g. "messaging" for "did:example:123#messaging"
+    pub fn dereference_service(&self, service_name: &str) -> Option<&str> {
+        self.service
+            .as_ref()?
+            .iter()
+            .find(|sv| {
+                sv.id == service_name
+                    || sv.id.rsplit_once('#').map(|(_, fragment)| fragment) == Some(service_name)
+            })
+            .map(|sv| sv.service_endpoint.as_str())
+    }
+
+    // First service of the given type, e.g. "CredentialRegistry"
+    pub fn get_service_by_type(&self, type_: &str) -> Option<&Service> {
+        self.service.as_ref()?.iter().find(|sv| sv.type_ == type_)
+    }
+
+    // Serialize to JSON string, pretty printed for people reading it
     pub fn to_json(&self) -> Result<String, serde_json::Error> {
         serde_json::to_string_pretty(self)
     }
+
+    // Serialize to JSON on a single line, for machines and payloads
+    pub fn to_json_compact(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string(self)
+    }
+
+    // Parse a document from JSON and validate it, with the trusted contexts
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        Self::from_json_with(json, &ContextPolicy::default())
+    }
+
+    // Parse a document from JSON and validate it, with the given contexts
+    pub fn from_json_with(json: &str, policy: &ContextPolicy) -> Result<Self, String> {
+        let document: DidDocument =
+            serde_json::from_str(json).map_err(|err| format!("Invalid document: {}", err))?;
+        document
+            .validate_quick_with(policy)
+            .map_err(|err| err.to_string())?;
+
+        Ok(document)
+    }
+
+    // Check the document, all problems found are reported at once
+    pub fn validate(&self) -> Result<(), Vec<ValidationError>> {
+        self.validate_with(&ContextPolicy::default())
+    }
+
+    pub fn validate_with(&self, policy: &ContextPolicy) -> Result<(), Vec<ValidationError>> {
+        let errors = self.problems(false, policy);
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    // Same checks as validate, stopping at the first problem
+    pub fn validate_quick(&self) -> Result<(), ValidationError> {
+        self.validate_quick_with(&ContextPolicy::default())
+    }
+
+    pub fn validate_quick_with(&self, policy: &ContextPolicy) -> Result<(), ValidationError> {
+        match self.problems(true, policy).into_iter().next() {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    // Problems of the document: the DID must be well formed, the base context
+    // must come first, every context must be allowed by the policy,
+    // controllers must be DIDs, ids must be unique, service endpoints must be
+    // URIs and every authentication and assertion reference must point at one
+    // of its methods
+    fn problems(&self, quick: bool, policy: &ContextPolicy) -> Vec<ValidationError> {
+        let mut errors = Vec::new();
+        if let Err(err) = DID::new(&self.id) {
+            errors.push(ValidationError::InvalidDid(err.to_string()));
+        }
+
+        if let Some(first) = self.context.first() {
+            if first != DID_CONTEXT {
+                errors.push(ValidationError::ContextOrder(first.clone()));
+            }
+        }
+        for context in self.context.iter() {
+            if !policy.allows(context) {
+                errors.push(ValidationError::UntrustedContext(context.clone()));
+            }
+        }
+        if quick && !errors.is_empty() {
+            return errors;
+        }
+
+        for vm in self.verification_method.iter() {
+            if DID::new(&vm.controller).is_err() {
+                errors.push(ValidationError::InvalidController {
+                    method: vm.id.clone(),
+                    controller: vm.controller.clone(),
+                });
+            }
+        }
+
+        let services = self.service.iter().flatten().map(|sv| &sv.id);
+        let mut ids = HashSet::new();
+        for id in self
+            .verification_method
+            .iter()
+            .map(|vm| &vm.id)
+            .chain(services)
+        {
+            if !ids.insert(id) {
+                errors.push(ValidationError::DuplicateId(id.clone()));
+            }
+        }
+
+        for sv in self.service.iter().flatten() {
+            if !is_uri(&sv.service_endpoint) {
+                errors.push(ValidationError::InvalidServiceEndpoint {
+                    service: sv.id.clone(),
+                    endpoint: sv.service_endpoint.clone(),
+                });
+            }
+        }
+        if quick && !errors.is_empty() {
+            return errors;
+        }
+
+        for auth in self.authentication.iter() {
+            if !self.verification_method.iter().any(|vm| &vm.id == auth) {
+                errors.push(ValidationError::UnknownAuthentication(auth.clone()));
+            }
+        }
+        for assertion in self.assertion_method.iter() {
+            if !self
+                .verification_method
+                .iter()
+                .any(|vm| &vm.id == assertion)
+            {
+                errors.push(ValidationError::UnknownAssertion(assertion.clone()));
+            }
+        }
+
+        errors
+    }
+}
+
+// A problem found by DidDocument::validate
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValidationError {
+    InvalidDid(String),
+    // The first @context, which isn't the base context
+    ContextOrder(String),
+    UntrustedContext(String),
+    InvalidController { method: String, controller: String },
+    DuplicateId(String),
+    InvalidServiceEndpoint { service: String, endpoint: String },
+    UnknownAuthentication(String),
+    UnknownAssertion(String),
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationError::InvalidDid(err) => write!(f, "{}", err),
+            ValidationError::ContextOrder(first) => write!(
+                f,
+                "The first @context must be {}, got {}",
+                DID_CONTEXT, first
+            ),
+            ValidationError::UntrustedContext(context) => {
+                write!(f, "Untrusted @context: {}", context)
+            }
+            ValidationError::InvalidController { method, controller } => {
+                write!(f, "Controller of {} is not a DID: {}", method, controller)
+            }
+            ValidationError::DuplicateId(id) => write!(f, "Duplicate id: {}", id),
+            ValidationError::InvalidServiceEndpoint { service, endpoint } => write!(
+                f,
+                "Endpoint of {} is not a valid URI: {}",
+                service, endpoint
+            ),
+            ValidationError::UnknownAuthentication(id) => {
+                write!(f, "Unknown authentication method: {}", id)
+            }
+            ValidationError::UnknownAssertion(id) => write!(f, "Unknown assertion method: {}", id),
+        }
+    }
+}
+
+// Absolute URI with a scheme, e.g. https://example.com/vc/ or a DID URL
+fn is_uri(value: &str) -> bool {
+    let Some((scheme, rest)) = value.split_once(':') else {
+        return false;
+    };
+    let mut scheme_chars = scheme.chars();
+    scheme_chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && scheme_chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+        && !rest.trim_start_matches('/').is_empty()
+        && !value.chars().any(char::is_whitespace)
+}
+
+// Security vocabulary context of a verification method type
+fn security_context(vc_type: &str) -> Option<&'static str> {
+    match vc_type {
+        "Ed25519VerificationKey2020" => Some("https://w3id.org/security/suites/ed25519-2020/v1"),
+        "Ed25519VerificationKey2018" => Some("https://w3id.org/security/suites/ed25519-2018/v1"),
+        _ => None,
+    }
 }
 
 pub fn generate_document(
@@ -89,6 +563,8 @@ pub fn generate_document(
         controller: did.to_string(),
         public_key_hex: None,
         public_key_base58: base58_signing_key,
+        expires: None,
+        public_key_jwk: None,
     };
     did_doc.add_verification_method(verification_method);
 
@@ -115,6 +591,60 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn test_signed_document_verifies_its_own_proof() {
+        let signer = SigningKey::generate(&mut OsRng);
+        let did = "did:example:123456789abcdefghi";
+        let key = encode_public_key_to_multibase(&signer.verifying_key()).unwrap();
+        let mut doc = generate_document(did, Some(key)).unwrap();
+        assert!(!doc.verify_proof());
+
+        doc.sign(&signer, &format!("{}#key1", did)).unwrap();
+        let parsed = DidDocument::from_json(&doc.to_json().unwrap()).unwrap();
+        assert!(parsed.verify_proof());
+
+        // Any change after signing breaks the proof
+        let mut tampered = parsed.clone();
+        tampered.add_authentication(&format!("{}#key2", did));
+        assert!(!tampered.verify_proof());
+
+        // So does a key the document doesn't list
+        let mut foreign = parsed;
+        foreign
+            .sign(&SigningKey::generate(&mut OsRng), &format!("{}#key1", did))
+            .unwrap();
+        assert!(!foreign.verify_proof());
+    }
+
+    #[test]
+    fn test_proof_by_an_expired_method_does_not_verify() {
+        let signer = SigningKey::generate(&mut OsRng);
+        let did = "did:example:123456789abcdefghi";
+        let key = encode_public_key_to_multibase(&signer.verifying_key()).unwrap();
+        let mut doc = generate_document(did, Some(key)).unwrap();
+
+        // Rotated out, still within its grace period
+        doc.verification_method[0].expires =
+            Some((Utc::now() + chrono::Duration::hours(1)).to_rfc3339());
+        doc.sign(&signer, &format!("{}#key1", did)).unwrap();
+        assert!(doc.verify_proof());
+
+        // Grace period over
+        doc.verification_method[0].expires =
+            Some((Utc::now() - chrono::Duration::hours(1)).to_rfc3339());
+        doc.sign(&signer, &format!("{}#key1", did)).unwrap();
+        assert!(!doc.verify_proof());
+    }
+
+    #[test]
+    fn test_minimal_document_deserializes() {
+        let json = r#"{ "@context": ["https://www.w3.org/ns/did/v1"], "id": "did:example:123" }"#;
+        let doc = DidDocument::from_json(json).unwrap();
+        assert!(doc.verification_method.is_empty());
+        assert!(doc.authentication.is_empty());
+        assert!(doc.semantically_eq(&DidDocument::new("did:example:123")));
+    }
+
     #[test]
     fn test_generate_document() {
         let did = "did:example:123456789abcdefghi";
@@ -137,4 +667,306 @@ mod tests {
 
         assert!(doc.is_ok());
     }
+
+    #[test]
+    fn test_generated_document_has_security_context() {
+        let did = "did:example:123456789abcdefghi";
+        let doc = generate_document(did, None).unwrap();
+        assert_eq!(
+            doc.context,
+            vec![
+                "https://www.w3.org/ns/did/v1",
+                "https://w3id.org/security/suites/ed25519-2020/v1"
+            ]
+        );
+
+        let mut doc = DidDocument::new(did);
+        for n in ["key1", "key2"] {
+            doc.add_verification_method(VerificationMethod {
+                id: format!("{}#{}", did, n),
+                vc_type: "Ed25519VerificationKey2018".to_string(),
+                controller: did.to_string(),
+                public_key_hex: None,
+                public_key_base58: None,
+                expires: None,
+                public_key_jwk: None,
+            });
+        }
+        assert_eq!(
+            doc.context,
+            vec![
+                "https://www.w3.org/ns/did/v1",
+                "https://w3id.org/security/suites/ed25519-2018/v1"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_from_json() {
+        let did = "did:example:123456789abcdefghi";
+        let doc = generate_document(did, None).unwrap();
+        let parsed = DidDocument::from_json(&doc.to_json().unwrap()).unwrap();
+        assert_eq!(parsed.id, did);
+
+        let mut dangling = doc.clone();
+        dangling.add_authentication(&format!("{}#key2", did));
+        assert!(DidDocument::from_json(&dangling.to_json().unwrap()).is_err());
+
+        let invalid_did = DidDocument::new("example:123");
+        assert!(DidDocument::from_json(&invalid_did.to_json().unwrap()).is_err());
+        assert!(DidDocument::from_json("{").is_err());
+    }
+
+    #[test]
+    fn test_to_json_compact() {
+        let doc = generate_document("did:example:123456789abcdefghi", None).unwrap();
+
+        let compact = doc.to_json_compact().unwrap();
+        assert!(!compact.contains('\n'));
+        assert!(compact.len() < doc.to_json().unwrap().len());
+        assert!(DidDocument::from_json(&compact)
+            .unwrap()
+            .semantically_eq(&doc));
+    }
+
+    #[test]
+    fn test_base_context_must_come_first() {
+        let did = "did:example:123456789abcdefghi";
+        let doc = generate_document(did, None).unwrap();
+        assert_eq!(doc.context[0], DID_CONTEXT);
+        assert!(doc.validate().is_ok());
+
+        let mut reordered = doc.clone();
+        reordered.context.rotate_left(1);
+        assert_eq!(reordered.context[1], DID_CONTEXT);
+        assert_eq!(
+            DidDocument::from_json(&reordered.to_json().unwrap()).unwrap_err(),
+            format!(
+                "The first @context must be {}, got {}",
+                DID_CONTEXT, reordered.context[0]
+            )
+        );
+    }
+
+    #[test]
+    fn test_unknown_context_needs_permissive_policy() {
+        let did = "did:example:123456789abcdefghi";
+        let mut doc = generate_document(did, None).unwrap();
+        doc.context
+            .push("https://attacker.example/context.jsonld".to_string());
+        let json = doc.to_json().unwrap();
+
+        assert_eq!(
+            DidDocument::from_json(&json).unwrap_err(),
+            "Untrusted @context: https://attacker.example/context.jsonld"
+        );
+        assert!(DidDocument::from_json_with(&json, &ContextPolicy::Permissive).is_ok());
+
+        let custom = ContextPolicy::Allowlist(doc.context.clone());
+        assert!(doc.validate_with(&custom).is_ok());
+        assert_eq!(
+            ContextPolicy::try_from("permissive".to_string()),
+            Ok(ContextPolicy::Permissive)
+        );
+    }
+
+    #[test]
+    fn test_validate_reports_all_problems() {
+        let did = "did:example:123456789abcdefghi";
+        let mut doc = generate_document(did, None).unwrap();
+        let mut duplicate = doc.verification_method[0].clone();
+        duplicate.controller = "alice".to_string();
+        doc.verification_method.push(duplicate);
+        doc.authentication.push(format!("{}#missing", did));
+        doc.service.get_or_insert_with(Vec::new).push(Service {
+            id: format!("{}#vc", did),
+            type_: "VerifiableCredentialService".to_string(),
+            service_endpoint: "not a url".to_string(),
+        });
+
+        let errors = doc.validate().unwrap_err();
+        assert_eq!(
+            errors,
+            vec![
+                ValidationError::InvalidController {
+                    method: format!("{}#key1", did),
+                    controller: "alice".to_string(),
+                },
+                ValidationError::DuplicateId(format!("{}#key1", did)),
+                ValidationError::InvalidServiceEndpoint {
+                    service: format!("{}#vc", did),
+                    endpoint: "not a url".to_string(),
+                },
+                ValidationError::UnknownAuthentication(format!("{}#missing", did)),
+            ]
+        );
+        assert_eq!(doc.validate_quick().unwrap_err(), errors[0]);
+        assert_eq!(errors[1].to_string(), format!("Duplicate id: {}#key1", did));
+    }
+
+    #[test]
+    fn test_dereference_service() {
+        let did = "did:example:123456789abcdefghi";
+        let mut doc = DidDocument::new(did);
+        doc.add_service(Service {
+            id: format!("{}#messaging", did),
+            type_: "DIDCommMessaging".to_string(),
+            service_endpoint: "https://example.com/didcomm".to_string(),
+        });
+
+        assert_eq!(
+            doc.dereference_service("messaging"),
+            Some("https://example.com/didcomm")
+        );
+        assert_eq!(doc.dereference_service("vcs"), None);
+        assert_eq!(DidDocument::new(did).dereference_service("messaging"), None);
+    }
+
+    #[test]
+    fn test_canonical_sort_ignores_insertion_order() {
+        let did = "did:example:123456789abcdefghi";
+        let key = |n: &str| VerificationMethod {
+            id: format!("{}#{}", did, n),
+            vc_type: "Ed25519VerificationKey2020".to_string(),
+            controller: did.to_string(),
+            public_key_hex: None,
+            public_key_base58: None,
+            expires: None,
+            public_key_jwk: None,
+        };
+        let service = |n: &str| Service {
+            id: format!("{}#{}", did, n),
+            type_: "VerifiableCredentialService".to_string(),
+            service_endpoint: format!("https://example.com/{}/", n),
+        };
+
+        let mut first = DidDocument::new(did);
+        first.add_verification_method(key("key1"));
+        first.add_verification_method(key("key2"));
+        first.add_authentication(&format!("{}#key1", did));
+        first.add_authentication(&format!("{}#key2", did));
+        first.add_service(service("messaging"));
+        first.add_service(service("vcs"));
+
+        let mut second = DidDocument::new(did);
+        second.add_service(service("vcs"));
+        second.add_service(service("messaging"));
+        second.add_authentication(&format!("{}#key2", did));
+        second.add_authentication(&format!("{}#key1", did));
+        second.add_verification_method(key("key2"));
+        second.add_verification_method(key("key1"));
+
+        assert_ne!(first.to_json().unwrap(), second.to_json().unwrap());
+
+        first.canonical_sort();
+        second.canonical_sort();
+        assert_eq!(first.to_json().unwrap(), second.to_json().unwrap());
+    }
+
+    #[test]
+    fn test_semantically_eq_ignores_insertion_order() {
+        let did = "did:example:123456789abcdefghi";
+        let key = |n: &str| VerificationMethod {
+            id: format!("{}#{}", did, n),
+            vc_type: "Ed25519VerificationKey2020".to_string(),
+            controller: did.to_string(),
+            public_key_hex: None,
+            public_key_base58: None,
+            expires: None,
+            public_key_jwk: None,
+        };
+
+        let mut first = DidDocument::new(did);
+        first.add_verification_method(key("key1"));
+        first.add_verification_method(key("key2"));
+        first.add_authentication(&format!("{}#key1", did));
+        first.add_authentication(&format!("{}#key2", did));
+
+        let mut second = DidDocument::new(did);
+        second.add_authentication(&format!("{}#key2", did));
+        second.add_authentication(&format!("{}#key1", did));
+        second.add_verification_method(key("key2"));
+        second.add_verification_method(key("key1"));
+        second.service = Some(vec![]);
+
+        assert!(first.semantically_eq(&second));
+
+        second.add_service(Service {
+            id: format!("{}#vcs", did),
+            type_: "VerifiableCredentialService".to_string(),
+            service_endpoint: "https://example.com/vc/".to_string(),
+        });
+        assert!(!first.semantically_eq(&second));
+    }
+
+    #[test]
+    fn test_semantically_eq_compares_aliases() {
+        let did = "did:example:123";
+        let mut first = DidDocument::new(did);
+        first.also_known_as = vec!["did:example:456".to_string(), "did:example:789".to_string()];
+        let mut second = DidDocument::new(did);
+        second.also_known_as = vec![
+            "did:example:789".to_string(),
+            "did:example:456".to_string(),
+            "did:example:789".to_string(),
+        ];
+        assert!(first.semantically_eq(&second));
+        assert_eq!(first.content_hash(), second.content_hash());
+
+        second.also_known_as = vec!["did:example:456".to_string()];
+        assert!(!first.semantically_eq(&second));
+        assert!(!DidDocument::new(did).semantically_eq(&first));
+    }
+
+    #[test]
+    fn test_content_hash_ignores_field_order() {
+        let first = DidDocument::from_json(
+            r#"{
+                "@context": ["https://www.w3.org/ns/did/v1"],
+                "id": "did:example:123",
+                "verificationMethod": [
+                    {"id": "did:example:123#key1", "type": "Ed25519VerificationKey2020", "controller": "did:example:123"},
+                    {"id": "did:example:123#key2", "type": "Ed25519VerificationKey2020", "controller": "did:example:123"}
+                ],
+                "authentication": ["did:example:123#key1"]
+            }"#,
+        )
+        .unwrap();
+        let second = DidDocument::from_json(
+            r#"{"authentication":["did:example:123#key1"],"id":"did:example:123",
+                "verificationMethod":[
+                    {"controller":"did:example:123","type":"Ed25519VerificationKey2020","id":"did:example:123#key2"},
+                    {"type":"Ed25519VerificationKey2020","id":"did:example:123#key1","controller":"did:example:123"}
+                ],"@context":["https://www.w3.org/ns/did/v1"]}"#,
+        )
+        .unwrap();
+
+        assert_eq!(first.content_hash(), second.content_hash());
+        assert!(first.content_hash().starts_with('z'));
+
+        let mut changed = second.clone();
+        changed.add_authentication("did:example:123#key2");
+        assert_ne!(first.content_hash(), changed.content_hash());
+    }
+
+    #[test]
+    fn test_get_service_by_type() {
+        let did = "did:example:issuer";
+        let mut doc = generate_document(did, None).unwrap();
+        assert!(doc.get_service_by_type(CREDENTIAL_REGISTRY_TYPE).is_none());
+
+        doc.add_service(Service::credential_registry(
+            did,
+            "https://example.com/schemas/",
+        ));
+        let registry = doc.get_service_by_type(CREDENTIAL_REGISTRY_TYPE).unwrap();
+        assert_eq!(registry.id, "did:example:issuer#credential-registry");
+        assert_eq!(registry.service_endpoint, "https://example.com/schemas/");
+        assert_eq!(
+            doc.get_service_by_type("VerifiableCredentialService")
+                .unwrap()
+                .id,
+            "did:example:123456789abcdefghi#vcs"
+        );
+    }
 }
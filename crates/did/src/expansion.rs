@@ -0,0 +1,45 @@
+use json_syntax::Parse;
+use ssi::json_ld::{syntax::IntoJsonWithContext, ContextLoader, Expand, IriBuf};
+
+use crate::DidDocument;
+
+// JSON-LD expanded form of a document, with every term replaced by the IRI
+// its @context maps it to. Contexts come from the ones bundled with ssi,
+// nothing is fetched.
+pub async fn expand_document(document: &DidDocument) -> Result<serde_json::Value, String> {
+    let json = serde_json::to_string(document).map_err(|err| err.to_string())?;
+    let (value, _) = json_syntax::Value::parse_str(&json).map_err(|err| err.to_string())?;
+
+    let expanded = Expand::<IriBuf>::expand(&value, &ContextLoader::default())
+        .await
+        .map_err(|err| format!("Failed to expand: {}", err))?;
+    let expanded = json_syntax::Value::Array(
+        expanded
+            .into_iter()
+            .map(|object| object.into_json_with(&()))
+            .collect(),
+    );
+
+    serde_json::from_str(&expanded.to_string()).map_err(|err| err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixtures::sample_document;
+
+    use super::*;
+
+    #[async_std::test]
+    async fn test_expand_document_maps_terms_to_iris() {
+        let expanded = expand_document(&sample_document("did:example:alice"))
+            .await
+            .unwrap();
+
+        let node = &expanded[0];
+        assert_eq!(node["@id"], "did:example:alice");
+        assert_eq!(
+            node["https://w3id.org/security#verificationMethod"][0]["@id"],
+            "did:example:alice#key1"
+        );
+    }
+}
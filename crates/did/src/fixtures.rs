@@ -0,0 +1,98 @@
+//! Deterministic fixtures shared by tests, in this crate and others through
+//! the `testing` feature.
+use ed25519_dalek::SigningKey;
+use rand::{rngs::StdRng, SeedableRng};
+
+use crate::{
+    encode_public_key_to_multibase, DidDocument, Service, VCCreator, VerifiableCredential,
+    VerificationMethod,
+};
+
+/// Seed of the keypair used by `sample_document` and `sample_credential`.
+pub const FIXTURE_SEED: u64 = 42;
+
+/// Builds a signing key that is always the same for a given seed.
+pub fn deterministic_keypair(seed: u64) -> SigningKey {
+    let mut rng = StdRng::seed_from_u64(seed);
+    SigningKey::generate(&mut rng)
+}
+
+/// Builds a DID document with one verification method holding the
+/// `FIXTURE_SEED` public key, referenced for authentication and assertions,
+/// and a service.
+pub fn sample_document(did: &str) -> DidDocument {
+    let verifying_key = deterministic_keypair(FIXTURE_SEED).verifying_key();
+    let encoded_vk =
+        encode_public_key_to_multibase(&verifying_key).expect("Failed to encode verifying key");
+
+    let mut did_doc = DidDocument::new(did);
+
+    let ver_method_id = format!("{}#key1", did);
+    did_doc.add_verification_method(VerificationMethod {
+        id: ver_method_id.clone(),
+        vc_type: "Ed25519VerificationKey2020".to_string(),
+        controller: did.to_string(),
+        public_key_hex: None,
+        public_key_base58: Some(encoded_vk),
+        expires: None,
+        public_key_jwk: None,
+    });
+    did_doc.add_authentication(&ver_method_id);
+    did_doc.add_assertion_method(&ver_method_id);
+    did_doc.add_service(Service {
+        id: format!("{}#vcs", did),
+        type_: "VerifiableCredentialService".to_string(),
+        service_endpoint: "https://example.com/vc/".to_string(),
+    });
+
+    did_doc
+}
+
+/// Builds a creditworthiness credential signed with the `FIXTURE_SEED` keypair.
+pub fn sample_credential(issuer: &str, subject: &str, score: u32) -> VerifiableCredential {
+    let creator = VCCreator::with_signing_key(issuer, deterministic_keypair(FIXTURE_SEED))
+        .expect("Invalid issuer DID");
+
+    creator
+        .generate_vc(subject, score)
+        .expect("Failed to generate credential")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{decode_multibase_to_public_key, verify_vc};
+
+    use super::*;
+
+    #[test]
+    fn test_deterministic_keypair() {
+        assert_eq!(
+            deterministic_keypair(1).to_bytes(),
+            deterministic_keypair(1).to_bytes()
+        );
+        assert_ne!(
+            deterministic_keypair(1).to_bytes(),
+            deterministic_keypair(2).to_bytes()
+        );
+    }
+
+    #[test]
+    fn test_fixtures_are_verifiable() {
+        let verifying_key = deterministic_keypair(FIXTURE_SEED).verifying_key();
+
+        let doc = sample_document("did:example:issuer");
+        let encoded_vk = doc.verification_method[0]
+            .public_key_base58
+            .as_ref()
+            .unwrap();
+        assert_eq!(
+            decode_multibase_to_public_key(encoded_vk).unwrap(),
+            verifying_key
+        );
+
+        let vc = sample_credential("did:example:issuer", "did:example:alice", 750);
+        assert_eq!(vc.issuer, "did:example:issuer");
+        assert_eq!(vc.credential_subject.credit_score, 750);
+        assert!(verify_vc(&vc, &verifying_key).unwrap());
+    }
+}
@@ -1,6 +1,11 @@
+use crate::encode_public_key_to_multibase;
+use ed25519_dalek::SigningKey;
+use rand::rngs::OsRng;
 use rand::Rng;
 use serde::{Deserialize, Serialize};
+use std::error::Error;
 use std::fmt;
+use std::str::FromStr;
 
 pub struct Keypair {}
 
@@ -15,36 +20,62 @@ pub struct DID {
     pub method_specific_id: String,
 }
 
-impl DID {
-    /// Creates a new DID instance by parsing a DID string.
-    ///
-    /// # Arguments
-    /// * `did` - A string representing the DID (e.g., "did:example:123456789abcdefghi").
-    ///
-    /// # Returns
-    /// * `Result<DID, String>` - Ok with parsed DID or Err with error message.
-    pub fn new(did: &str) -> Result<Self, String> {
+/// A freshly generated `did:key` DID together with the key it embeds.
+pub struct GeneratedDid {
+    pub did: DID,
+    pub signing_key: SigningKey,
+}
+
+/// Why a string is not a valid DID.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DidParseError {
+    /// The string doesn't start with the "did" scheme.
+    MissingScheme(String),
+    /// The string has no method or no method-specific identifier part.
+    InvalidFormat(String),
+    /// The method name isn't lowercase alphanumeric, or is too long.
+    InvalidMethod(String),
+    /// The method-specific identifier is empty, e.g. "did:example:".
+    EmptyMethodSpecificId,
+}
+
+impl fmt::Display for DidParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DidParseError::MissingScheme(did) | DidParseError::InvalidFormat(did) => {
+                write!(f, "Invalid DID format: {}", did)
+            }
+            DidParseError::InvalidMethod(method) => write!(f, "Invalid method name: {}", method),
+            DidParseError::EmptyMethodSpecificId => {
+                write!(f, "Method-specific ID cannot be empty")
+            }
+        }
+    }
+}
+
+impl Error for DidParseError {}
+
+impl FromStr for DID {
+    type Err = DidParseError;
+
+    fn from_str(did: &str) -> Result<Self, Self::Err> {
         // Split the DID into components: did:method:method_specific_id
         let parts: Vec<&str> = did.split(':').collect();
-        if parts.len() < 3 || parts[0] != "did" {
-            return Err(format!("Invalid DID format: {}", did));
+        if parts[0] != "did" {
+            return Err(DidParseError::MissingScheme(did.to_string()));
+        }
+        if parts.len() < 3 {
+            return Err(DidParseError::InvalidFormat(did.to_string()));
         }
 
         let method = parts[1].to_string();
         let method_specific_id = parts[2..].join(":");
 
-        // Validate method name (alphanumeric, lowercase, 1-50 chars)
-        if !method
-            .chars()
-            .all(|c| c.is_alphanumeric() && c.is_lowercase())
-            || method.len() > 50
-        {
-            return Err(format!("Invalid method name: {}", method));
-        }
+        validate_method(&method)?;
 
         // Validate method-specific ID (basic check for non-empty)
         if method_specific_id.is_empty() {
-            return Err("Method-specific ID cannot be empty".to_string());
+            return Err(DidParseError::EmptyMethodSpecificId);
         }
 
         Ok(DID {
@@ -53,12 +84,56 @@ impl DID {
             method_specific_id,
         })
     }
+}
+
+impl TryFrom<&str> for DID {
+    type Error = DidParseError;
+
+    fn try_from(did: &str) -> Result<Self, Self::Error> {
+        did.parse()
+    }
+}
+
+impl TryFrom<String> for DID {
+    type Error = DidParseError;
+
+    fn try_from(did: String) -> Result<Self, Self::Error> {
+        did.parse()
+    }
+}
+
+impl DID {
+    /// Creates a new DID instance by parsing a DID string.
+    ///
+    /// # Arguments
+    /// * `did` - A string representing the DID (e.g., "did:example:123456789abcdefghi").
+    ///
+    /// # Returns
+    /// * `Result<DID, DidParseError>` - Ok with parsed DID or Err telling what is malformed.
+    pub fn new(did: &str) -> Result<Self, DidParseError> {
+        did.parse()
+    }
 
     pub fn generate() -> Self {
+        DID::generate_with_method("example").expect("Failed to generate new DID")
+    }
+
+    /// Generates a `did:key` DID for a fresh Ed25519 keypair, the
+    /// method-specific identifier being the multibase-encoded public key.
+    pub fn generate_key() -> Result<GeneratedDid, Box<dyn Error>> {
+        let signing_key = SigningKey::generate(&mut OsRng);
+        let multibase_key = encode_public_key_to_multibase(&signing_key.verifying_key())?;
+        let did = DID::new(&format!("did:key:{}", multibase_key))?;
+
+        Ok(GeneratedDid { did, signing_key })
+    }
+
+    /// Generates a random DID of the given method, e.g. "did:demo:...".
+    pub fn generate_with_method(method: &str) -> Result<Self, DidParseError> {
         let random = generate_random_string(18);
-        let did = format!("did:example:{}", random);
+        let did = format!("did:{}:{}", method, random);
 
-        DID::new(&did).expect("Failed to generate new DID")
+        DID::new(&did)
     }
 
     /// Returns the DID string.
@@ -83,6 +158,20 @@ impl fmt::Display for DID {
     }
 }
 
+/// Checks a DID method name, the rules `DID::new` applies to the method part.
+pub fn validate_method(method: &str) -> Result<(), DidParseError> {
+    // Validate method name (alphanumeric, lowercase, 1-50 chars)
+    if !method
+        .chars()
+        .all(|c| c.is_alphanumeric() && c.is_lowercase())
+        || method.len() > 50
+    {
+        return Err(DidParseError::InvalidMethod(method.to_string()));
+    }
+
+    Ok(())
+}
+
 fn generate_random_string(length: usize) -> String {
     let charset: Vec<char> = "abcdefghijklmnopqrstuvwxyz0123456789".chars().collect();
 
@@ -106,6 +195,16 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_generate_key_embeds_the_public_key() {
+        let generated = DID::generate_key().unwrap();
+        let did = DID::new(generated.did.id()).unwrap();
+        assert_eq!(did.method(), "key");
+
+        let public_key = crate::decode_multibase_to_public_key(did.method_specific_id()).unwrap();
+        assert_eq!(public_key, generated.signing_key.verifying_key());
+    }
+
     #[test]
     fn test_valid_did() {
         let did_str = "did:example:123456789abcdefghi";
@@ -123,8 +222,40 @@ mod tests {
         assert!(result.is_err());
         assert_eq!(
             result.unwrap_err(),
+            DidParseError::MissingScheme(did_str.to_string())
+        );
+        assert_eq!(
+            DID::new(did_str).unwrap_err().to_string(),
             format!("Invalid DID format: {}", did_str)
         );
+        assert_eq!(
+            DID::new("did:example").unwrap_err(),
+            DidParseError::InvalidFormat("did:example".to_string())
+        );
+    }
+
+    #[test]
+    fn test_did_from_str() {
+        let did: DID = "did:example:123".parse().unwrap();
+        assert_eq!(did, DID::new("did:example:123").unwrap());
+        assert_eq!(DID::try_from("did:example:123").unwrap(), did);
+        assert_eq!(DID::try_from("did:example:123".to_string()).unwrap(), did);
+        assert_eq!(
+            "did:example:".parse::<DID>().unwrap_err(),
+            DidParseError::EmptyMethodSpecificId
+        );
+    }
+
+    #[test]
+    fn test_generate_with_method() {
+        let did = DID::generate_with_method("demo").unwrap();
+        assert!(did.id().starts_with("did:demo:"));
+        assert_eq!(did.method(), "demo");
+
+        assert_eq!(
+            DID::generate_with_method("Demo").unwrap_err(),
+            DidParseError::InvalidMethod("Demo".to_string())
+        );
     }
 
     #[test]
@@ -132,7 +263,14 @@ mod tests {
         let did_str = "did:EXAMPLE:123456789abcdefghi";
         let result = DID::new(did_str);
         assert!(result.is_err());
-        assert_eq!(result.unwrap_err(), "Invalid method name: EXAMPLE");
+        assert_eq!(
+            result.unwrap_err(),
+            DidParseError::InvalidMethod("EXAMPLE".to_string())
+        );
+        assert_eq!(
+            DID::new(did_str).unwrap_err().to_string(),
+            "Invalid method name: EXAMPLE"
+        );
     }
 
     #[test]
@@ -140,6 +278,10 @@ mod tests {
         let did_str = "did:example:";
         let result = DID::new(did_str);
         assert!(result.is_err());
-        assert_eq!(result.unwrap_err(), "Method-specific ID cannot be empty");
+        assert_eq!(result.unwrap_err(), DidParseError::EmptyMethodSpecificId);
+        assert_eq!(
+            DID::new(did_str).unwrap_err().to_string(),
+            "Method-specific ID cannot be empty"
+        );
     }
 }
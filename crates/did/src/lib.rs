@@ -1,19 +1,35 @@
+#[cfg(feature = "ssi")]
 pub mod bbs_vp;
 pub mod crypto;
 pub mod document;
+#[cfg(feature = "ssi")]
+pub mod expansion;
+#[cfg(any(test, feature = "testing"))]
+pub mod fixtures;
 pub mod identifier;
+pub mod presentation;
 pub mod qr_code;
 pub mod request;
+pub mod resolver;
+pub mod status_list;
+#[cfg(feature = "ssi")]
 pub mod verifiable_presentation;
 pub mod verifiable_registry;
 pub mod verification_credential;
 
+#[cfg(feature = "ssi")]
 pub use bbs_vp::*;
 pub use crypto::*;
 pub use document::*;
+#[cfg(feature = "ssi")]
+pub use expansion::*;
 pub use identifier::*;
+pub use presentation::*;
 pub use qr_code::*;
 pub use request::*;
+pub use resolver::*;
+pub use status_list::*;
+#[cfg(feature = "ssi")]
 pub use verifiable_presentation::*;
 pub use verifiable_registry::*;
 pub use verification_credential::*;
@@ -0,0 +1,469 @@
+use chrono::{Duration, Utc};
+use ed25519_dalek::{Signature, SigningKey, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+
+use crate::{
+    proof_in_future, proof_input, sign_payload, verify_payload, verify_proof_signatures,
+    DidResolver, Proof, StatusListResolver, VerifiableCredential, DEFAULT_CLOCK_SKEW_SECONDS, DID,
+    ED25519_SUITE,
+};
+
+// Define the Verifiable Presentation structure based on W3C VC Data Model
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct VerifiablePresentation {
+    #[serde(rename = "@context")]
+    pub context: Vec<String>,
+    #[serde(rename = "type")]
+    pub presentation_type: Vec<String>,
+    pub holder: String,
+    #[serde(rename = "verifiableCredential")]
+    pub verifiable_credential: Vec<VerifiableCredential>,
+    pub proof: Proof,
+}
+
+// Result of checking a single proof
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum ProofOutcome {
+    Valid,
+    // The signature doesn't match, or the credential expired
+    InvalidProof,
+    // The signing key couldn't be resolved from the signer's DID document
+    UnresolvedKey,
+    // The credential's subject isn't the holder presenting it
+    HolderBindingFailed,
+    // The proof is dated further in the future than the clock skew allows
+    ProofInFuture,
+    // The credential's status list marks it as revoked
+    Revoked,
+    // The credential has a status, but its status list couldn't be checked
+    StatusUnavailable,
+}
+
+// Knobs for presentation verification
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct VerificationOptions {
+    // Credential types that are intentionally bearer, anyone holding them may
+    // present them so the subject isn't checked against the holder
+    pub bearer_types: Vec<String>,
+    // Accept keys whatever relationship they're listed under, for documents
+    // predating assertionMethod
+    pub ignore_proof_purpose: bool,
+    // Challenge the verifier sent, the holder's proof must carry it as nonce
+    pub challenge: Option<String>,
+    // How far in the future proofs may be dated, DEFAULT_CLOCK_SKEW_SECONDS
+    // when not set
+    pub clock_skew_seconds: Option<i64>,
+}
+
+impl VerificationOptions {
+    fn clock_skew(&self) -> Duration {
+        Duration::seconds(
+            self.clock_skew_seconds
+                .unwrap_or(DEFAULT_CLOCK_SKEW_SECONDS),
+        )
+    }
+
+    fn is_bearer(&self, vc: &VerifiableCredential) -> bool {
+        vc.credential_type
+            .iter()
+            .any(|credential_type| self.bearer_types.contains(credential_type))
+    }
+}
+
+// Outcome of one credential embedded in a presentation
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CredentialReport {
+    pub id: String,
+    pub issuer: String,
+    pub outcome: ProofOutcome,
+}
+
+// Outcome of verifying a presentation and all of its credentials
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PresentationVerificationReport {
+    pub holder: String,
+    pub holder_outcome: ProofOutcome,
+    pub credentials: Vec<CredentialReport>,
+    pub valid: bool,
+}
+
+impl VerifiablePresentation {
+    // Wrap credentials into a presentation signed by the holder
+    pub fn new(
+        holder_did: &str,
+        credentials: Vec<VerifiableCredential>,
+        signer: &SigningKey,
+    ) -> Result<Self, Box<dyn Error>> {
+        Self::with_challenge(holder_did, credentials, signer, None)
+    }
+
+    // Same as new, answering a verifier's challenge so the presentation
+    // can't be replayed to another request
+    pub fn with_challenge(
+        holder_did: &str,
+        credentials: Vec<VerifiableCredential>,
+        signer: &SigningKey,
+        challenge: Option<&str>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let holder = DID::new(holder_did)?;
+
+        let mut vp = VerifiablePresentation {
+            context: vec!["https://www.w3.org/2018/credentials/v1".to_string()],
+            presentation_type: vec!["VerifiablePresentation".to_string()],
+            holder: holder.id.clone(),
+            verifiable_credential: credentials,
+            proof: Proof {
+                proof_type: "Ed25519Signature2020".to_string(),
+                created: Utc::now().to_rfc3339(),
+                proof_purpose: "authentication".to_string(),
+                verification_method: format!("{}#key1", holder.id),
+                proof_value: None, // Placeholder, will be replaced
+                nonce: challenge.map(str::to_string),
+            },
+        };
+        vp.proof.proof_value = Some(sign_payload(&vp, signer)?);
+
+        Ok(vp)
+    }
+
+    // Verify the holder's proof and the issuer proof of every credential,
+    // resolving the keys from the signers' DID documents. Credentials with a
+    // credentialStatus are checked against their status list too.
+    pub fn verify_full<R: DidResolver + ?Sized>(
+        &self,
+        resolver: &R,
+    ) -> PresentationVerificationReport {
+        self.verify_full_with(resolver, &VerificationOptions::default())
+    }
+
+    // Same as verify_full, every non-bearer credential must also be about the holder
+    pub fn verify_full_with<R: DidResolver + ?Sized>(
+        &self,
+        resolver: &R,
+        options: &VerificationOptions,
+    ) -> PresentationVerificationReport {
+        self.verify_full_with_status(resolver, options, StatusListResolver::global())
+    }
+
+    // Same as verify_full_with, checking credentials against the given status
+    // lists
+    pub fn verify_full_with_status<R: DidResolver + ?Sized>(
+        &self,
+        resolver: &R,
+        options: &VerificationOptions,
+        status_lists: &StatusListResolver,
+    ) -> PresentationVerificationReport {
+        let mut vp_for_verification = self.clone();
+        let proof_value = vp_for_verification.proof.proof_value.take();
+        let answers_challenge = match &options.challenge {
+            Some(challenge) => self.proof.nonce.as_ref() == Some(challenge),
+            None => true,
+        };
+        let in_future =
+            |proof: &Proof| matches!(proof_in_future(proof, options.clock_skew()), Ok(true));
+        let holder_outcome = match (
+            proof_value,
+            resolve_key(resolver, &self.holder, &self.proof, options),
+        ) {
+            (_, None) => ProofOutcome::UnresolvedKey,
+            (_, Some(_)) if !answers_challenge => ProofOutcome::InvalidProof,
+            (_, Some(_)) if in_future(&self.proof) => ProofOutcome::ProofInFuture,
+            (Some(proof_value), Some(key)) => {
+                match verify_payload(&vp_for_verification, &proof_value, &key) {
+                    Ok(true) => ProofOutcome::Valid,
+                    _ => ProofOutcome::InvalidProof,
+                }
+            }
+            (None, Some(_)) => ProofOutcome::InvalidProof,
+        };
+
+        // The signatures of all credentials are checked at once
+        let mut outcomes = Vec::with_capacity(self.verifiable_credential.len());
+        let mut pending = Vec::new();
+        for (i, vc) in self.verifiable_credential.iter().enumerate() {
+            let outcome = match resolve_key(resolver, &vc.issuer, &vc.proof, options) {
+                None => ProofOutcome::UnresolvedKey,
+                Some(_) if in_future(&vc.proof) => ProofOutcome::ProofInFuture,
+                Some(key) => match proof_input(vc) {
+                    Ok(Some((payload, signature))) => {
+                        pending.push((i, payload, signature, key));
+                        ProofOutcome::Valid
+                    }
+                    _ => ProofOutcome::InvalidProof,
+                },
+            };
+            outcomes.push(outcome);
+        }
+        let items: Vec<(&[u8], Signature, VerifyingKey)> = pending
+            .iter()
+            .map(|(_, payload, signature, key)| (payload.as_slice(), *signature, *key))
+            .collect();
+        let all_ed25519 = self
+            .verifiable_credential
+            .iter()
+            .all(|vc| vc.proof.proof_type == ED25519_SUITE);
+        let signatures = verify_proof_signatures(&items, all_ed25519);
+        for ((i, _, _, _), valid) in pending.iter().zip(signatures) {
+            let vc = &self.verifiable_credential[*i];
+            outcomes[*i] = if !valid {
+                ProofOutcome::InvalidProof
+            } else if vc.credential_subject.id != self.holder && !options.is_bearer(vc) {
+                ProofOutcome::HolderBindingFailed
+            } else {
+                match &vc.credential_status {
+                    Some(status) => match status_lists.is_revoked(status, &vc.issuer, resolver) {
+                        Ok(false) => ProofOutcome::Valid,
+                        Ok(true) => ProofOutcome::Revoked,
+                        Err(_) => ProofOutcome::StatusUnavailable,
+                    },
+                    None => ProofOutcome::Valid,
+                }
+            };
+        }
+
+        let credentials: Vec<CredentialReport> = self
+            .verifiable_credential
+            .iter()
+            .zip(outcomes)
+            .map(|(vc, outcome)| CredentialReport {
+                id: vc.id.clone(),
+                issuer: vc.issuer.clone(),
+                outcome,
+            })
+            .collect();
+
+        let valid = holder_outcome == ProofOutcome::Valid
+            && credentials
+                .iter()
+                .all(|report| report.outcome == ProofOutcome::Valid);
+
+        PresentationVerificationReport {
+            holder: self.holder.clone(),
+            holder_outcome,
+            credentials,
+            valid,
+        }
+    }
+
+    // Serialize to JSON string
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(self)
+    }
+}
+
+// Resolve the key of a proof, which must be one of the signer's own
+// verification methods, listed for the proof's purpose
+fn resolve_key<R: DidResolver + ?Sized>(
+    resolver: &R,
+    signer_did: &str,
+    proof: &Proof,
+    options: &VerificationOptions,
+) -> Option<VerifyingKey> {
+    let (did, _) = proof.verification_method.split_once('#')?;
+    if did != signer_did {
+        return None;
+    }
+    let document = resolver.resolve(did)?;
+    if !options.ignore_proof_purpose
+        && !document.has_relationship(&proof.proof_purpose, &proof.verification_method)
+    {
+        return None;
+    }
+
+    document
+        .verification_method
+        .iter()
+        .find(|vm| vm.id == proof.verification_method && !vm.is_expired())?
+        .verifying_key()
+}
+
+impl fmt::Display for PresentationVerificationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Holder {}: {:?}", self.holder, self.holder_outcome)?;
+        for report in self.credentials.iter() {
+            writeln!(
+                f,
+                "Credential {} from {}: {:?}",
+                report.id, report.issuer, report.outcome
+            )?;
+        }
+        write!(
+            f,
+            "Presentation is {}",
+            if self.valid { "valid" } else { "invalid" }
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixtures::{
+        deterministic_keypair, sample_credential, sample_document, FIXTURE_SEED,
+    };
+    use crate::DidStorage;
+
+    use super::*;
+
+    fn resolver_with(dids: &[&str]) -> DidStorage {
+        let mut resolver = DidStorage::new();
+        for did in dids {
+            resolver
+                .store(did.to_string(), sample_document(did))
+                .unwrap();
+        }
+
+        resolver
+    }
+
+    #[test]
+    fn test_verify_full_flags_tampered_credential() {
+        let issuer = "did:example:issuer";
+        let holder = "did:example:alice";
+        let resolver = resolver_with(&[issuer, holder]);
+
+        let valid = sample_credential(issuer, holder, 750);
+        let mut tampered = sample_credential(issuer, holder, 600);
+        tampered.credential_subject.credit_score = 850;
+
+        let signer = deterministic_keypair(FIXTURE_SEED);
+        let vp =
+            VerifiablePresentation::new(holder, vec![valid.clone(), tampered.clone()], &signer)
+                .unwrap();
+
+        let report = vp.verify_full(&resolver);
+        assert_eq!(report.holder_outcome, ProofOutcome::Valid);
+        assert_eq!(report.credentials.len(), 2);
+        assert_eq!(report.credentials[0].id, valid.id);
+        assert_eq!(report.credentials[0].outcome, ProofOutcome::Valid);
+        assert_eq!(report.credentials[1].id, tampered.id);
+        assert_eq!(report.credentials[1].outcome, ProofOutcome::InvalidProof);
+        assert!(!report.valid);
+    }
+
+    #[test]
+    fn test_verify_full_valid_presentation() {
+        let issuer = "did:example:issuer";
+        let holder = "did:example:alice";
+        let resolver = resolver_with(&[issuer, holder]);
+
+        let signer = deterministic_keypair(FIXTURE_SEED);
+        let vp = VerifiablePresentation::new(
+            holder,
+            vec![sample_credential(issuer, holder, 750)],
+            &signer,
+        )
+        .unwrap();
+
+        assert!(vp.verify_full(&resolver).valid);
+    }
+
+    #[test]
+    fn test_verify_full_unresolved_holder() {
+        let issuer = "did:example:issuer";
+        let holder = "did:example:alice";
+        let resolver = resolver_with(&[issuer]);
+
+        let signer = deterministic_keypair(FIXTURE_SEED);
+        let vp = VerifiablePresentation::new(
+            holder,
+            vec![sample_credential(issuer, holder, 750)],
+            &signer,
+        )
+        .unwrap();
+
+        let report = vp.verify_full(&resolver);
+        assert_eq!(report.holder_outcome, ProofOutcome::UnresolvedKey);
+        assert_eq!(report.credentials[0].outcome, ProofOutcome::Valid);
+        assert!(!report.valid);
+    }
+
+    #[test]
+    fn test_verify_full_flags_someone_elses_credential() {
+        let issuer = "did:example:issuer";
+        let holder = "did:example:alice";
+        let resolver = resolver_with(&[issuer, holder]);
+
+        let own = sample_credential(issuer, holder, 750);
+        let stolen = sample_credential(issuer, "did:example:bob", 800);
+
+        let signer = deterministic_keypair(FIXTURE_SEED);
+        let vp = VerifiablePresentation::new(holder, vec![own, stolen.clone()], &signer).unwrap();
+
+        let report = vp.verify_full(&resolver);
+        assert_eq!(report.holder_outcome, ProofOutcome::Valid);
+        assert_eq!(report.credentials[0].outcome, ProofOutcome::Valid);
+        assert_eq!(
+            report.credentials[1].outcome,
+            ProofOutcome::HolderBindingFailed
+        );
+        assert!(!report.valid);
+
+        // The same credential is accepted when its type is configured as bearer
+        let options = VerificationOptions {
+            bearer_types: vec![stolen.credential_type[1].clone()],
+            ..Default::default()
+        };
+        let report = vp.verify_full_with(&resolver, &options);
+        assert_eq!(report.credentials[1].outcome, ProofOutcome::Valid);
+        assert!(report.valid);
+    }
+
+    #[test]
+    fn test_verify_full_checks_proof_purpose() {
+        let issuer = "did:example:issuer";
+        let holder = "did:example:alice";
+        let mut resolver = resolver_with(&[holder]);
+        // The issuer's key is only listed for authentication
+        let mut issuer_document = sample_document(issuer);
+        issuer_document.assertion_method.clear();
+        resolver.store(issuer.to_string(), issuer_document).unwrap();
+
+        let signer = deterministic_keypair(FIXTURE_SEED);
+        let vp = VerifiablePresentation::new(
+            holder,
+            vec![sample_credential(issuer, holder, 750)],
+            &signer,
+        )
+        .unwrap();
+
+        let report = vp.verify_full(&resolver);
+        assert_eq!(report.holder_outcome, ProofOutcome::Valid);
+        assert_eq!(report.credentials[0].outcome, ProofOutcome::UnresolvedKey);
+
+        let options = VerificationOptions {
+            ignore_proof_purpose: true,
+            ..Default::default()
+        };
+        assert!(vp.verify_full_with(&resolver, &options).valid);
+    }
+
+    #[test]
+    fn test_verify_full_checks_challenge() {
+        let issuer = "did:example:issuer";
+        let holder = "did:example:alice";
+        let resolver = resolver_with(&[issuer, holder]);
+
+        let signer = deterministic_keypair(FIXTURE_SEED);
+        let vp = VerifiablePresentation::with_challenge(
+            holder,
+            vec![sample_credential(issuer, holder, 750)],
+            &signer,
+            Some("challenge-1"),
+        )
+        .unwrap();
+
+        let options = |challenge: &str| VerificationOptions {
+            challenge: Some(challenge.to_string()),
+            ..Default::default()
+        };
+        assert!(
+            vp.verify_full_with(&resolver, &options("challenge-1"))
+                .valid
+        );
+        let report = vp.verify_full_with(&resolver, &options("challenge-2"));
+        assert_eq!(report.holder_outcome, ProofOutcome::InvalidProof);
+        assert!(!report.valid);
+    }
+}
@@ -58,6 +58,46 @@ pub fn print_qr_code(data: &str) -> Result<String, String> {
     Ok(qr_string)
 }
 
+/// Prints a QR code like `print_qr_code`, without the quiet zone around it,
+/// for terminals too narrow for the full code.
+///
+/// # Arguments
+/// * `data` - The string to encode in the QR code.
+///
+/// # Returns
+/// * `Result<String, String>` - The ASCII representation of the QR code if successful, or an error message.
+pub fn print_compact_qr_code(data: &str) -> Result<String, String> {
+    if data.is_empty() {
+        return Err("Data is empty".into());
+    }
+    let code =
+        QrCode::new(data.as_bytes()).map_err(|e| format!("Failed to create QR code: {}", e))?;
+
+    let qr_string = code
+        .render::<unicode::Dense1x2>()
+        .dark_color(unicode::Dense1x2::Light)
+        .light_color(unicode::Dense1x2::Dark)
+        .quiet_zone(false)
+        .build();
+
+    Ok(qr_string)
+}
+
+/// Number of modules on a side of the QR code of the input string, which is
+/// also the number of terminal columns of its compact rendering.
+///
+/// # Arguments
+/// * `data` - The string to encode in the QR code.
+///
+/// # Returns
+/// * `Result<usize, String>` - The module count if successful, or an error message.
+pub fn qr_code_modules(data: &str) -> Result<usize, String> {
+    let code =
+        QrCode::new(data.as_bytes()).map_err(|e| format!("Failed to create QR code: {}", e))?;
+
+    Ok(code.width())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -132,6 +172,19 @@ mod tests {
         assert!(true);
     }
 
+    #[test]
+    fn test_compact_qr_code_is_as_wide_as_the_modules() {
+        let test_data = "https://example.com";
+        let modules = qr_code_modules(test_data).unwrap();
+
+        let compact = print_compact_qr_code(test_data).unwrap();
+        let line = compact.lines().next().unwrap();
+        assert_eq!(line.chars().count(), modules);
+
+        let full = print_qr_code(test_data).unwrap();
+        assert!(full.lines().next().unwrap().chars().count() > modules);
+    }
+
     #[test]
     fn test_print_qr_code_empty_input() {
         let test_data = "";
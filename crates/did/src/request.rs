@@ -1,13 +1,14 @@
 use base58::{FromBase58, ToBase58};
-use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use std::error::Error;
+use std::fmt;
 
-use crate::{encode_public_key_to_multibase, generate_document, DidDocument};
+use crate::{encode_public_key_to_multibase, generate_document, ContextPolicy, DidDocument};
 
 // Create request structure
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct CreateRequest {
     #[serde(rename = "type")]
     pub request_type: String,
@@ -25,9 +26,28 @@ pub fn create_signed_request(
     let encoded_vk = encode_public_key_to_multibase(&verifying_key)?;
     let document = generate_document(did, Some(encoded_vk)).unwrap();
 
+    sign_request("create", document, signer)
+}
+
+// Function to create and sign an update request, the signer must hold one of
+// the authentication keys of the currently registered document
+pub fn create_signed_update(
+    document: &DidDocument,
+    signer: &SigningKey,
+) -> Result<CreateRequest, Box<dyn Error>> {
+    sign_request("update", document.clone(), signer)
+}
+
+fn sign_request(
+    request_type: &str,
+    mut document: DidDocument,
+    signer: &SigningKey,
+) -> Result<CreateRequest, Box<dyn Error>> {
+    document.canonical_sort();
+
     let payload = json!({
-        "type": "create",
-        "did": did,
+        "type": request_type,
+        "did": document.id,
         "document": document,
     });
 
@@ -35,8 +55,8 @@ pub fn create_signed_request(
     let signature = signer.sign(&payload_bytes);
 
     Ok(CreateRequest {
-        request_type: "create".to_string(),
-        did: did.to_string(),
+        request_type: request_type.to_string(),
+        did: document.id.clone(),
         document,
         signature: signature.to_bytes().to_base58(),
     })
@@ -45,18 +65,73 @@ pub fn create_signed_request(
 // Function to verify the signature in a create request
 pub fn verify_request(request: &CreateRequest, key: &VerifyingKey) -> Result<bool, String> {
     // Reconstruct payload for verification
+    let mut document = request.document.clone();
+    document.canonical_sort();
     let payload = json!({
         "type": request.request_type,
         "did": request.did,
-        "document": request.document,
+        "document": document,
     });
     let payload_bytes = serde_json::to_string(&payload).unwrap().into_bytes();
 
     // Decode and verify signature
-    let signature_bytes = request.signature.from_base58().unwrap();
-    let signature: Signature = Signature::try_from(&signature_bytes[..64]).unwrap();
+    let signature_bytes = request
+        .signature
+        .from_base58()
+        .map_err(|_| "Signature is not base58 encoded".to_string())?;
+    let signature = Signature::from_slice(&signature_bytes).map_err(|err| err.to_string())?;
+
+    Ok(key.verify_strict(&payload_bytes, &signature).is_ok())
+}
+
+// Why a request was rejected, a bad signature or a malformed document
+#[derive(Debug, Clone, PartialEq)]
+pub enum RequestError {
+    Signature(String),
+    Invalid(String),
+}
+
+impl fmt::Display for RequestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RequestError::Signature(err) => write!(f, "Invalid signature: {}", err),
+            RequestError::Invalid(err) => write!(f, "Invalid request: {}", err),
+        }
+    }
+}
+
+impl Error for RequestError {}
+
+// Verify the signature of a request and check that it carries a valid
+// document for the DID it claims
+pub fn validate_request(request: &CreateRequest, key: &VerifyingKey) -> Result<(), RequestError> {
+    validate_request_with(request, key, &ContextPolicy::default())
+}
 
-    Ok(key.verify(&payload_bytes, &signature).is_ok())
+// Same as validate_request, with the given trusted contexts
+pub fn validate_request_with(
+    request: &CreateRequest,
+    key: &VerifyingKey,
+    policy: &ContextPolicy,
+) -> Result<(), RequestError> {
+    if request.did != request.document.id {
+        return Err(RequestError::Invalid(format!(
+            "DID {} doesn't match document ID {}",
+            request.did, request.document.id
+        )));
+    }
+    request.document.validate_with(policy).map_err(|errors| {
+        let errors: Vec<String> = errors.iter().map(ToString::to_string).collect();
+        RequestError::Invalid(errors.join("; "))
+    })?;
+
+    match verify_request(request, key) {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(RequestError::Signature(
+            "Signature doesn't match the request".to_string(),
+        )),
+        Err(err) => Err(RequestError::Signature(err)),
+    }
 }
 
 #[cfg(test)]
@@ -87,4 +162,52 @@ mod tests {
             .expect("Failed to verify tampered request");
         assert!(!is_valid_tampered, "Tampered signature should not verify");
     }
+
+    #[test]
+    fn test_verify_request_rejects_malformed_signature() {
+        use rand::rngs::OsRng;
+
+        let signing_key = SigningKey::generate(&mut OsRng);
+        let did = "did:example:123456789abcdefghi";
+        let mut request = create_signed_request(did, &signing_key).unwrap();
+
+        request.signature = "abc".to_string();
+        assert!(verify_request(&request, &signing_key.verifying_key()).is_err());
+    }
+
+    #[test]
+    fn test_validate_request() {
+        use rand::rngs::OsRng;
+
+        let signing_key = SigningKey::generate(&mut OsRng);
+        let verifying_key = signing_key.verifying_key();
+        let did = "did:example:123456789abcdefghi";
+        let request = create_signed_request(did, &signing_key).unwrap();
+        assert_eq!(validate_request(&request, &verifying_key), Ok(()));
+
+        let other_key = SigningKey::generate(&mut OsRng).verifying_key();
+        assert!(matches!(
+            validate_request(&request, &other_key),
+            Err(RequestError::Signature(_))
+        ));
+
+        let mut mismatch = request.clone();
+        mismatch.did = "did:example:someone-else".to_string();
+        assert!(matches!(
+            validate_request(&mismatch, &verifying_key),
+            Err(RequestError::Invalid(_))
+        ));
+
+        // Signed, but the authentication method isn't in the document
+        let mut document = request.document.clone();
+        document.authentication = vec![format!("{}#unknown", did)];
+        let invalid = sign_request("create", document, &signing_key).unwrap();
+        assert_eq!(
+            validate_request(&invalid, &verifying_key),
+            Err(RequestError::Invalid(format!(
+                "Unknown authentication method: {}#unknown",
+                did
+            )))
+        );
+    }
 }
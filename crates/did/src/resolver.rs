@@ -0,0 +1,581 @@
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::io::Read;
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{DidDocument, DidStorage, DID};
+
+// Media type of the documents returned by resolution
+pub static DID_CONTENT_TYPE: &str = "application/did+ld+json";
+
+// How long a resolver waits for a remote registry
+const HTTP_RESOLUTION_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Largest resolution result read from a remote registry
+const MAX_RESOLUTION_BYTES: u64 = 1024 * 1024;
+
+// Metadata about the resolution itself, `error` is set when it failed
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ResolutionMetadata {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+// Metadata about the resolved document, as tracked by the registry
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct DocumentMetadata {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub updated: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub canonical_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub equivalent_id: Vec<String>,
+    // Every change of the document, oldest first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub operations: Vec<OperationRecord>,
+}
+
+// Kind of change made to a stored document
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Operation {
+    Create,
+    Update,
+}
+
+// One change of a stored document, who made it and when
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct OperationRecord {
+    pub operation: Operation,
+    // Client or verification method behind the change, when known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub actor: Option<String>,
+    // RFC 3339
+    pub timestamp: String,
+}
+
+// Other identifiers of a DID subject, for methods with several forms of the
+// same DID such as a long and a short form
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EquivalentIds {
+    // The form clients should use from now on
+    pub canonical_id: Option<String>,
+    pub equivalent_id: Vec<String>,
+}
+
+impl EquivalentIds {
+    // Every identifier must be a valid DID
+    pub fn validate(&self) -> Result<(), String> {
+        self.canonical_id
+            .iter()
+            .chain(self.equivalent_id.iter())
+            .try_for_each(|id| DID::new(id).map(|_| ()).map_err(|err| err.to_string()))
+    }
+}
+
+// Full resolution result, shaped like the Universal Resolver output
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ResolutionResult {
+    pub did_document: Option<DidDocument>,
+    pub did_resolution_metadata: ResolutionMetadata,
+    pub did_document_metadata: DocumentMetadata,
+}
+
+impl ResolutionResult {
+    pub fn found(document: DidDocument, metadata: DocumentMetadata) -> Self {
+        ResolutionResult {
+            did_document: Some(document),
+            did_resolution_metadata: ResolutionMetadata {
+                content_type: Some(DID_CONTENT_TYPE.to_string()),
+                error: None,
+            },
+            did_document_metadata: metadata,
+        }
+    }
+
+    // Failed resolution, e.g. with the "notFound" or "invalidDid" error code
+    pub fn error(code: &str) -> Self {
+        ResolutionResult {
+            did_document: None,
+            did_resolution_metadata: ResolutionMetadata {
+                content_type: None,
+                error: Some(code.to_string()),
+            },
+            did_document_metadata: DocumentMetadata::default(),
+        }
+    }
+
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(self)
+    }
+}
+
+// A resolved document along with the documents its alsoKnownAs links lead to
+#[derive(Clone, Debug)]
+pub struct LinkedResolution {
+    pub document: DidDocument,
+    // Documents of the linked DIDs, nearest first, each at most once
+    pub related: Vec<DidDocument>,
+}
+
+// Resolves DID documents of one DID method
+pub trait DidResolver: Send + Sync {
+    fn resolve(&self, did: &str) -> Option<DidDocument>;
+
+    // Resolve a DID and follow the alsoKnownAs links of the documents found,
+    // up to `depth` links away. Links that aren't resolvable DIDs are skipped
+    // and every DID is resolved once, so documents linking to each other
+    // don't loop.
+    fn resolve_with_aka(&self, did: &str, depth: usize) -> Option<LinkedResolution> {
+        let document = self.resolve(did)?;
+        let mut seen = HashSet::from([document.id.clone()]);
+        let mut queue: VecDeque<(String, usize)> = document
+            .also_known_as
+            .iter()
+            .map(|aka| (aka.clone(), 1))
+            .collect();
+        let mut related = Vec::new();
+        while let Some((aka, distance)) = queue.pop_front() {
+            if distance > depth || !seen.insert(aka.clone()) {
+                continue;
+            }
+            let Some(linked) = self.resolve(&aka) else {
+                continue;
+            };
+            queue.extend(
+                linked
+                    .also_known_as
+                    .iter()
+                    .map(|next| (next.clone(), distance + 1)),
+            );
+            related.push(linked);
+        }
+
+        Some(LinkedResolution { document, related })
+    }
+
+    // Resolve along with the resolution and document metadata
+    fn resolve_with_metadata(&self, did: &str) -> ResolutionResult {
+        match self.resolve(did) {
+            Some(document) => ResolutionResult::found(document, DocumentMetadata::default()),
+            None => ResolutionResult::error("notFound"),
+        }
+    }
+}
+
+// Storage shared between the registry and the resolvers reading from it
+pub type SharedStorage = Arc<RwLock<DidStorage>>;
+
+impl DidResolver for DidStorage {
+    fn resolve(&self, did: &str) -> Option<DidDocument> {
+        self.get(did).cloned()
+    }
+
+    fn resolve_with_metadata(&self, did: &str) -> ResolutionResult {
+        match (self.get(did), self.document_metadata(did)) {
+            (Some(document), Some(metadata)) => ResolutionResult::found(document.clone(), metadata),
+            _ => ResolutionResult::error("notFound"),
+        }
+    }
+}
+
+impl DidResolver for SharedStorage {
+    fn resolve(&self, did: &str) -> Option<DidDocument> {
+        self.read().ok()?.resolve(did)
+    }
+
+    fn resolve_with_metadata(&self, did: &str) -> ResolutionResult {
+        match self.read() {
+            Ok(storage) => storage.resolve_with_metadata(did),
+            Err(_) => ResolutionResult::error("internalError"),
+        }
+    }
+}
+
+// Dispatches resolution to the resolver registered for the DID's method
+#[derive(Default)]
+pub struct MethodRouter {
+    resolvers: BTreeMap<String, Box<dyn DidResolver>>,
+}
+
+impl MethodRouter {
+    pub fn new() -> Self {
+        MethodRouter {
+            resolvers: BTreeMap::new(),
+        }
+    }
+
+    // Register the resolver of a DID method, replacing any previous one
+    pub fn register(&mut self, method: &str, resolver: Box<dyn DidResolver>) {
+        self.resolvers.insert(method.to_string(), resolver);
+    }
+
+    // Names of the supported DID methods, sorted
+    pub fn methods(&self) -> Vec<&str> {
+        self.resolvers.keys().map(String::as_str).collect()
+    }
+
+    pub fn supports(&self, method: &str) -> bool {
+        self.resolvers.contains_key(method)
+    }
+}
+
+impl DidResolver for MethodRouter {
+    fn resolve(&self, did: &str) -> Option<DidDocument> {
+        let did = DID::new(did).ok()?;
+
+        self.resolvers.get(&did.method)?.resolve(&did.id)
+    }
+
+    fn resolve_with_metadata(&self, did: &str) -> ResolutionResult {
+        let Ok(did) = DID::new(did) else {
+            return ResolutionResult::error("invalidDid");
+        };
+
+        match self.resolvers.get(&did.method) {
+            Some(resolver) => resolver.resolve_with_metadata(&did.id),
+            None => ResolutionResult::error("methodNotSupported"),
+        }
+    }
+}
+
+// Resolves DIDs with a registry speaking the Universal Resolver HTTP API,
+// GET <base_url>/1.0/identifiers/<did>. Every resolution is a request, wrap
+// it in a CachingResolver to reuse the documents.
+pub struct HttpResolver {
+    base_url: String,
+    // Created on first use, as the blocking client can't be built in an
+    // async context
+    client: OnceLock<reqwest::blocking::Client>,
+}
+
+impl HttpResolver {
+    pub fn new(base_url: &str) -> Self {
+        HttpResolver {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            client: OnceLock::new(),
+        }
+    }
+
+    fn fetch(&self, did: &str) -> Result<DidDocument, Box<dyn std::error::Error>> {
+        let client = self.client.get_or_init(|| {
+            reqwest::blocking::Client::builder()
+                .timeout(HTTP_RESOLUTION_TIMEOUT)
+                .build()
+                .expect("Failed to build the HTTP client")
+        });
+        let response = client
+            .get(format!("{}/1.0/identifiers/{}", self.base_url, did))
+            .send()?
+            .error_for_status()?;
+        let mut body = Vec::new();
+        response.take(MAX_RESOLUTION_BYTES).read_to_end(&mut body)?;
+
+        // Either the full resolution result or the bare document
+        let document = match serde_json::from_slice::<ResolutionResult>(&body) {
+            Ok(result) => result.did_document.ok_or("No document in the result")?,
+            Err(_) => serde_json::from_slice::<DidDocument>(&body)?,
+        };
+        if document.id != did {
+            return Err(format!("Resolved {} instead of {}", document.id, did).into());
+        }
+
+        Ok(document)
+    }
+}
+
+impl DidResolver for HttpResolver {
+    fn resolve(&self, did: &str) -> Option<DidDocument> {
+        self.fetch(did).ok()
+    }
+}
+
+// Keeps the documents another resolver found for `ttl`, across calls, so
+// verifying many credentials of the same issuer resolves it once. Documents
+// updated at the source are only seen once their entry expires, and DIDs
+// that failed to resolve are asked again every time.
+pub struct CachingResolver<R> {
+    inner: R,
+    ttl: Duration,
+    cache: Mutex<HashMap<String, (Instant, DidDocument)>>,
+}
+
+impl<R: DidResolver> CachingResolver<R> {
+    pub fn new(inner: R, ttl: Duration) -> Self {
+        CachingResolver {
+            inner,
+            ttl,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<R: DidResolver> DidResolver for CachingResolver<R> {
+    fn resolve(&self, did: &str) -> Option<DidDocument> {
+        if let Some((resolved_at, document)) = self.cache.lock().ok()?.get(did) {
+            if resolved_at.elapsed() < self.ttl {
+                return Some(document.clone());
+            }
+        }
+
+        let document = self.inner.resolve(did)?;
+        self.cache
+            .lock()
+            .ok()?
+            .insert(did.to_string(), (Instant::now(), document.clone()));
+
+        Some(document)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+
+    use crate::fixtures::sample_document;
+
+    use super::*;
+
+    // Answer every request with the resolution result of a sample document of
+    // the requested DID, counting them
+    fn serve_registry() -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    line.clear();
+                }
+                counter.fetch_add(1, Ordering::SeqCst);
+                let did = request_line
+                    .split_whitespace()
+                    .nth(1)
+                    .and_then(|path| path.strip_prefix("/1.0/identifiers/"))
+                    .unwrap();
+                let body = ResolutionResult::found(sample_document(did), Default::default())
+                    .to_json()
+                    .unwrap();
+                write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                )
+                .unwrap();
+            }
+        });
+
+        (base_url, requests)
+    }
+
+    #[test]
+    fn test_http_resolver_is_cached_across_calls() {
+        let (base_url, requests) = serve_registry();
+        let resolver = CachingResolver::new(HttpResolver::new(&base_url), Duration::from_secs(60));
+
+        for _ in 0..3 {
+            let document = resolver.resolve("did:example:issuer").unwrap();
+            assert_eq!(
+                document.to_json().unwrap(),
+                sample_document("did:example:issuer").to_json().unwrap()
+            );
+        }
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        // Expired entries are resolved again
+        let resolver = CachingResolver::new(HttpResolver::new(&base_url), Duration::ZERO);
+        resolver.resolve("did:example:issuer").unwrap();
+        resolver.resolve("did:example:issuer").unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_router_dispatches_by_method() {
+        let mut example = DidStorage::new();
+        example
+            .store(
+                "did:example:123".to_string(),
+                sample_document("did:example:123"),
+            )
+            .unwrap();
+        let mut web = DidStorage::new();
+        web.store(
+            "did:web:example.com".to_string(),
+            sample_document("did:web:example.com"),
+        )
+        .unwrap();
+
+        let mut router = MethodRouter::new();
+        router.register("web", Box::new(web));
+        router.register("example", Box::new(example));
+
+        assert_eq!(router.methods(), vec!["example", "web"]);
+        assert!(router.resolve("did:example:123").is_some());
+        assert!(router.resolve("did:web:example.com").is_some());
+        assert!(router.resolve("did:key:z6Mk").is_none());
+        assert!(router.resolve("not-a-did").is_none());
+    }
+
+    #[test]
+    fn test_resolve_with_aka_stops_at_cycles() {
+        let mut storage = DidStorage::new();
+        for (did, aka) in [
+            ("did:example:alice", "did:example:alias"),
+            ("did:example:alias", "did:example:alice"),
+        ] {
+            let mut document = sample_document(did);
+            document.also_known_as = vec![aka.to_string(), "https://alice.example".to_string()];
+            storage.store(did.to_string(), document).unwrap();
+        }
+
+        let linked = storage.resolve_with_aka("did:example:alice", 10).unwrap();
+        assert_eq!(linked.document.id, "did:example:alice");
+        let related: Vec<&str> = linked.related.iter().map(|doc| doc.id.as_str()).collect();
+        assert_eq!(related, ["did:example:alias"]);
+
+        let linked = storage.resolve_with_aka("did:example:alice", 0).unwrap();
+        assert!(linked.related.is_empty());
+        assert!(storage.resolve_with_aka("did:example:nobody", 1).is_none());
+    }
+
+    #[test]
+    fn test_shared_storage_sees_later_writes() {
+        let storage: SharedStorage = Default::default();
+        let mut router = MethodRouter::new();
+        router.register("example", Box::new(storage.clone()));
+
+        assert!(router.resolve("did:example:123").is_none());
+        storage
+            .write()
+            .unwrap()
+            .store(
+                "did:example:123".to_string(),
+                sample_document("did:example:123"),
+            )
+            .unwrap();
+        assert!(router.resolve("did:example:123").is_some());
+    }
+
+    #[test]
+    fn test_canonical_id_in_resolution_metadata() {
+        let mut storage = DidStorage::new();
+        let ids = EquivalentIds {
+            canonical_id: Some("did:example:short".to_string()),
+            equivalent_id: vec!["did:example:short".to_string()],
+        };
+        storage
+            .store_with_ids(
+                "did:example:long".to_string(),
+                sample_document("did:example:long"),
+                ids.clone(),
+            )
+            .unwrap();
+
+        let envelope: serde_json::Value = serde_json::from_str(
+            &storage
+                .resolve_with_metadata("did:example:long")
+                .to_json()
+                .unwrap(),
+        )
+        .unwrap();
+        let metadata = &envelope["didDocumentMetadata"];
+        assert_eq!(metadata["canonicalId"], "did:example:short");
+        assert_eq!(metadata["equivalentId"][0], "did:example:short");
+
+        // A plain update keeps them, documents without any don't show them
+        storage
+            .update("did:example:long", sample_document("did:example:long"))
+            .unwrap();
+        let metadata = storage.document_metadata("did:example:long").unwrap();
+        assert_eq!(metadata.canonical_id, ids.canonical_id);
+        storage
+            .store(
+                "did:example:123".to_string(),
+                sample_document("did:example:123"),
+            )
+            .unwrap();
+        let envelope = storage
+            .resolve_with_metadata("did:example:123")
+            .to_json()
+            .unwrap();
+        assert!(!envelope.contains("canonicalId"));
+        assert!(!envelope.contains("equivalentId"));
+
+        let invalid = EquivalentIds {
+            canonical_id: Some("not-a-did".to_string()),
+            ..Default::default()
+        };
+        assert!(storage
+            .update_with_ids(
+                "did:example:long",
+                sample_document("did:example:long"),
+                invalid
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn test_resolution_result_envelope() {
+        let storage: SharedStorage = Default::default();
+        storage
+            .write()
+            .unwrap()
+            .store(
+                "did:example:123".to_string(),
+                sample_document("did:example:123"),
+            )
+            .unwrap();
+        let mut router = MethodRouter::new();
+        router.register("example", Box::new(storage));
+
+        let result = router.resolve_with_metadata("did:example:123");
+        assert!(result.did_document.is_some());
+        assert_eq!(
+            result.did_resolution_metadata.content_type.as_deref(),
+            Some(DID_CONTENT_TYPE)
+        );
+        assert!(result.did_document_metadata.created.is_some());
+        assert!(result.did_document_metadata.updated.is_some());
+        assert_eq!(
+            result.did_document_metadata.version_id.as_deref(),
+            Some("1")
+        );
+
+        let result = router.resolve_with_metadata("did:example:unknown");
+        assert!(result.did_document.is_none());
+        assert_eq!(
+            result.did_resolution_metadata.error.as_deref(),
+            Some("notFound")
+        );
+
+        assert_eq!(
+            router
+                .resolve_with_metadata("did:web:example.com")
+                .did_resolution_metadata
+                .error
+                .as_deref(),
+            Some("methodNotSupported")
+        );
+    }
+}
@@ -0,0 +1,489 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::{Read, Write};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::Utc;
+use ed25519_dalek::SigningKey;
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    generate_nonce, issuer_key, sign_payload, verify_payload, DidResolver, Proof, VCError,
+};
+
+// How long a fetched status list is trusted before it's fetched again
+const STATUS_LIST_TTL: Duration = Duration::from_secs(300);
+
+// Smallest status list, so that a list doesn't tell how many credentials
+// an issuer has
+pub const MIN_STATUS_LIST_LENGTH: usize = 131_072;
+
+// How long fetching a status list may take
+const STATUS_LIST_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Largest status list credential read from its URL
+const MAX_LIST_RESPONSE_BYTES: u64 = 1024 * 1024;
+
+// Largest decompressed bitstring, room for 16 million entries. A small
+// response can still decompress to far more, so it's capped too.
+const MAX_LIST_BYTES: u64 = 2 * 1024 * 1024;
+
+// Points a credential at its entry of a StatusList2021 credential
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct CredentialStatus {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub status_type: String,
+    #[serde(rename = "statusPurpose")]
+    pub status_purpose: String,
+    #[serde(rename = "statusListIndex")]
+    pub status_list_index: String,
+    #[serde(rename = "statusListCredential")]
+    pub status_list_credential: String,
+}
+
+impl CredentialStatus {
+    // Revocation entry at an index of the status list hosted at a URL
+    pub fn revocation(status_list_credential: &str, index: usize) -> Self {
+        CredentialStatus {
+            id: format!("{}#{}", status_list_credential, index),
+            status_type: "StatusList2021Entry".to_string(),
+            status_purpose: "revocation".to_string(),
+            status_list_index: index.to_string(),
+            status_list_credential: status_list_credential.to_string(),
+        }
+    }
+}
+
+// Credential published by an issuer with one bit per issued credential
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct StatusListCredential {
+    #[serde(rename = "@context")]
+    pub context: Vec<String>,
+    pub id: String,
+    #[serde(rename = "type")]
+    pub credential_type: Vec<String>,
+    pub issuer: String,
+    #[serde(rename = "issuanceDate")]
+    pub issuance_date: String,
+    #[serde(rename = "credentialSubject")]
+    pub credential_subject: StatusListSubject,
+    pub proof: Proof,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct StatusListSubject {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub subject_type: String,
+    #[serde(rename = "statusPurpose")]
+    pub status_purpose: String,
+    // GZIP compressed bitstring, base64url encoded without padding
+    #[serde(rename = "encodedList")]
+    pub encoded_list: String,
+}
+
+impl StatusListCredential {
+    // Issue a revocation list hosted at `id`, with the bits of the revoked
+    // indices set, signed with the issuer's assertion key `method_id`
+    pub fn issue(
+        id: &str,
+        method_id: &str,
+        revoked: &[usize],
+        signer: &SigningKey,
+    ) -> Result<Self, Box<dyn Error>> {
+        let issuer = method_id.split_once('#').map_or(method_id, |(did, _)| did);
+        let length = revoked
+            .iter()
+            .map(|index| index + 1)
+            .max()
+            .unwrap_or_default()
+            .max(MIN_STATUS_LIST_LENGTH);
+        let mut bits = vec![0u8; length.div_ceil(8)];
+        for index in revoked {
+            bits[index / 8] |= 0x80 >> (index % 8);
+        }
+
+        let now = Utc::now().to_rfc3339();
+        let mut list = StatusListCredential {
+            context: vec![
+                "https://www.w3.org/2018/credentials/v1".to_string(),
+                "https://w3id.org/vc/status-list/2021/v1".to_string(),
+            ],
+            id: id.to_string(),
+            credential_type: vec![
+                "VerifiableCredential".to_string(),
+                "StatusList2021Credential".to_string(),
+            ],
+            issuer: issuer.to_string(),
+            issuance_date: now.clone(),
+            credential_subject: StatusListSubject {
+                id: format!("{}#list", id),
+                subject_type: "StatusList2021".to_string(),
+                status_purpose: "revocation".to_string(),
+                encoded_list: encode_list(&bits)?,
+            },
+            proof: Proof {
+                proof_type: "Ed25519Signature2020".to_string(),
+                created: now,
+                proof_purpose: "assertionMethod".to_string(),
+                verification_method: method_id.to_string(),
+                proof_value: None,
+                nonce: Some(generate_nonce()),
+            },
+        };
+        list.proof.proof_value = Some(sign_payload(&list, signer)?);
+
+        Ok(list)
+    }
+
+    // Check the list was signed by its issuer, then decode its bitstring
+    fn verified_bits<R: DidResolver + ?Sized>(
+        &self,
+        resolver: &R,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut unsigned = self.clone();
+        let proof_value = unsigned
+            .proof
+            .proof_value
+            .take()
+            .ok_or_else(|| VCError("Status list has no proof value".to_string()))?;
+        let vr_key = issuer_key(&self.issuer, &self.proof, resolver)?;
+        if !verify_payload(&unsigned, &proof_value, &vr_key)? {
+            return Err(Box::new(VCError(format!(
+                "Status list {} has an invalid signature",
+                self.id
+            ))));
+        }
+
+        decode_list(&self.credential_subject.encoded_list)
+    }
+}
+
+fn encode_list(bits: &[u8]) -> Result<String, Box<dyn Error>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(bits)?;
+
+    Ok(URL_SAFE_NO_PAD.encode(encoder.finish()?))
+}
+
+fn decode_list(encoded_list: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    let compressed = URL_SAFE_NO_PAD.decode(encoded_list)?;
+    let mut bits = Vec::new();
+    GzDecoder::new(&compressed[..])
+        .take(MAX_LIST_BYTES + 1)
+        .read_to_end(&mut bits)?;
+    if bits.len() as u64 > MAX_LIST_BYTES {
+        return Err(Box::new(VCError(format!(
+            "Status list is larger than {} bytes",
+            MAX_LIST_BYTES
+        ))));
+    }
+
+    Ok(bits)
+}
+
+// Issuer and URL of a status list, the key of its cached bitstring
+type ListKey = (String, String);
+
+// Fetches StatusList2021 credentials over HTTP, keeping the verified
+// bitstrings for a while so that verifying many credentials of the same
+// issuer only fetches its list once.
+//
+// The cache is keyed by the list's issuer and URL, not by the DidResolver
+// that checked its signature. Within the TTL, a list verified against one
+// resolver is trusted by calls passing another resolver for the same issuer.
+// Use separate StatusListResolvers when the resolvers don't agree on issuers.
+pub struct StatusListResolver {
+    ttl: Duration,
+    client: OnceLock<reqwest::blocking::Client>,
+    cache: Mutex<HashMap<ListKey, (Instant, Vec<u8>)>>,
+}
+
+impl Default for StatusListResolver {
+    fn default() -> Self {
+        Self::with_ttl(STATUS_LIST_TTL)
+    }
+}
+
+impl StatusListResolver {
+    pub fn with_ttl(ttl: Duration) -> Self {
+        StatusListResolver {
+            ttl,
+            client: OnceLock::new(),
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Resolver shared by every verification of the process
+    pub fn global() -> &'static StatusListResolver {
+        static GLOBAL: OnceLock<StatusListResolver> = OnceLock::new();
+        GLOBAL.get_or_init(StatusListResolver::default)
+    }
+
+    // Whether the bit of a credential's entry is set. Only a list of the
+    // credential's own issuer is trusted, that issuer being resolved with
+    // `resolver` to check the list's signature.
+    pub fn is_revoked<R: DidResolver + ?Sized>(
+        &self,
+        status: &CredentialStatus,
+        issuer: &str,
+        resolver: &R,
+    ) -> Result<bool, Box<dyn Error>> {
+        let index: usize = status.status_list_index.parse().map_err(|_| {
+            VCError(format!(
+                "Invalid status list index: {}",
+                status.status_list_index
+            ))
+        })?;
+        let bits = self.bits(&status.status_list_credential, issuer, resolver)?;
+        let byte = bits.get(index / 8).ok_or_else(|| {
+            VCError(format!(
+                "Status list index {} is out of range of {}",
+                index, status.status_list_credential
+            ))
+        })?;
+
+        Ok(byte & (0x80 >> (index % 8)) != 0)
+    }
+
+    fn bits<R: DidResolver + ?Sized>(
+        &self,
+        url: &str,
+        issuer: &str,
+        resolver: &R,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        let key = (issuer.to_string(), url.to_string());
+        if let Some((fetched_at, bits)) = self.cache.lock().unwrap().get(&key) {
+            if fetched_at.elapsed() < self.ttl {
+                return Ok(bits.clone());
+            }
+        }
+
+        let response = self
+            .client
+            .get_or_init(|| {
+                reqwest::blocking::Client::builder()
+                    .timeout(STATUS_LIST_TIMEOUT)
+                    .build()
+                    .expect("Failed to build the HTTP client")
+            })
+            .get(url)
+            .send()?
+            .error_for_status()?;
+        let mut body = Vec::new();
+        response
+            .take(MAX_LIST_RESPONSE_BYTES + 1)
+            .read_to_end(&mut body)?;
+        if body.len() as u64 > MAX_LIST_RESPONSE_BYTES {
+            return Err(Box::new(VCError(format!(
+                "Status list {} is larger than {} bytes",
+                url, MAX_LIST_RESPONSE_BYTES
+            ))));
+        }
+        let list: StatusListCredential = serde_json::from_slice(&body)?;
+        if list.id != url {
+            return Err(Box::new(VCError(format!(
+                "Status list fetched from {} is {}",
+                url, list.id
+            ))));
+        }
+        // Anyone can sign a list, only the credential's issuer is trusted
+        if list.issuer != issuer {
+            return Err(Box::new(VCError(format!(
+                "Status list {} is issued by {}, not by {}",
+                url, list.issuer, issuer
+            ))));
+        }
+        let bits = list.verified_bits(resolver)?;
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(key, (Instant::now(), bits.clone()));
+
+        Ok(bits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    use crate::fixtures::{
+        deterministic_keypair, sample_credential, sample_document, FIXTURE_SEED,
+    };
+    use crate::{verify_vc_with_status, DidStorage, ProofOutcome, VerifiablePresentation};
+
+    use super::*;
+
+    // Serve the same body to every request, counting them
+    fn serve(body: impl Fn(&str) -> String + Send + 'static) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        let url = base_url.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    line.clear();
+                }
+                counter.fetch_add(1, Ordering::SeqCst);
+                let body = body(&url);
+                write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                )
+                .unwrap();
+            }
+        });
+
+        (base_url, requests)
+    }
+
+    #[test]
+    fn test_status_list_revokes_only_set_indices() {
+        let issuer = "did:example:issuer";
+        let mut storage = DidStorage::new();
+        storage
+            .store(issuer.to_string(), sample_document(issuer))
+            .unwrap();
+        let (base_url, requests) = serve(move |url| {
+            let list = StatusListCredential::issue(
+                &format!("{}/status/1", url),
+                &format!("{}#key1", issuer),
+                &[3],
+                &deterministic_keypair(FIXTURE_SEED),
+            )
+            .unwrap();
+            serde_json::to_string(&list).unwrap()
+        });
+        let list_url = format!("{}/status/1", base_url);
+        let statuses = StatusListResolver::default();
+
+        let with_status = |index| {
+            let mut vc = sample_credential(issuer, "did:example:alice", 750);
+            vc.credential_status = Some(CredentialStatus::revocation(&list_url, index));
+            vc.proof.proof_value = None;
+            vc.proof.proof_value =
+                Some(sign_payload(&vc, &deterministic_keypair(FIXTURE_SEED)).unwrap());
+            vc
+        };
+
+        let revoked = with_status(3);
+        assert!(statuses
+            .is_revoked(
+                revoked.credential_status.as_ref().unwrap(),
+                issuer,
+                &storage
+            )
+            .unwrap());
+        assert!(!verify_vc_with_status(&revoked, &storage, &statuses).unwrap());
+
+        let valid = with_status(5);
+        assert!(verify_vc_with_status(&valid, &storage, &statuses).unwrap());
+
+        // Presentations check the status of the credentials they carry
+        let holder = "did:example:alice";
+        storage
+            .store(holder.to_string(), sample_document(holder))
+            .unwrap();
+        let vp = VerifiablePresentation::new(
+            holder,
+            vec![revoked, valid],
+            &deterministic_keypair(FIXTURE_SEED),
+        )
+        .unwrap();
+        let report = vp.verify_full_with_status(&storage, &Default::default(), &statuses);
+        assert_eq!(report.holder_outcome, ProofOutcome::Valid);
+        assert_eq!(report.credentials[0].outcome, ProofOutcome::Revoked);
+        assert_eq!(report.credentials[1].outcome, ProofOutcome::Valid);
+        assert!(!report.valid);
+
+        // The list was fetched once, then served from the cache
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_oversized_lists_are_rejected() {
+        let bomb = encode_list(&vec![0u8; MAX_LIST_BYTES as usize + 1]).unwrap();
+        // Zeros compress well, the encoded list is only a few kilobytes
+        assert!(bomb.len() < 16 * 1024);
+        assert!(decode_list(&bomb)
+            .unwrap_err()
+            .to_string()
+            .contains("is larger than"));
+
+        let largest = encode_list(&vec![0u8; MAX_LIST_BYTES as usize]).unwrap();
+        assert_eq!(
+            decode_list(&largest).unwrap().len(),
+            MAX_LIST_BYTES as usize
+        );
+    }
+
+    #[test]
+    fn test_status_list_with_a_bad_signature_is_rejected() {
+        let issuer = "did:example:issuer";
+        let mut storage = DidStorage::new();
+        storage
+            .store(issuer.to_string(), sample_document(issuer))
+            .unwrap();
+        // Signed with a key that isn't the issuer's
+        let (base_url, _) = serve(move |url| {
+            let list = StatusListCredential::issue(
+                &format!("{}/status/1", url),
+                &format!("{}#key1", issuer),
+                &[],
+                &deterministic_keypair(1),
+            )
+            .unwrap();
+            serde_json::to_string(&list).unwrap()
+        });
+
+        let status = CredentialStatus::revocation(&format!("{}/status/1", base_url), 0);
+        let err = StatusListResolver::default()
+            .is_revoked(&status, issuer, &storage)
+            .unwrap_err();
+        assert!(err.to_string().contains("invalid signature"));
+    }
+
+    #[test]
+    fn test_status_list_of_another_issuer_is_rejected() {
+        let (issuer, other) = ("did:example:issuer", "did:example:other");
+        let mut storage = DidStorage::new();
+        for did in [issuer, other] {
+            storage
+                .store(did.to_string(), sample_document(did))
+                .unwrap();
+        }
+        // A valid list, but signed by another DID than the credential's issuer
+        let (base_url, _) = serve(move |url| {
+            let list = StatusListCredential::issue(
+                &format!("{}/status/1", url),
+                &format!("{}#key1", other),
+                &[],
+                &deterministic_keypair(FIXTURE_SEED),
+            )
+            .unwrap();
+            serde_json::to_string(&list).unwrap()
+        });
+
+        let status = CredentialStatus::revocation(&format!("{}/status/1", base_url), 0);
+        let statuses = StatusListResolver::default();
+        assert!(!statuses.is_revoked(&status, other, &storage).unwrap());
+        let err = statuses.is_revoked(&status, issuer, &storage).unwrap_err();
+        assert!(err.to_string().contains("is issued by did:example:other"));
+    }
+}
@@ -1,10 +1,56 @@
 use std::collections::HashMap;
 
-use crate::DidDocument;
+use base58::FromBase58;
+use chrono::Utc;
+use ed25519_dalek::{Signature, SigningKey, VerifyingKey};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    sign_payload, validate_request_with, verify_payload, ContextPolicy, CreateRequest, DidDocument,
+    DocumentMetadata, EquivalentIds, Operation, OperationRecord, RequestError,
+};
+
+// State of a registry at one point in time
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Snapshot {
+    pub created: String,
+    // Stored documents, sorted by DID
+    pub documents: Vec<DidDocument>,
+}
+
+// Snapshot signed by the registry that exported it, so another registry can
+// check where it comes from
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SignedSnapshot {
+    pub snapshot: Snapshot,
+    #[serde(rename = "proofValue")]
+    pub proof_value: String,
+}
+
+impl SignedSnapshot {
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(self)
+    }
+}
+
+// Check that a snapshot was signed with the key and not changed since
+pub fn verify_snapshot(snapshot: &SignedSnapshot, vr_key: &VerifyingKey) -> bool {
+    verify_payload(&snapshot.snapshot, &snapshot.proof_value, vr_key).unwrap_or(false)
+}
 
 // Main storage structure for DID documents
 pub struct DidStorage {
     documents: HashMap<String, DidDocument>,
+    // Version of every stored document, starting at 1 and bumped on update
+    versions: HashMap<String, u64>,
+    // When every stored document was first stored and last changed, RFC 3339
+    timestamps: HashMap<String, (String, String)>,
+    // Canonical and equivalent identifiers of the documents that have some
+    equivalent_ids: HashMap<String, EquivalentIds>,
+    // Changes of every stored document, oldest first
+    operations: HashMap<String, Vec<OperationRecord>>,
+    // Contexts accepted in imported and client supplied documents
+    context_policy: ContextPolicy,
 }
 
 impl DidStorage {
@@ -12,80 +58,359 @@ impl DidStorage {
     pub fn new() -> Self {
         DidStorage {
             documents: HashMap::new(),
+            versions: HashMap::new(),
+            timestamps: HashMap::new(),
+            equivalent_ids: HashMap::new(),
+            operations: HashMap::new(),
+            context_policy: ContextPolicy::default(),
         }
     }
 
+    pub fn set_context_policy(&mut self, policy: ContextPolicy) {
+        self.context_policy = policy;
+    }
+
     // Store a DID document
     pub fn store(&mut self, did: String, document: DidDocument) -> Result<(), String> {
+        self.store_by(did, document, None)
+    }
+
+    // Store a DID document, recording who stored it
+    pub fn store_by(
+        &mut self,
+        did: String,
+        mut document: DidDocument,
+        actor: Option<&str>,
+    ) -> Result<(), String> {
         if did != document.id {
             return Err("DID and document ID must match".to_string());
         }
+        document.normalize();
+        // Storing over an existing document makes a new version of it
+        let operation = if self.documents.contains_key(&did) {
+            Operation::Update
+        } else {
+            Operation::Create
+        };
+        *self.versions.entry(did.clone()).or_default() += 1;
+        self.touch(&did, operation, actor);
         self.documents.insert(did, document);
         Ok(())
     }
 
+    // Store a DID document along with its other identifiers
+    pub fn store_with_ids(
+        &mut self,
+        did: String,
+        document: DidDocument,
+        ids: EquivalentIds,
+    ) -> Result<(), String> {
+        ids.validate()?;
+        self.store(did.clone(), document)?;
+        self.equivalent_ids.insert(did, ids);
+        Ok(())
+    }
+
+    fn touch(&mut self, did: &str, operation: Operation, actor: Option<&str>) {
+        let now = Utc::now().to_rfc3339();
+        self.operations
+            .entry(did.to_string())
+            .or_default()
+            .push(OperationRecord {
+                operation,
+                actor: actor.map(str::to_string),
+                timestamp: now.clone(),
+            });
+        self.timestamps
+            .entry(did.to_string())
+            .and_modify(|(_, updated)| *updated = now.clone())
+            .or_insert_with(|| (now.clone(), now));
+    }
+
+    // Metadata of a stored DID document, as returned by DID resolution
+    pub fn document_metadata(&self, did: &str) -> Option<DocumentMetadata> {
+        let (created, updated) = self.timestamps.get(did)?;
+        let ids = self.equivalent_ids.get(did).cloned().unwrap_or_default();
+
+        Some(DocumentMetadata {
+            created: Some(created.clone()),
+            updated: Some(updated.clone()),
+            version_id: self.version(did).map(|version| version.to_string()),
+            canonical_id: ids.canonical_id,
+            equivalent_id: ids.equivalent_id,
+            operations: self.operations.get(did).cloned().unwrap_or_default(),
+        })
+    }
+
+    // Current version of a stored DID document
+    pub fn version(&self, did: &str) -> Option<u64> {
+        self.versions.get(did).copied()
+    }
+
+    // Number of stored DID documents
+    pub fn len(&self) -> usize {
+        self.documents.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.documents.is_empty()
+    }
+
+    // Rough size of the registry in bytes, that of the stored documents
+    // serialized as JSON
+    pub fn estimated_size(&self) -> usize {
+        self.documents
+            .values()
+            .map(|document| serde_json::to_vec(document).map_or(0, |json| json.len()))
+            .sum()
+    }
+
     // Retrieve a DID document
     pub fn get(&self, did: &str) -> Option<&DidDocument> {
         self.documents.get(did)
     }
 
+    // Stored DIDs starting with a prefix, sorted
+    pub fn find_by_prefix(&self, prefix: &str) -> Vec<&str> {
+        let mut dids: Vec<&str> = self
+            .documents
+            .keys()
+            .filter(|did| did.starts_with(prefix))
+            .map(String::as_str)
+            .collect();
+        dids.sort();
+
+        dids
+    }
+
+    // Dereference a DID URL such as "did:example:123?service=messaging" to the
+    // matching service endpoint
+    pub fn dereference_service(&self, did_url: &str) -> Option<&str> {
+        let (did, query) = did_url.split_once('?')?;
+        let service_name = query
+            .split('&')
+            .find_map(|param| param.strip_prefix("service="))?;
+
+        self.get(did)?.dereference_service(service_name)
+    }
+
+    // Resolve the public key of a verification method such as
+    // "did:example:123#key1"
+    pub fn resolve_verification_key(&self, method_id: &str) -> Option<VerifyingKey> {
+        let (did, _) = method_id.split_once('#')?;
+        let method = self
+            .get(did)?
+            .verification_method
+            .iter()
+            .find(|vm| vm.id == method_id && !vm.is_expired())?;
+
+        method.verifying_key()
+    }
+
+    // Import a JSON array of DID documents, storing the valid ones. DIDs
+    // already registered are never overwritten. Returns the stored DID or the
+    // reason of the failure for every entry, in order
+    pub fn import_json(&mut self, json: &str) -> Result<Vec<Result<String, String>>, String> {
+        let entries: Vec<serde_json::Value> = serde_json::from_str(json)
+            .map_err(|err| format!("Expected a JSON array of documents: {}", err))?;
+
+        let outcomes = entries
+            .iter()
+            .map(|entry| {
+                let document =
+                    DidDocument::from_json_with(&entry.to_string(), &self.context_policy)?;
+                let did = document.id.clone();
+                if self.get(&did).is_some() {
+                    return Err(format!("{} is already registered", did));
+                }
+                self.store(did.clone(), document)?;
+                Ok(did)
+            })
+            .collect();
+
+        Ok(outcomes)
+    }
+
+    // Store the document of a create request signed with one of the
+    // authentication keys of that same document
+    pub fn apply_signed_create(&mut self, request: &CreateRequest) -> Result<(), String> {
+        if request.request_type != "create" {
+            return Err(format!(
+                "Expected a create request, got {}",
+                request.request_type
+            ));
+        }
+        if self.get(&request.did).is_some() {
+            return Err(format!("{} is already registered", request.did));
+        }
+
+        let document = &request.document;
+        let mut outcome = Err("Document has no authentication key".to_string());
+        for method_id in document.authentication.iter() {
+            let key = document
+                .verification_method
+                .iter()
+                .find(|vm| vm.id == *method_id)
+                .and_then(|vm| vm.verifying_key());
+            if let Some(key) = key {
+                outcome = validate_request_with(request, &key, &self.context_policy)
+                    .map(|()| method_id.clone())
+                    .map_err(|err| err.to_string());
+                if outcome.is_ok() {
+                    break;
+                }
+            }
+        }
+        let signed_by = outcome?;
+
+        self.store_by(
+            request.did.clone(),
+            request.document.clone(),
+            Some(&signed_by),
+        )
+    }
+
+    // Apply an update request signed with one of the authentication keys of
+    // the currently stored document
+    pub fn apply_signed_update(&mut self, request: &CreateRequest) -> Result<(), String> {
+        if request.request_type != "update" {
+            return Err(format!(
+                "Expected an update request, got {}",
+                request.request_type
+            ));
+        }
+        if request.did != request.document.id {
+            return Err("DID and document ID must match".to_string());
+        }
+        let current = self.get(&request.did).ok_or("DID not found".to_string())?;
+
+        // The new document must be as valid as a registered one, whichever
+        // key signed it
+        let mut signed_by = None;
+        for method_id in current.authentication.iter() {
+            if let Some(key) = self.resolve_verification_key(method_id) {
+                match validate_request_with(request, &key, &self.context_policy) {
+                    Ok(()) => {
+                        signed_by = Some(method_id.clone());
+                        break;
+                    }
+                    Err(RequestError::Invalid(err)) => return Err(err),
+                    Err(RequestError::Signature(_)) => {}
+                }
+            }
+        }
+        let Some(signed_by) = signed_by else {
+            return Err("Update is not signed by an authentication key".to_string());
+        };
+
+        self.update_by(&request.did, request.document.clone(), Some(&signed_by))
+    }
+
+    // Whether a base58 signature over the message was made with one of the
+    // authentication keys of the stored document, proving control of the DID
+    pub fn verify_control(
+        &self,
+        did: &str,
+        message: &[u8],
+        signature: &str,
+    ) -> Result<bool, String> {
+        let document = self.get(did).ok_or("DID not found".to_string())?;
+        let signature_bytes = signature
+            .from_base58()
+            .map_err(|_| "Signature is not base58 encoded".to_string())?;
+        let signature = Signature::from_slice(&signature_bytes).map_err(|err| err.to_string())?;
+
+        Ok(document.authentication.iter().any(|method_id| {
+            self.resolve_verification_key(method_id)
+                .is_some_and(|key| key.verify_strict(message, &signature).is_ok())
+        }))
+    }
+
     // Update an existing DID document
     pub fn update(&mut self, did: &str, document: DidDocument) -> Result<(), String> {
+        self.update_by(did, document, None)
+    }
+
+    // Update an existing DID document, recording who changed it
+    pub fn update_by(
+        &mut self,
+        did: &str,
+        mut document: DidDocument,
+        actor: Option<&str>,
+    ) -> Result<(), String> {
         if did != document.id {
             return Err("DID and document ID must match".to_string());
         }
         if !self.documents.contains_key(did) {
             return Err("DID not found".to_string());
         }
+        document.normalize();
         self.documents.insert(did.to_string(), document);
+        *self.versions.entry(did.to_string()).or_default() += 1;
+        self.touch(did, Operation::Update, actor);
         Ok(())
     }
 
+    // Update an existing DID document, replacing its other identifiers
+    pub fn update_with_ids(
+        &mut self,
+        did: &str,
+        document: DidDocument,
+        ids: EquivalentIds,
+    ) -> Result<(), String> {
+        ids.validate()?;
+        self.update(did, document)?;
+        self.equivalent_ids.insert(did.to_string(), ids);
+        Ok(())
+    }
+
+    // Export every stored document, signed with the registry's key
+    pub fn signed_snapshot(&self, signer: &SigningKey) -> SignedSnapshot {
+        let mut documents: Vec<DidDocument> = self.documents.values().cloned().collect();
+        documents.sort_by(|a, b| a.id.cmp(&b.id));
+        let snapshot = Snapshot {
+            created: Utc::now().to_rfc3339(),
+            documents,
+        };
+        let proof_value = sign_payload(&snapshot, signer).expect("Failed to serialize snapshot");
+
+        SignedSnapshot {
+            snapshot,
+            proof_value,
+        }
+    }
+
     // Delete a DID document
     pub fn delete(&mut self, did: &str) -> Option<DidDocument> {
+        self.versions.remove(did);
+        self.timestamps.remove(did);
+        self.equivalent_ids.remove(did);
+        self.operations.remove(did);
         self.documents.remove(did)
     }
 }
 
+impl Default for DidStorage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::{Service, VerificationMethod};
-
-    use super::*;
-
-    fn create_test_document(did: &str) -> DidDocument {
-        // Create a new DID Document
-        let mut did_doc = DidDocument::new(did);
-
-        // Add a verification method
-        let verification_method = VerificationMethod {
-            id: "did:example:123456789abcdefghi#keys-1".to_string(),
-            vc_type: "Ed25519VerificationKey2018".to_string(),
-            controller: did.to_string(),
-            public_key_hex: None,
-            public_key_base58: Some("H3C2AVvLMv6gmMNam3uVAjZpfkcJCwDwnZn6z3wXmqPV".to_string()),
-        };
-        did_doc.add_verification_method(verification_method);
-
-        // Add authentication
-        did_doc.add_authentication("did:example:123456789abcdefghi#keys-1");
+    use ed25519_dalek::SigningKey;
+    use rand::rngs::OsRng;
 
-        // Add a service
-        let service = Service {
-            id: "did:example:123456789abcdefghi#vcs".to_string(),
-            type_: "VerifiableCredentialService".to_string(),
-            service_endpoint: "https://example.com/vc/".to_string(),
-        };
-        did_doc.add_service(service);
+    use crate::fixtures::{deterministic_keypair, sample_document, FIXTURE_SEED};
+    use crate::{create_signed_update, DID_CONTEXT};
 
-        did_doc
-    }
+    use super::*;
 
     #[test]
     fn test_store_and_get() {
         let mut storage = DidStorage::new();
         let did = "did:example:123";
-        let doc = create_test_document(did);
+        let doc = sample_document(did);
 
         // Test successful store
         assert!(storage.store(did.to_string(), doc.clone()).is_ok());
@@ -94,17 +419,14 @@ mod tests {
         let retrieved = storage.get(did);
         assert!(retrieved.is_some());
 
-        assert_eq!(
-            &retrieved.unwrap().to_json().unwrap(),
-            &doc.to_json().unwrap()
-        );
+        assert!(retrieved.unwrap().semantically_eq(&doc));
     }
 
     #[test]
     fn test_store_invalid_did() {
         let mut storage = DidStorage::new();
         let did = "did:example:123";
-        let mut doc = create_test_document(did);
+        let mut doc = sample_document(did);
         doc.id = "did:example:456".to_string();
 
         // Test storing with mismatched DID
@@ -113,18 +435,221 @@ mod tests {
         assert_eq!(result.unwrap_err(), "DID and document ID must match");
     }
 
+    #[test]
+    fn test_find_by_prefix() {
+        let mut storage = DidStorage::new();
+        for did in ["did:example:alice", "did:example:albert", "did:example:bob"] {
+            storage
+                .store(did.to_string(), DidDocument::new(did))
+                .unwrap();
+        }
+
+        assert_eq!(storage.find_by_prefix("did:example:b"), ["did:example:bob"]);
+        assert_eq!(
+            storage.find_by_prefix("did:example:al"),
+            ["did:example:albert", "did:example:alice"]
+        );
+        assert!(storage.find_by_prefix("did:web:").is_empty());
+    }
+
+    #[test]
+    fn test_store_normalizes_documents() {
+        let mut storage = DidStorage::new();
+        let did = "did:example:123";
+        let mut doc = sample_document(did);
+        let mut second_key = doc.verification_method[0].clone();
+        second_key.id = format!("{}#key0", did);
+        doc.add_verification_method(second_key);
+        doc.add_authentication(&format!("{}#key0", did));
+        doc.add_authentication(&format!("{}#key1", did));
+        // Base context last, and repeated
+        doc.context = [&doc.context[1..], &doc.context[..], &doc.context[..1]].concat();
+
+        storage.store(did.to_string(), doc.clone()).unwrap();
+
+        let stored = storage.get(did).unwrap();
+        assert_eq!(
+            stored.context,
+            vec![
+                DID_CONTEXT.to_string(),
+                "https://w3id.org/security/suites/ed25519-2020/v1".to_string()
+            ]
+        );
+        assert_eq!(
+            stored
+                .verification_method
+                .iter()
+                .map(|vm| vm.id.as_str())
+                .collect::<Vec<_>>(),
+            vec!["did:example:123#key0", "did:example:123#key1"]
+        );
+        assert_eq!(
+            stored.authentication,
+            vec!["did:example:123#key0", "did:example:123#key1"]
+        );
+        assert!(stored.semantically_eq(&doc));
+    }
+
+    #[test]
+    fn test_import_json_keeps_valid_documents() {
+        let mut storage = DidStorage::new();
+        let json = format!(
+            "[{}, {{\"id\": \"did:example:broken\"}}, {}]",
+            sample_document("did:example:123")
+                .to_json_compact()
+                .unwrap(),
+            sample_document("did:example:456")
+                .to_json_compact()
+                .unwrap(),
+        );
+
+        let outcomes = storage.import_json(&json).unwrap();
+        assert_eq!(outcomes.len(), 3);
+        assert_eq!(outcomes[0], Ok("did:example:123".to_string()));
+        assert!(outcomes[1].is_err());
+        assert_eq!(outcomes[2], Ok("did:example:456".to_string()));
+        assert!(storage.get("did:example:123").is_some());
+        assert!(storage.get("did:example:broken").is_none());
+        assert!(storage.get("did:example:456").is_some());
+
+        // A registered DID is never replaced
+        let mut replacement = sample_document("did:example:123");
+        replacement.also_known_as = vec!["did:example:mallory".to_string()];
+        let json = format!("[{}]", replacement.to_json_compact().unwrap());
+        let outcomes = storage.import_json(&json).unwrap();
+        assert_eq!(
+            outcomes,
+            vec![Err("did:example:123 is already registered".to_string())]
+        );
+        assert_eq!(
+            storage.get("did:example:123").unwrap().to_json().unwrap(),
+            sample_document("did:example:123").to_json().unwrap()
+        );
+
+        assert!(storage.import_json("{}").is_err());
+    }
+
+    #[test]
+    fn test_dereference_service() {
+        let mut storage = DidStorage::new();
+        let did = "did:example:123";
+        storage
+            .store(did.to_string(), sample_document(did))
+            .unwrap();
+
+        assert_eq!(
+            storage.dereference_service("did:example:123?service=vcs"),
+            Some("https://example.com/vc/")
+        );
+        assert_eq!(
+            storage.dereference_service("did:example:123?service=messaging"),
+            None
+        );
+        assert_eq!(storage.dereference_service("did:example:123"), None);
+        assert_eq!(
+            storage.dereference_service("did:example:456?service=vcs"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_resolve_verification_key() {
+        let mut storage = DidStorage::new();
+        let did = "did:example:123";
+        storage
+            .store(did.to_string(), sample_document(did))
+            .unwrap();
+
+        assert_eq!(
+            storage.resolve_verification_key("did:example:123#key1"),
+            Some(deterministic_keypair(FIXTURE_SEED).verifying_key())
+        );
+        assert!(storage
+            .resolve_verification_key("did:example:123#key2")
+            .is_none());
+        assert!(storage
+            .resolve_verification_key("did:example:456#key1")
+            .is_none());
+    }
+
+    #[test]
+    fn test_resolve_jwk_verification_key() {
+        let mut storage = DidStorage::new();
+        let did = "did:example:123";
+        let verifying_key = deterministic_keypair(FIXTURE_SEED).verifying_key();
+        let mut document = sample_document(did);
+        document.verification_method[0].public_key_base58 = None;
+        document.verification_method[0].public_key_jwk =
+            Some(crate::verifying_key_to_jwk(&verifying_key));
+        storage.store(did.to_string(), document).unwrap();
+
+        assert_eq!(
+            storage.resolve_verification_key("did:example:123#key1"),
+            Some(verifying_key)
+        );
+    }
+
+    #[test]
+    fn test_apply_signed_update() {
+        let mut storage = DidStorage::new();
+        let did = "did:example:123";
+        storage
+            .store(did.to_string(), sample_document(did))
+            .unwrap();
+
+        let mut updated = sample_document(did);
+        updated.service = None;
+
+        // Signed by someone else
+        let other = SigningKey::generate(&mut OsRng);
+        let request = create_signed_update(&updated, &other).unwrap();
+        assert!(storage.apply_signed_update(&request).is_err());
+        assert_eq!(storage.version(did), Some(1));
+
+        // Signed by the key in the document
+        let owner = deterministic_keypair(FIXTURE_SEED);
+        let request = create_signed_update(&updated, &owner).unwrap();
+        assert!(storage.apply_signed_update(&request).is_ok());
+        assert_eq!(storage.version(did), Some(2));
+        assert!(storage.get(did).unwrap().service.is_none());
+    }
+
+    #[test]
+    fn test_signed_update_with_untrusted_context_is_rejected() {
+        let mut storage = DidStorage::new();
+        let did = "did:example:123";
+        storage
+            .store(did.to_string(), sample_document(did))
+            .unwrap();
+
+        let mut updated = sample_document(did);
+        updated
+            .context
+            .push("https://untrusted.example/context".to_string());
+        let owner = deterministic_keypair(FIXTURE_SEED);
+        let request = create_signed_update(&updated, &owner).unwrap();
+        let err = storage.apply_signed_update(&request).unwrap_err();
+        assert!(err.contains("https://untrusted.example/context"), "{}", err);
+        assert_eq!(storage.version(did), Some(1));
+        assert_eq!(
+            storage.get(did).unwrap().context,
+            sample_document(did).context
+        );
+    }
+
     #[test]
     fn test_update() {
         let mut storage = DidStorage::new();
         let did = "did:example:123";
-        let doc = create_test_document(did);
+        let doc = sample_document(did);
 
         // Store initial document
         storage.store(did.to_string(), doc.clone()).unwrap();
+        assert_eq!(storage.version(did), Some(1));
 
         // Create updated document
         let updated_doc = {
-            let mut doc = create_test_document(did);
+            let mut doc = sample_document(did);
             doc.service = None;
             doc
         };
@@ -136,18 +661,16 @@ mod tests {
         );
 
         // Verify update
+        assert_eq!(storage.version(did), Some(2));
         let retrieved = storage.get(did).unwrap();
-        assert_eq!(
-            &retrieved.to_json().unwrap(),
-            &updated_doc.to_json().unwrap()
-        );
+        assert!(retrieved.semantically_eq(&updated_doc));
     }
 
     #[test]
     fn test_update_nonexistent() {
         let mut storage = DidStorage::new();
         let did = "did:example:123";
-        let doc = create_test_document(did);
+        let doc = sample_document(did);
 
         // Test updating non-existent DID
         let result = storage.update(did, doc);
@@ -159,7 +682,7 @@ mod tests {
     fn test_delete() {
         let mut storage = DidStorage::new();
         let did = "did:example:123";
-        let doc = create_test_document(did);
+        let doc = sample_document(did);
 
         // Store document
         storage.store(did.to_string(), doc.clone()).unwrap();
@@ -167,10 +690,7 @@ mod tests {
         // Test successful deletion
         let deleted = storage.delete(did);
         assert!(deleted.is_some());
-        assert_eq!(
-            &deleted.unwrap().to_json().unwrap(),
-            &doc.to_json().unwrap()
-        );
+        assert!(deleted.unwrap().semantically_eq(&doc));
 
         // Verify document is gone
         assert!(storage.get(did).is_none());
@@ -191,4 +711,36 @@ mod tests {
         let storage = DidStorage::new();
         assert!(storage.get("did:example:123").is_none());
     }
+
+    #[test]
+    fn test_signed_snapshot() {
+        let mut storage = DidStorage::new();
+        for did in ["did:example:456", "did:example:123"] {
+            storage
+                .store(did.to_string(), sample_document(did))
+                .unwrap();
+        }
+        let signer = deterministic_keypair(FIXTURE_SEED);
+
+        let snapshot = storage.signed_snapshot(&signer);
+        let dids: Vec<&str> = snapshot
+            .snapshot
+            .documents
+            .iter()
+            .map(|doc| doc.id.as_str())
+            .collect();
+        assert_eq!(dids, vec!["did:example:123", "did:example:456"]);
+        assert!(verify_snapshot(&snapshot, &signer.verifying_key()));
+
+        // Survives a round trip through JSON
+        let parsed: SignedSnapshot = serde_json::from_str(&snapshot.to_json().unwrap()).unwrap();
+        assert!(verify_snapshot(&parsed, &signer.verifying_key()));
+
+        let mut tampered = snapshot.clone();
+        tampered.snapshot.documents.pop();
+        assert!(!verify_snapshot(&tampered, &signer.verifying_key()));
+
+        let other = SigningKey::generate(&mut OsRng);
+        assert!(!verify_snapshot(&snapshot, &other.verifying_key()));
+    }
 }
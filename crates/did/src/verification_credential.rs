@@ -1,59 +1,311 @@
-use base58::{FromBase58, ToBase58};
-
-use chrono::Utc;
-use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use base58::ToBase58;
+use chrono::{DateTime, Duration, Utc};
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
 use rand::rngs::OsRng;
+use rand::Rng;
 use serde::{Deserialize, Serialize};
 use serde_json;
+use sha2::{Digest, Sha256};
 use std::error::Error;
 
+use crate::{
+    encode_public_key_to_multibase, generate_from_seed, verify_batch, CredentialStatus,
+    DidDocument, DidResolver, DidStorage, SignedSnapshot, StatusListResolver, VerificationMethod,
+    DID,
+};
+
+// How long a newly issued or renewed credential stays valid
+const VALIDITY_DAYS: i64 = 365;
+
+// How far in the future a proof may be dated, for signers whose clock is
+// ahead of ours
+pub const DEFAULT_CLOCK_SKEW_SECONDS: i64 = 300;
+
+// Base of the ids of issued credentials
+const CREDENTIAL_ID_BASE: &str = "http://creditscoringcompany.com/credentials/";
+
+// Proof suite of the signatures made by this crate
+pub(crate) const ED25519_SUITE: &str = "Ed25519Signature2020";
+
+// Base of the contexts of credential types
+const SCHEMA_BASE: &str = "https://schema.creditscoringcompany.com/";
+
 // Define the Verifiable Credential structure based on W3C VC Data Model
-#[derive(Serialize, Deserialize, Clone)]
-struct VerifiableCredential {
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct VerifiableCredential {
     #[serde(rename = "@context")]
-    context: Vec<String>,
-    id: String,
+    pub context: Vec<String>,
+    pub id: String,
     #[serde(rename = "type")]
-    credential_type: Vec<String>,
-    issuer: String,
+    pub credential_type: Vec<String>,
+    pub issuer: String,
     #[serde(rename = "issuanceDate")]
-    issuance_date: String,
+    pub issuance_date: String,
+    #[serde(
+        rename = "expirationDate",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub expiration_date: Option<String>,
     #[serde(rename = "credentialSubject")]
-    credential_subject: CredentialSubject,
-    proof: Proof,
+    pub credential_subject: CredentialSubject,
+    #[serde(
+        rename = "credentialStatus",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub credential_status: Option<CredentialStatus>,
+    pub proof: Proof,
 }
 
 // Define the CredentialSubject for creditworthiness claims
-#[derive(Serialize, Deserialize, Clone)]
-struct CredentialSubject {
-    id: String,
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CredentialSubject {
+    pub id: String,
     #[serde(rename = "creditScore")]
-    credit_score: u32,
+    pub credit_score: u32,
     #[serde(rename = "scoreRange")]
-    score_range: String,
+    pub score_range: String,
     #[serde(rename = "evaluationDate")]
-    evaluation_date: String,
+    pub evaluation_date: String,
     #[serde(rename = "confidenceLevel")]
-    confidence_level: String,
+    pub confidence_level: String,
+}
+
+// Scale and confidence of an issued credit score
+#[derive(Clone, Debug, PartialEq)]
+pub struct IssuanceParams {
+    pub min_score: u32,
+    pub max_score: u32,
+    pub confidence_level: String,
+    pub credential_id: CredentialId,
+}
+
+// How the id of an issued credential is chosen
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum CredentialId {
+    // Random UUID, a new id for every issuance
+    #[default]
+    Random,
+    // Hash of the issuer, the subject and an issuance context, the same
+    // inputs always giving the same id
+    Derived(String),
+    // Id supplied by the caller, used as is
+    Given(String),
+}
+
+impl CredentialId {
+    fn resolve(&self, issuer_did: &str, subject_did: &str) -> String {
+        match self {
+            CredentialId::Random => format!("{}{}", CREDENTIAL_ID_BASE, uuid::Uuid::new_v4()),
+            CredentialId::Derived(context) => {
+                let mut hasher = Sha256::new();
+                for part in [issuer_did, subject_did, context] {
+                    // Length prefixes keep ("ab", "c") and ("a", "bc") apart
+                    hasher.update((part.len() as u64).to_be_bytes());
+                    hasher.update(part.as_bytes());
+                }
+                format!("{}{:x}", CREDENTIAL_ID_BASE, hasher.finalize())
+            }
+            CredentialId::Given(id) => id.clone(),
+        }
+    }
+}
+
+impl Default for IssuanceParams {
+    fn default() -> Self {
+        IssuanceParams {
+            min_score: 0,
+            max_score: 850,
+            confidence_level: "High".to_string(),
+            credential_id: CredentialId::Random,
+        }
+    }
+}
+
+impl IssuanceParams {
+    // Parse a score range like "300-850" along with the confidence level
+    pub fn parse(score_range: &str, confidence_level: &str) -> Result<Self, VCError> {
+        let invalid_range = || VCError(format!("Invalid score range: {}", score_range));
+        let (min_score, max_score) = score_range.split_once('-').ok_or_else(invalid_range)?;
+        let min_score = min_score.trim().parse().map_err(|_| invalid_range())?;
+        let max_score = max_score.trim().parse().map_err(|_| invalid_range())?;
+        if min_score > max_score {
+            return Err(invalid_range());
+        }
+        if confidence_level.trim().is_empty() {
+            return Err(VCError("Missing confidence level".to_string()));
+        }
+
+        Ok(IssuanceParams {
+            min_score,
+            max_score,
+            confidence_level: confidence_level.trim().to_string(),
+            credential_id: CredentialId::Random,
+        })
+    }
+
+    pub fn score_range(&self) -> String {
+        format!("{}-{}", self.min_score, self.max_score)
+    }
+
+    pub fn contains(&self, credit_score: u32) -> bool {
+        (self.min_score..=self.max_score).contains(&credit_score)
+    }
 }
 
 // Define the Proof for the digital signature
-#[derive(Serialize, Deserialize, Clone)]
-struct Proof {
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Proof {
     #[serde(rename = "type")]
-    proof_type: String,
-    created: String,
+    pub proof_type: String,
+    pub created: String,
     #[serde(rename = "proofPurpose")]
-    proof_purpose: String,
+    pub proof_purpose: String,
     #[serde(rename = "verificationMethod")]
-    verification_method: String,
+    pub verification_method: String,
     #[serde(rename = "proofValue")]
-    proof_value: Option<String>, // Base58-encoded signature
+    pub proof_value: Option<String>, // Multibase (base58btc) encoded signature
+    // Random value making every signed proof unique, even for identical claims
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+}
+
+// Random 128-bit nonce, hex encoded
+pub fn generate_nonce() -> String {
+    format!("{:032x}", OsRng.gen::<u128>())
+}
+
+impl VerifiableCredential {
+    // Re-issue the credential with a new expiration date, keeping its id and
+    // subject, and sign it again
+    pub fn renew(&self, new_expiry: DateTime<Utc>, signer: &SigningKey) -> VerifiableCredential {
+        let mut renewed = self.clone();
+        renewed.expiration_date = Some(new_expiry.to_rfc3339());
+        renewed.proof.created = Utc::now().to_rfc3339();
+        renewed.proof.nonce = Some(generate_nonce());
+        renewed.proof.proof_value = None;
+
+        renewed
+            .sign(signer)
+            .expect("Failed to serialize credential")
+    }
+
+    // Serialize to JSON string
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(self)
+    }
+
+    // Sign the credential, which must not carry a proof value yet
+    fn sign(mut self, signer: &SigningKey) -> Result<Self, serde_json::Error> {
+        self.proof.proof_value = Some(sign_payload(&self, signer)?);
+
+        Ok(self)
+    }
+}
+
+// Sign the JSON serialization of a payload, returning the encoded signature
+pub(crate) fn sign_payload<T: Serialize>(
+    payload: &T,
+    signer: &SigningKey,
+) -> Result<String, serde_json::Error> {
+    let payload_json = serde_json::to_string(payload)?;
+    let signature = signer.sign(payload_json.as_bytes());
+
+    Ok(multibase::encode(
+        multibase::Base::Base58Btc,
+        signature.to_bytes(),
+    ))
+}
+
+// Verify an encoded signature over the JSON serialization of a payload
+pub(crate) fn verify_payload<T: Serialize>(
+    payload: &T,
+    proof_value: &str,
+    vr_key: &VerifyingKey,
+) -> Result<bool, Box<dyn Error>> {
+    let payload_json = serde_json::to_string(payload)?;
+    let signature = decode_proof_value(proof_value)?;
+
+    Ok(vr_key
+        .verify_strict(payload_json.as_bytes(), &signature)
+        .is_ok())
+}
+
+// Ed25519Signature2020 proofs are multibase base58btc, so a bare base58 value
+// (without the 'z' prefix) is rejected
+fn decode_proof_value(proof_value: &str) -> Result<Signature, Box<dyn Error>> {
+    let signature_bytes = match multibase::decode(proof_value) {
+        Ok((multibase::Base::Base58Btc, bytes)) => bytes,
+        _ => {
+            return Err(Box::new(VCError(
+                "Proof value is not multibase base58btc encoded".to_string(),
+            )))
+        }
+    };
+
+    Ok(Signature::from_slice(&signature_bytes)?)
+}
+
+// Check the signatures of many proofs, with a single batch verification when
+// all of them use the Ed25519 suite
+pub(crate) fn verify_proof_signatures(
+    items: &[(&[u8], Signature, VerifyingKey)],
+    all_ed25519: bool,
+) -> Vec<bool> {
+    if all_ed25519 {
+        return verify_batch(items);
+    }
+
+    items
+        .iter()
+        .map(|(message, signature, key)| key.verify_strict(message, signature).is_ok())
+        .collect()
+}
+
+// Unsigned skeleton of a credential of the given type, for issuers authoring
+// a new credential type to fill in. Creditworthiness credentials get their
+// known claims, other types only the subject id.
+pub fn credential_template(credential_type: &str) -> serde_json::Value {
+    let name = credential_type
+        .strip_suffix("Credential")
+        .unwrap_or(credential_type)
+        .to_lowercase();
+    let credential_subject = if credential_type == "CreditworthinessCredential" {
+        serde_json::json!({
+            "id": "",
+            "creditScore": 0,
+            "scoreRange": "",
+            "evaluationDate": "",
+            "confidenceLevel": "",
+        })
+    } else {
+        serde_json::json!({ "id": "" })
+    };
+
+    serde_json::json!({
+        "@context": [
+            "https://www.w3.org/2018/credentials/v1",
+            format!("{}{}/v1", SCHEMA_BASE, name),
+        ],
+        "id": "",
+        "type": ["VerifiableCredential", credential_type],
+        "issuer": "",
+        "issuanceDate": "",
+        "credentialSubject": credential_subject,
+        "proof": {
+            "type": "Ed25519Signature2020",
+            "created": "",
+            "proofPurpose": "assertionMethod",
+            "verificationMethod": "",
+            "proofValue": null,
+        },
+    })
 }
 
 // Custom error type for VC operations
 #[derive(Debug)]
-struct VCError(String);
+pub struct VCError(pub String);
 
 impl std::fmt::Display for VCError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
@@ -64,28 +316,84 @@ impl std::fmt::Display for VCError {
 impl Error for VCError {}
 
 // VC generation and verification logic
-struct VCCreator {
-    issuer_did: String,
+pub struct VCCreator {
+    pub issuer_did: String,
     signer: SigningKey,
+    // Number of the current key, bumped on every rotation
+    key_number: u32,
 }
 
 impl VCCreator {
     // Initialize the VC creator with a DID and generate a keypair
-    fn new(issuer_did: &str) -> Self {
+    pub fn new(issuer_did: &str) -> Result<Self, VCError> {
         let mut csprng = OsRng {};
         let signer = SigningKey::generate(&mut csprng);
-        VCCreator {
-            issuer_did: issuer_did.to_string(),
+        Self::with_signing_key(issuer_did, signer)
+    }
+
+    // Initialize the VC creator with a DID and an existing keypair
+    pub fn with_signing_key(issuer_did: &str, signer: SigningKey) -> Result<Self, VCError> {
+        let issuer_did = DID::new(issuer_did).map_err(|err| VCError(err.to_string()))?;
+        Ok(VCCreator {
+            issuer_did: issuer_did.id,
             signer,
+            key_number: 1,
+        })
+    }
+
+    // Initialize the VC creator with a keypair derived from a seed, and a DID
+    // of the given method derived from its public key, so that the same seed
+    // always gives the same issuer
+    pub fn from_seed(method: &str, seed: &[u8]) -> Result<Self, VCError> {
+        let signer = generate_from_seed(seed);
+        let did = format!(
+            "did:{}:{}",
+            method,
+            signer.verifying_key().as_bytes()[..16].to_base58()
+        );
+        Self::with_signing_key(&did, signer)
+    }
+
+    // Initialize the VC creator, also making sure the issuer DID can be
+    // resolved so the credentials it issues can be verified
+    pub fn new_with_resolver<R: DidResolver + ?Sized>(
+        issuer_did: &str,
+        resolver: &R,
+    ) -> Result<Self, VCError> {
+        let creator = Self::new(issuer_did)?;
+        if resolver.resolve(issuer_did).is_none() {
+            return Err(VCError(format!(
+                "Issuer DID is not resolvable: {}",
+                issuer_did
+            )));
         }
+
+        Ok(creator)
     }
 
     // Generate a Verifiable Credential for Alice
-    fn generate_vc(
+    pub fn generate_vc(
+        &self,
+        subject_did: &str,
+        credit_score: u32,
+    ) -> Result<VerifiableCredential, Box<dyn Error>> {
+        self.generate_vc_with(subject_did, credit_score, &IssuanceParams::default())
+    }
+
+    // Same as generate_vc, on the issuer's own score range and confidence
+    pub fn generate_vc_with(
         &self,
         subject_did: &str,
         credit_score: u32,
+        params: &IssuanceParams,
     ) -> Result<VerifiableCredential, Box<dyn Error>> {
+        if !params.contains(credit_score) {
+            return Err(Box::new(VCError(format!(
+                "Credit score {} is outside the range {}",
+                credit_score,
+                params.score_range()
+            ))));
+        }
         let now = Utc::now();
         let issuance_date = now.to_rfc3339();
         let evaluation_date = now.date_naive().to_string();
@@ -94,9 +402,9 @@ impl VCCreator {
         let credential_subject = CredentialSubject {
             id: subject_did.to_string(),
             credit_score,
-            score_range: "0-850".to_string(),
+            score_range: params.score_range(),
             evaluation_date,
-            confidence_level: "High".to_string(),
+            confidence_level: params.confidence_level.clone(),
         };
 
         // Create the unsigned VC
@@ -105,73 +413,334 @@ impl VCCreator {
                 "https://www.w3.org/2018/credentials/v1".to_string(),
                 "https://schema.creditscoringcompany.com/creditworthiness/v1".to_string(),
             ],
-            id: format!(
-                "http://creditscoringcompany.com/credentials/{}",
-                uuid::Uuid::new_v4()
-            ),
+            id: params.credential_id.resolve(&self.issuer_did, subject_did),
             credential_type: vec![
                 "VerifiableCredential".to_string(),
                 "CreditworthinessCredential".to_string(),
             ],
             issuer: self.issuer_did.clone(),
             issuance_date,
+            expiration_date: Some((now + Duration::days(VALIDITY_DAYS)).to_rfc3339()),
             credential_subject,
+            credential_status: None,
             proof: Proof {
                 proof_type: "Ed25519Signature2020".to_string(),
                 created: now.to_rfc3339(),
                 proof_purpose: "assertionMethod".to_string(),
-                verification_method: format!("{}#key-1", self.issuer_did),
+                verification_method: self.verification_method_id(),
                 proof_value: None, // Placeholder, will be replaced
+                nonce: Some(generate_nonce()),
             },
         };
 
-        // Serialize VC to JSON for signing (excluding proof.jws)
-        let vc_for_signing = vc.clone();
-        let vc_json = serde_json::to_string(&vc_for_signing)?;
+        Ok(vc.sign(&self.signer)?)
+    }
 
-        // Sign the JSON string
-        let signature = self.signer.sign(vc_json.as_bytes());
-        let signature = signature.to_bytes().to_base58();
+    // Renew a credential issued by this creator for another validity period
+    pub fn renew_vc(&self, vc: &VerifiableCredential) -> VerifiableCredential {
+        let mut vc = vc.clone();
+        vc.proof.verification_method = self.verification_method_id();
 
-        // Update the VC with the signature
-        let mut signed_vc = vc;
-        signed_vc.proof.proof_value = Some(signature);
+        vc.renew(Utc::now() + Duration::days(VALIDITY_DAYS), &self.signer)
+    }
+
+    // Id of the verification method of the current key, e.g. "did:example:issuer#key1"
+    pub fn verification_method_id(&self) -> String {
+        format!("{}#key{}", self.issuer_did, self.key_number)
+    }
 
-        Ok(signed_vc)
+    // Verification method of the current key, to publish in the issuer's document
+    pub fn verification_method(&self) -> VerificationMethod {
+        VerificationMethod {
+            id: self.verification_method_id(),
+            vc_type: "Ed25519VerificationKey2020".to_string(),
+            controller: self.issuer_did.clone(),
+            public_key_hex: None,
+            public_key_base58: Some(
+                encode_public_key_to_multibase(&self.verifying_key())
+                    .expect("Failed to encode key"),
+            ),
+            expires: None,
+            public_key_jwk: None,
+        }
+    }
+
+    // Switch to a new signing key and publish it in the issuer's document. The
+    // previous key stays in the document, marked as expiring after the grace
+    // period, so credentials signed with it keep verifying until then
+    pub fn rotate_key(
+        &mut self,
+        document: &mut DidDocument,
+        grace: Duration,
+    ) -> Result<(), VCError> {
+        if document.id != self.issuer_did {
+            return Err(VCError(format!(
+                "{} is not the document of issuer {}",
+                document.id, self.issuer_did
+            )));
+        }
+
+        let previous = self.verification_method_id();
+        let expires = (Utc::now() + grace).to_rfc3339();
+        for vm in document.verification_method.iter_mut() {
+            if vm.id == previous {
+                vm.expires = Some(expires.clone());
+            }
+        }
+
+        self.signer = SigningKey::generate(&mut OsRng);
+        self.key_number += 1;
+        document.add_verification_method(self.verification_method());
+        document.add_authentication(&self.verification_method_id());
+        document.add_assertion_method(&self.verification_method_id());
+
+        Ok(())
+    }
+
+    // Export the registry signed with the current issuer key
+    pub fn sign_snapshot(&self, storage: &DidStorage) -> SignedSnapshot {
+        storage.signed_snapshot(&self.signer)
     }
 
     // Get the public key for verification
-    fn verifying_key(&self) -> VerifyingKey {
+    pub fn verifying_key(&self) -> VerifyingKey {
         self.signer.verifying_key()
     }
 }
 
 // Verify a Verifiable Credential
-fn verify_vc(vc: &VerifiableCredential, vr_key: &VerifyingKey) -> Result<bool, Box<dyn Error>> {
-    // Create a copy of the VC with proof.jws set to empty for verification
+pub fn verify_vc(vc: &VerifiableCredential, vr_key: &VerifyingKey) -> Result<bool, Box<dyn Error>> {
+    verify_vc_with_skew(vc, vr_key, Duration::seconds(DEFAULT_CLOCK_SKEW_SECONDS))
+}
+
+// Same as verify_vc, tolerating proofs dated up to `skew` in the future.
+// Proofs dated later are rejected with an error saying so.
+pub fn verify_vc_with_skew(
+    vc: &VerifiableCredential,
+    vr_key: &VerifyingKey,
+    skew: Duration,
+) -> Result<bool, Box<dyn Error>> {
+    reject_future_proof(&vc.proof, skew)?;
+
+    match proof_input(vc)? {
+        Some((payload, signature)) => Ok(vr_key.verify_strict(&payload, &signature).is_ok()),
+        None => Ok(false),
+    }
+}
+
+// Signed payload and signature of a proof
+pub(crate) type ProofInput = (Vec<u8>, Signature);
+
+// Proof input of a credential, `None` once the credential has expired
+pub(crate) fn proof_input(vc: &VerifiableCredential) -> Result<Option<ProofInput>, Box<dyn Error>> {
+    // Create a copy of the VC with proof.proofValue set to empty for verification
     let mut vc_for_verification = vc.clone();
-    vc_for_verification.proof.proof_value = None;
-    let vc_json = serde_json::to_string(&vc_for_verification)
-        .unwrap()
-        .into_bytes();
+    let proof_value = vc_for_verification
+        .proof
+        .proof_value
+        .take()
+        .ok_or_else(|| VCError("Missing proof value".to_string()))?;
+
+    // Expired credentials are no longer valid
+    if let Some(expiration_date) = &vc.expiration_date {
+        if DateTime::parse_from_rfc3339(expiration_date)? < Utc::now() {
+            return Ok(None);
+        }
+    }
 
-    // Decode and verify signature
-    let signature_bytes = vc.proof.proof_value.clone();
-    let signature_bytes = signature_bytes.unwrap().from_base58().unwrap();
-    let signature: Signature = Signature::try_from(&signature_bytes[..64]).unwrap();
+    let payload = serde_json::to_vec(&vc_for_verification)?;
+    let signature = decode_proof_value(&proof_value)?;
 
-    Ok(vr_key.verify(&vc_json, &signature).is_ok())
+    Ok(Some((payload, signature)))
+}
+
+// Fails with an error saying so when a proof is dated in the future
+fn reject_future_proof(proof: &Proof, skew: Duration) -> Result<(), Box<dyn Error>> {
+    if proof_in_future(proof, skew)? {
+        return Err(Box::new(VCError(format!(
+            "Proof is created in the future: {}",
+            proof.created
+        ))));
+    }
+
+    Ok(())
+}
+
+// Whether a proof is dated later than now plus the tolerated clock skew
+pub fn proof_in_future(proof: &Proof, skew: Duration) -> Result<bool, Box<dyn Error>> {
+    let created = DateTime::parse_from_rfc3339(&proof.created)?;
+
+    Ok(created > Utc::now() + skew)
+}
+
+// Verify a Verifiable Credential, resolving the issuer's DID document to find
+// the key referenced by the proof's verification method. Credentials with a
+// credentialStatus are checked against their status list too.
+pub fn verify_vc_resolved<R: DidResolver + ?Sized>(
+    vc: &VerifiableCredential,
+    resolver: &R,
+) -> Result<bool, Box<dyn Error>> {
+    verify_vc_with_status(vc, resolver, StatusListResolver::global())
+}
+
+// Verify a Verifiable Credential as `verify_vc_resolved` does, with the given
+// status lists
+pub fn verify_vc_with_status<R: DidResolver + ?Sized>(
+    vc: &VerifiableCredential,
+    resolver: &R,
+    status_lists: &StatusListResolver,
+) -> Result<bool, Box<dyn Error>> {
+    let vr_key = issuer_key(&vc.issuer, &vc.proof, resolver)?;
+    if !verify_vc(vc, &vr_key)? {
+        return Ok(false);
+    }
+
+    // Revoked credentials are no longer valid
+    match &vc.credential_status {
+        Some(status) => Ok(!status_lists.is_revoked(status, &vc.issuer, resolver)?),
+        None => Ok(true),
+    }
+}
+
+// Verify many credentials as `verify_vc_resolved` does, checking all of their
+// signatures at once. The results are in the order of the credentials.
+pub fn verify_vcs_resolved<R: DidResolver + ?Sized>(
+    vcs: &[VerifiableCredential],
+    resolver: &R,
+) -> Vec<Result<bool, Box<dyn Error>>> {
+    let status_lists = StatusListResolver::global();
+    let mut results: Vec<Result<bool, Box<dyn Error>>> = Vec::with_capacity(vcs.len());
+    // Index in `results` and proof input of the credentials left to check
+    let mut pending = Vec::new();
+    for (i, vc) in vcs.iter().enumerate() {
+        let skew = Duration::seconds(DEFAULT_CLOCK_SKEW_SECONDS);
+        let input = reject_future_proof(&vc.proof, skew)
+            .and_then(|_| issuer_key(&vc.issuer, &vc.proof, resolver))
+            .and_then(|key| Ok(proof_input(vc)?.map(|input| (input, key))));
+        match input {
+            Ok(Some(((payload, signature), key))) => {
+                pending.push((i, payload, signature, key));
+                results.push(Ok(true));
+            }
+            Ok(None) => results.push(Ok(false)),
+            Err(err) => results.push(Err(err)),
+        }
+    }
+
+    let items: Vec<(&[u8], Signature, VerifyingKey)> = pending
+        .iter()
+        .map(|(_, payload, signature, key)| (payload.as_slice(), *signature, *key))
+        .collect();
+    let all_ed25519 = vcs.iter().all(|vc| vc.proof.proof_type == ED25519_SUITE);
+    let signatures = verify_proof_signatures(&items, all_ed25519);
+    for ((i, _, _, _), valid) in pending.iter().zip(signatures) {
+        let vc = &vcs[*i];
+        results[*i] = match (&vc.credential_status, valid) {
+            (_, false) => Ok(false),
+            // Revoked credentials are no longer valid
+            (Some(status), true) => status_lists
+                .is_revoked(status, &vc.issuer, resolver)
+                .map(|revoked| !revoked),
+            (None, true) => Ok(true),
+        };
+    }
+
+    results
+}
+
+// Key of the issuer's DID document referenced by a proof, if the issuer
+// authorized it for the proof's purpose
+pub(crate) fn issuer_key<R: DidResolver + ?Sized>(
+    issuer: &str,
+    proof: &Proof,
+    resolver: &R,
+) -> Result<VerifyingKey, Box<dyn Error>> {
+    let issuer = DID::new(issuer).map_err(|err| VCError(err.to_string()))?;
+    let method_id = &proof.verification_method;
+    if method_id.split_once('#').map(|(did, _)| did) != Some(issuer.id.as_str()) {
+        return Err(Box::new(VCError(format!(
+            "Verification method {} doesn't belong to the issuer",
+            method_id
+        ))));
+    }
+
+    let document = resolver
+        .resolve(&issuer.id)
+        .ok_or_else(|| VCError(format!("Issuer DID is not resolvable: {}", issuer.id)))?;
+    // A key may only sign for the relationships it's listed under
+    if !document.has_relationship(&proof.proof_purpose, method_id) {
+        return Err(Box::new(VCError(format!(
+            "Verification method {} is not authorized for {}",
+            method_id, proof.proof_purpose
+        ))));
+    }
+    let vr_key = document
+        .verification_method
+        .iter()
+        .find(|vm| &vm.id == method_id && !vm.is_expired())
+        .and_then(|vm| vm.verifying_key())
+        .ok_or_else(|| VCError(format!("Verification method not found: {}", method_id)))?;
+
+    Ok(vr_key)
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::fixtures::{sample_credential, sample_document};
+    use crate::MethodRouter;
+
     use super::*;
 
+    #[test]
+    fn test_creditworthiness_template() {
+        let template = credential_template("CreditworthinessCredential");
+        assert_eq!(
+            template["@context"][1],
+            "https://schema.creditscoringcompany.com/creditworthiness/v1"
+        );
+        assert_eq!(
+            template["type"],
+            serde_json::json!(["VerifiableCredential", "CreditworthinessCredential"])
+        );
+        for claim in [
+            "id",
+            "creditScore",
+            "scoreRange",
+            "evaluationDate",
+            "confidenceLevel",
+        ] {
+            assert!(
+                template["credentialSubject"].get(claim).is_some(),
+                "{}",
+                claim
+            );
+        }
+        assert_eq!(template["proof"]["type"], "Ed25519Signature2020");
+
+        // The template has the shape of an issued credential, minus the claims
+        let issued = VCCreator::new("did:example:issuer")
+            .unwrap()
+            .generate_vc("did:example:alice", 700)
+            .unwrap();
+        let issued = serde_json::to_value(issued).unwrap();
+        for field in issued.as_object().unwrap().keys() {
+            let optional = ["expirationDate", "credentialStatus"].contains(&field.as_str());
+            assert!(optional || template.get(field).is_some(), "{}", field);
+        }
+
+        let other = credential_template("MembershipCredential");
+        assert_eq!(
+            other["@context"][1],
+            "https://schema.creditscoringcompany.com/membership/v1"
+        );
+        assert_eq!(other["credentialSubject"], serde_json::json!({ "id": "" }));
+    }
+
     #[test]
     fn test_generate_and_verify_vc() {
         // Initialize the issuer
         let issuer_did = "did:web:creditscoringcompany.com";
-        let vc_creator = VCCreator::new(issuer_did);
+        let vc_creator = VCCreator::new(issuer_did).unwrap();
 
         // Generate a VC for Alice
         let subject_did = "did:ion:123456789abcdef";
@@ -193,10 +762,62 @@ mod tests {
         assert_eq!(vc.proof.proof_type, "Ed25519Signature2020");
     }
 
+    #[test]
+    fn test_generate_vc_with_issuance_params() {
+        let vc_creator = VCCreator::new("did:example:issuer").unwrap();
+        let params = IssuanceParams::parse("300-600", "Medium").unwrap();
+
+        let vc = vc_creator
+            .generate_vc_with("did:example:alice", 550, &params)
+            .unwrap();
+        assert_eq!(vc.credential_subject.score_range, "300-600");
+        assert_eq!(vc.credential_subject.confidence_level, "Medium");
+        assert!(verify_vc(&vc, &vc_creator.verifying_key()).unwrap());
+
+        let err = vc_creator
+            .generate_vc_with("did:example:alice", 750, &params)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "VC Error: Credit score 750 is outside the range 300-600"
+        );
+        assert!(IssuanceParams::parse("600-300", "Medium").is_err());
+    }
+
+    #[test]
+    fn test_derived_credential_ids_are_reproducible() {
+        let vc_creator = VCCreator::new("did:example:issuer").unwrap();
+        let params = IssuanceParams {
+            credential_id: CredentialId::Derived("2024-q1".to_string()),
+            ..Default::default()
+        };
+
+        let first = vc_creator
+            .generate_vc_with("did:example:alice", 700, &params)
+            .unwrap();
+        let second = vc_creator
+            .generate_vc_with("did:example:alice", 700, &params)
+            .unwrap();
+        let other = vc_creator
+            .generate_vc_with("did:example:bob", 700, &params)
+            .unwrap();
+        assert_eq!(first.id, second.id);
+        assert_ne!(first.id, other.id);
+
+        let given = IssuanceParams {
+            credential_id: CredentialId::Given("urn:uuid:fixture-1".to_string()),
+            ..Default::default()
+        };
+        let vc = vc_creator
+            .generate_vc_with("did:example:alice", 700, &given)
+            .unwrap();
+        assert_eq!(vc.id, "urn:uuid:fixture-1");
+    }
+
     #[test]
     fn test_verify_tampered_vc() {
         let issuer_did = "did:web:creditscoringcompany.com";
-        let vc_creator = VCCreator::new(issuer_did);
+        let vc_creator = VCCreator::new(issuer_did).unwrap();
         let subject_did = "did:ion:123456789abcdef";
         let credit_score = 750;
 
@@ -215,7 +836,7 @@ mod tests {
     #[test]
     fn test_verify_invalid_signature() {
         let issuer_did = "did:web:creditscoringcompany.com";
-        let vc_creator = VCCreator::new(issuer_did);
+        let vc_creator = VCCreator::new(issuer_did).unwrap();
         let subject_did = "did:ion:123456789abcdef";
         let credit_score = 750;
 
@@ -223,9 +844,12 @@ mod tests {
         let mut vc = vc_creator.generate_vc(subject_did, credit_score).unwrap();
 
         // Decode and verify signature
-        let signature: Signature = Signature::try_from([1u8; 64]).unwrap();
+        let signature = Signature::from_bytes(&[1u8; 64]);
         // Replace the signature with an invalid one
-        vc.proof.proof_value = Some(signature.to_bytes().to_base58());
+        vc.proof.proof_value = Some(multibase::encode(
+            multibase::Base::Base58Btc,
+            signature.to_bytes(),
+        ));
 
         // Verify the VC
         let vr_key = vc_creator.verifying_key();
@@ -238,4 +862,227 @@ mod tests {
         let result = result.unwrap();
         assert!(!result, "VC with invalid signature should return to false");
     }
+
+    #[test]
+    fn test_proofs_from_the_future_are_rejected() {
+        let vc_creator = VCCreator::new("did:web:creditscoringcompany.com").unwrap();
+        let vr_key = vc_creator.verifying_key();
+        let dated = |offset: Duration| {
+            let mut vc = vc_creator
+                .generate_vc("did:ion:123456789abcdef", 750)
+                .unwrap();
+            vc.proof.created = (Utc::now() + offset).to_rfc3339();
+            vc.proof.proof_value = None;
+            vc.sign(&vc_creator.signer).unwrap()
+        };
+
+        assert!(verify_vc(&dated(Duration::zero()), &vr_key).unwrap());
+        assert!(verify_vc(&dated(Duration::minutes(2)), &vr_key).unwrap());
+
+        let err = verify_vc(&dated(Duration::hours(1)), &vr_key).unwrap_err();
+        assert!(err.to_string().contains("Proof is created in the future"));
+        // Unless the tolerance covers it
+        let future = dated(Duration::hours(1));
+        assert!(verify_vc_with_skew(&future, &vr_key, Duration::hours(2)).unwrap());
+    }
+
+    #[test]
+    fn test_proof_value_is_multibase() {
+        let vc_creator = VCCreator::new("did:web:creditscoringcompany.com").unwrap();
+        let mut vc = vc_creator
+            .generate_vc("did:ion:123456789abcdef", 750)
+            .unwrap();
+        let vr_key = vc_creator.verifying_key();
+
+        let proof_value = vc.proof.proof_value.clone().unwrap();
+        assert!(proof_value.starts_with('z'));
+        assert!(verify_vc(&vc, &vr_key).unwrap());
+
+        // Legacy proofs carry the bare base58 signature
+        let (_, signature) = multibase::decode(&proof_value).unwrap();
+        vc.proof.proof_value = Some(base58::ToBase58::to_base58(signature.as_slice()));
+        assert!(verify_vc(&vc, &vr_key).is_err());
+    }
+
+    #[test]
+    fn test_rapid_issuance_is_unique() {
+        let vc_creator = VCCreator::new("did:web:creditscoringcompany.com").unwrap();
+        let vr_key = vc_creator.verifying_key();
+
+        let first = vc_creator
+            .generate_vc("did:ion:123456789abcdef", 750)
+            .unwrap();
+        let second = vc_creator
+            .generate_vc("did:ion:123456789abcdef", 750)
+            .unwrap();
+
+        assert_ne!(first.id, second.id);
+        assert_ne!(first.proof.nonce, second.proof.nonce);
+        assert_ne!(first.proof.proof_value, second.proof.proof_value);
+        assert!(verify_vc(&first, &vr_key).unwrap());
+        assert!(verify_vc(&second, &vr_key).unwrap());
+
+        // The nonce is covered by the signature
+        let mut replayed = second.clone();
+        replayed.proof.nonce = first.proof.nonce.clone();
+        assert!(!verify_vc(&replayed, &vr_key).unwrap());
+    }
+
+    #[test]
+    fn test_verify_vc_resolved() {
+        let issuer = "did:example:issuer";
+        let subject = "did:example:alice";
+        let vc = sample_credential(issuer, subject, 750);
+
+        let mut resolver = DidStorage::new();
+        assert!(verify_vc_resolved(&vc, &resolver).is_err());
+
+        resolver
+            .store(issuer.to_string(), sample_document(issuer))
+            .unwrap();
+        assert!(verify_vc_resolved(&vc, &resolver).unwrap());
+
+        let mut tampered = vc.clone();
+        tampered.credential_subject.credit_score = 850;
+        assert!(!verify_vc_resolved(&tampered, &resolver).unwrap());
+
+        // The proof must reference one of the issuer's own keys
+        let mut foreign = vc.clone();
+        foreign.proof.verification_method = format!("{}#key1", subject);
+        assert!(verify_vc_resolved(&foreign, &resolver).is_err());
+    }
+
+    #[test]
+    fn test_verify_vc_resolved_checks_proof_purpose() {
+        let issuer = "did:example:issuer";
+        let vc = sample_credential(issuer, "did:example:alice", 750);
+        assert_eq!(vc.proof.proof_purpose, "assertionMethod");
+
+        // The key is only listed for authentication
+        let mut document = sample_document(issuer);
+        document.assertion_method.clear();
+        let mut resolver = DidStorage::new();
+        resolver.store(issuer.to_string(), document).unwrap();
+
+        let err = verify_vc_resolved(&vc, &resolver).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "VC Error: Verification method did:example:issuer#key1 is not authorized for assertionMethod"
+        );
+    }
+
+    #[test]
+    fn test_new_rejects_invalid_issuer_did() {
+        assert!(VCCreator::new("did:web:creditscoringcompany.com").is_ok());
+
+        let result = VCCreator::new("did:creditscoringcompany.com");
+        assert!(result.is_err(), "Malformed issuer DID should be rejected");
+        assert_eq!(
+            result.err().unwrap().to_string(),
+            "VC Error: Invalid DID format: did:creditscoringcompany.com"
+        );
+    }
+
+    #[test]
+    fn test_new_with_resolver_requires_registered_issuer() {
+        let issuer_did = "did:web:creditscoringcompany.com";
+        let mut resolver = DidStorage::new();
+        assert!(VCCreator::new_with_resolver(issuer_did, &resolver).is_err());
+
+        resolver
+            .store(issuer_did.to_string(), DidDocument::new(issuer_did))
+            .unwrap();
+        assert!(VCCreator::new_with_resolver(issuer_did, &resolver).is_ok());
+
+        // Any resolver will do, such as one routing by DID method
+        let mut router = MethodRouter::new();
+        assert!(VCCreator::new_with_resolver(issuer_did, &router).is_err());
+        router.register("web", Box::new(resolver));
+        assert!(VCCreator::new_with_resolver(issuer_did, &router).is_ok());
+    }
+
+    #[test]
+    fn test_renew_vc() {
+        let issuer_did = "did:web:creditscoringcompany.com";
+        let vc_creator = VCCreator::new(issuer_did).unwrap();
+        let vc = vc_creator
+            .generate_vc("did:ion:123456789abcdef", 750)
+            .unwrap();
+
+        let new_expiry = Utc::now() + Duration::days(2 * VALIDITY_DAYS);
+        let renewed = vc.renew(new_expiry, &vc_creator.signer);
+
+        assert_eq!(renewed.id, vc.id);
+        assert_eq!(renewed.credential_subject.id, vc.credential_subject.id);
+        let expiry = |vc: &VerifiableCredential| {
+            DateTime::parse_from_rfc3339(vc.expiration_date.as_ref().unwrap()).unwrap()
+        };
+        assert!(expiry(&renewed) > expiry(&vc));
+
+        let vr_key = vc_creator.verifying_key();
+        assert!(verify_vc(&renewed, &vr_key).unwrap());
+    }
+
+    #[test]
+    fn test_verify_expired_vc() {
+        let issuer_did = "did:web:creditscoringcompany.com";
+        let vc_creator = VCCreator::new(issuer_did).unwrap();
+        let vc = vc_creator
+            .generate_vc("did:ion:123456789abcdef", 750)
+            .unwrap();
+
+        let expired = vc.renew(Utc::now() - Duration::days(1), &vc_creator.signer);
+
+        let vr_key = vc_creator.verifying_key();
+        assert!(!verify_vc(&expired, &vr_key).unwrap());
+    }
+
+    fn rotation_setup() -> (VCCreator, DidStorage) {
+        let issuer_did = "did:example:issuer";
+        let creator = VCCreator::new(issuer_did).unwrap();
+        let mut document = DidDocument::new(issuer_did);
+        document.add_verification_method(creator.verification_method());
+        document.add_assertion_method(&creator.verification_method_id());
+        let mut resolver = DidStorage::new();
+        resolver.store(issuer_did.to_string(), document).unwrap();
+
+        (creator, resolver)
+    }
+
+    fn rotate(creator: &mut VCCreator, resolver: &mut DidStorage, grace: Duration) {
+        let mut document = resolver.get(&creator.issuer_did).unwrap().clone();
+        creator.rotate_key(&mut document, grace).unwrap();
+        resolver.update(&creator.issuer_did, document).unwrap();
+    }
+
+    #[test]
+    fn test_credential_signed_before_rotation_verifies_during_grace() {
+        let (mut creator, mut resolver) = rotation_setup();
+        let vc = creator.generate_vc("did:example:alice", 700).unwrap();
+
+        rotate(&mut creator, &mut resolver, Duration::days(7));
+        assert_eq!(creator.verification_method_id(), "did:example:issuer#key2");
+        assert!(verify_vc_resolved(&vc, &resolver).unwrap());
+
+        // Once the grace period of a rotated out key is over it's refused
+        let vc_key2 = creator.generate_vc("did:example:alice", 700).unwrap();
+        rotate(&mut creator, &mut resolver, Duration::days(-1));
+        assert!(verify_vc_resolved(&vc_key2, &resolver).is_err());
+        assert!(verify_vc_resolved(&vc, &resolver).unwrap());
+    }
+
+    #[test]
+    fn test_credential_signed_after_rotation_verifies() {
+        let (mut creator, mut resolver) = rotation_setup();
+        rotate(&mut creator, &mut resolver, Duration::days(7));
+
+        let vc = creator.generate_vc("did:example:alice", 700).unwrap();
+        assert_eq!(vc.proof.verification_method, "did:example:issuer#key2");
+        assert!(verify_vc_resolved(&vc, &resolver).unwrap());
+
+        let document = resolver.get("did:example:issuer").unwrap();
+        assert_eq!(document.verification_method.len(), 2);
+        assert!(document.verification_method[0].expires.is_some());
+        assert!(document.verification_method[1].expires.is_none());
+    }
 }
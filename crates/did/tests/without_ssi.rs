@@ -0,0 +1,22 @@
+//! Makes sure the Ed25519 DID functionality keeps working when the crate is
+//! built without the `ssi` feature.
+#![cfg(not(feature = "ssi"))]
+
+use did::{create_signed_request, verify_request, DidStorage, DID};
+use ed25519_dalek::SigningKey;
+use rand::rngs::OsRng;
+
+#[test]
+fn test_create_store_and_verify_without_ssi() {
+    let signing_key = SigningKey::generate(&mut OsRng);
+    let did = DID::generate();
+
+    let request = create_signed_request(did.id(), &signing_key).unwrap();
+    assert!(verify_request(&request, &signing_key.verifying_key()).unwrap());
+
+    let mut storage = DidStorage::new();
+    storage
+        .store(request.did.clone(), request.document.clone())
+        .unwrap();
+    assert!(storage.get(did.id()).is_some());
+}
@@ -1,13 +1,48 @@
 use std::io;
 use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
 
-use crate::client::{spawn_client, ClientInfo};
+use crate::client::{spawn_client, ClientInfo, OUTBOX_CAPACITY};
+use crate::color::ColorMode;
 use crate::main_loop::{ServerHandle, ToDelivery};
+use crate::outbox::SendPolicy;
 
-use tokio::net::TcpListener;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Semaphore;
 
-pub async fn start_accept(bind: SocketAddr, mut handle: ServerHandle) {
-    let res = accept_loop(bind, handle.clone()).await;
+/// Limits applied to the connections accepted by `run_accept_loop`.
+#[derive(Debug, Clone)]
+pub struct AcceptLimits {
+    /// Most clients connected at the same time, others are turned away.
+    pub max_connections: usize,
+    /// What to drop when a client can't keep up with its messages.
+    pub send_policy: SendPolicy,
+    /// Messages queued for each client before the send policy applies, see
+    /// `ClientInfo::outbox_capacity`.
+    pub outbox_capacity: usize,
+    /// Whether replies are colored for the clients' terminals.
+    pub color_mode: ColorMode,
+    /// How long a connection may stay idle before the OS starts probing the
+    /// peer, so dead peers are noticed. `None` disables keepalive.
+    pub keepalive: Option<Duration>,
+}
+
+impl Default for AcceptLimits {
+    fn default() -> Self {
+        AcceptLimits {
+            max_connections: 1024,
+            send_policy: SendPolicy::default(),
+            outbox_capacity: OUTBOX_CAPACITY,
+            color_mode: ColorMode::default(),
+            keepalive: Some(Duration::from_secs(60)),
+        }
+    }
+}
+
+pub async fn start_accept(bind: SocketAddr, mut handle: ServerHandle, limits: AcceptLimits) {
+    let res = accept_loop(bind, handle.clone(), limits).await;
     match res {
         Ok(()) => {}
         Err(err) => {
@@ -16,14 +51,41 @@ pub async fn start_accept(bind: SocketAddr, mut handle: ServerHandle) {
     }
 }
 
-pub async fn accept_loop(bind: SocketAddr, handle: ServerHandle) -> Result<(), io::Error> {
+pub async fn accept_loop(
+    bind: SocketAddr,
+    handle: ServerHandle,
+    limits: AcceptLimits,
+) -> Result<(), io::Error> {
     let listen = TcpListener::bind(bind).await?;
 
+    run_accept_loop(listen, handle, limits).await
+}
+
+/// Accept connections on an already bound listener and spawn a client actor
+/// for each of them.
+pub async fn run_accept_loop(
+    listen: TcpListener,
+    handle: ServerHandle,
+    limits: AcceptLimits,
+) -> Result<(), io::Error> {
+    let connections = Arc::new(Semaphore::new(limits.max_connections));
+
     loop {
-        let (tcp, ip) = listen.accept().await?;
+        let (mut tcp, ip) = listen.accept().await?;
         println!("[Client] tcp: {:?}", tcp);
         println!("[Client] ip: {:?}", ip);
 
+        // The permit is held by the client actor until it disconnects
+        let Ok(permit) = connections.clone().try_acquire_owned() else {
+            println!("[Client] Turning away {}, server is full", ip);
+            let _ = tcp.write_all(b"Server is full, try again later.\r\n").await;
+            continue;
+        };
+
+        if let Err(err) = configure_stream(&tcp, &limits) {
+            eprintln!("[Client] Failed to set socket options for {}: {}", ip, err);
+        }
+
         let id = handle.next_id();
 
         let data = ClientInfo {
@@ -31,8 +93,197 @@ pub async fn accept_loop(bind: SocketAddr, handle: ServerHandle) -> Result<(), i
             id,
             tcp,
             handle: handle.clone(),
+            send_policy: limits.send_policy,
+            outbox_capacity: limits.outbox_capacity,
+            color_mode: limits.color_mode,
+            permit: Some(permit),
         };
 
         spawn_client(data);
     }
 }
+
+/// Disable Nagle's algorithm, so short replies to interactive commands aren't
+/// delayed, and enable keepalive as configured.
+fn configure_stream(tcp: &TcpStream, limits: &AcceptLimits) -> Result<(), io::Error> {
+    tcp.set_nodelay(true)?;
+    if let Some(keepalive) = limits.keepalive {
+        let keepalive = socket2::TcpKeepalive::new().with_time(keepalive);
+        socket2::SockRef::from(tcp).set_tcp_keepalive(&keepalive)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpStream;
+    use tokio::time::timeout;
+
+    use super::*;
+    use crate::main_loop::{spawn_main_loop, ToDelivery};
+    use did::create_signed_request;
+    use did::fixtures::{deterministic_keypair, FIXTURE_SEED};
+
+    // Read from the connection until the expected text shows up
+    async fn read_until(tcp: &mut TcpStream, expected: &str) -> String {
+        let mut received = Vec::new();
+        let mut buf = [0u8; 256];
+        while !String::from_utf8_lossy(&received).contains(expected) {
+            let n = timeout(Duration::from_secs(2), tcp.read(&mut buf))
+                .await
+                .expect("Timed out waiting for the server")
+                .unwrap();
+            assert!(n > 0, "Connection closed before {:?}", expected);
+            received.extend_from_slice(&buf[..n]);
+        }
+
+        String::from_utf8_lossy(&received).to_string()
+    }
+
+    #[tokio::test]
+    async fn test_accept_loop_registers_client() {
+        let (handle, _join) = spawn_main_loop();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let limits = AcceptLimits {
+            max_connections: 1,
+            ..Default::default()
+        };
+        tokio::spawn(run_accept_loop(listener, handle, limits));
+
+        let mut first = TcpStream::connect(addr).await.unwrap();
+        read_until(&mut first, "Welcome!").await;
+
+        first.write_all(b"c#wai\r\n").await.unwrap();
+        read_until(&mut first, "Hello").await;
+
+        // Interrupt process cancels the current operation, not the session
+        first.write_all(&[255, 244]).await.unwrap();
+        read_until(
+            &mut first,
+            "Interrupted, cancelled 0 pending operation(s)\r\n> ",
+        )
+        .await;
+        first.write_all(b"c#wai\r\n").await.unwrap();
+        read_until(&mut first, "Hello").await;
+
+        // A CHARSET request for UTF-8 is accepted, the session goes on
+        let mut request = vec![255, 250, 42, 1, b';'];
+        request.extend_from_slice(b"UTF-8");
+        request.extend_from_slice(&[255, 240]);
+        first.write_all(&request).await.unwrap();
+        first.write_all(b"c#options\r\n").await.unwrap();
+        read_until(&mut first, "charset: UTF-8").await;
+
+        // Only one connection is allowed at a time
+        let mut second = TcpStream::connect(addr).await.unwrap();
+        read_until(&mut second, "Server is full").await;
+    }
+
+    #[tokio::test]
+    async fn test_trace_describes_decoded_items() {
+        let (handle, _join) = spawn_main_loop();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(run_accept_loop(listener, handle, AcceptLimits::default()));
+        let mut tcp = TcpStream::connect(addr).await.unwrap();
+        read_until(&mut tcp, "Welcome!").await;
+
+        tcp.write_all(b"c#trace on\r\n").await.unwrap();
+        read_until(&mut tcp, "Tracing on").await;
+
+        // IAC WILL SUPPRESS-GO-AHEAD
+        tcp.write_all(&[255, 251, 3]).await.unwrap();
+        read_until(&mut tcp, "[trace] IAC WILL 3 (suppress-go-ahead)").await;
+
+        tcp.write_all(b"c#trace off\r\nc#wai\r\n").await.unwrap();
+        let received = read_until(&mut tcp, "Hello").await;
+        assert!(received.contains("[trace] command Trace"));
+        assert!(!received.contains("[trace] command WhoAmI"));
+    }
+
+    #[tokio::test]
+    async fn test_register_with_a_signed_create_request() {
+        let (handle, _join) = spawn_main_loop();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(run_accept_loop(listener, handle, AcceptLimits::default()));
+        let mut tcp = TcpStream::connect(addr).await.unwrap();
+        read_until(&mut tcp, "Welcome!").await;
+
+        let signer = deterministic_keypair(FIXTURE_SEED);
+        let mut request = create_signed_request("did:example:registered", &signer).unwrap();
+        let valid = request.clone();
+
+        // Signed by someone else
+        request.signature =
+            create_signed_request("did:example:registered", &deterministic_keypair(1))
+                .unwrap()
+                .signature;
+        let block = format!(
+            "c#register\r\n{}\r\nc#end\r\n",
+            serde_json::to_string(&request).unwrap()
+        );
+        tcp.write_all(block.as_bytes()).await.unwrap();
+        read_until(&mut tcp, "Registration rejected: Invalid signature").await;
+
+        let block = format!(
+            "c#register\r\n{}\r\nc#end\r\n",
+            serde_json::to_string(&valid).unwrap()
+        );
+        tcp.write_all(block.as_bytes()).await.unwrap();
+        read_until(&mut tcp, "Registered did:example:registered").await;
+
+        tcp.write_all(b"c#sdiddid:example:registered\r\n")
+            .await
+            .unwrap();
+        read_until(&mut tcp, "did:example:registered#key1").await;
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_says_goodbye_and_closes_cleanly() {
+        let (handle, join) = spawn_main_loop();
+        let mut server = handle.clone();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(run_accept_loop(listener, handle, AcceptLimits::default()));
+        let mut tcp = TcpStream::connect(addr).await.unwrap();
+        read_until(&mut tcp, "Welcome!").await;
+
+        server.send(ToDelivery::Shutdown).await;
+        read_until(&mut tcp, "Server is shutting down, goodbye!").await;
+
+        // The connection is closed, not reset
+        let mut buf = [0u8; 64];
+        let n = timeout(Duration::from_secs(2), tcp.read(&mut buf))
+            .await
+            .expect("Timed out waiting for the close")
+            .unwrap();
+        assert_eq!(n, 0);
+        timeout(Duration::from_secs(3), join)
+            .await
+            .expect("Main loop didn't stop")
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_accepted_stream_has_nodelay_and_keepalive() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let _client = TcpStream::connect(addr).await.unwrap();
+        let (tcp, _) = listener.accept().await.unwrap();
+        assert!(!tcp.nodelay().unwrap());
+
+        let limits = AcceptLimits {
+            keepalive: Some(Duration::from_secs(30)),
+            ..Default::default()
+        };
+        configure_stream(&tcp, &limits).unwrap();
+        assert!(tcp.nodelay().unwrap());
+        assert!(socket2::SockRef::from(&tcp).keepalive().unwrap());
+    }
+}
@@ -1,29 +1,46 @@
 use std::error::Error;
-use std::{io, net::SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use std::{collections::HashMap, io, net::SocketAddr};
 
-use did::{print_qr_code, DidDocument, VerificationMethod, DID};
+use base58::ToBase58;
+use did::{
+    credential_template, encode_public_key_to_multibase, print_compact_qr_code, print_qr_code,
+    qr_code_modules, verify_detached_signature, DidDocument, Service, VerifiablePresentation,
+    VerificationMethod, DID,
+};
+use ed25519_dalek::SigningKey;
 use futures::stream::StreamExt;
+use rand::rngs::OsRng;
 use tokio::{
-    io::AsyncWriteExt,
-    net::{
-        tcp::{ReadHalf, WriteHalf},
-        TcpStream,
-    },
+    io::{AsyncWrite, AsyncWriteExt},
+    net::{tcp::ReadHalf, TcpStream},
     select,
     sync::{
-        mpsc::{channel, unbounded_channel, Receiver, Sender, UnboundedReceiver, UnboundedSender},
-        oneshot,
+        mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+        oneshot, Notify, OwnedSemaphorePermit,
     },
     task::JoinHandle,
-    try_join,
+    time::timeout,
+};
+use tokio_util::{
+    bytes::{Buf, BytesMut},
+    codec::{Encoder, FramedRead},
 };
-use tokio_util::codec::FramedRead;
 
 static CONTEXT: &str = "Client";
+// Messages from the main loop waiting for a client before the send policy
+// applies, unless configured otherwise
+pub const OUTBOX_CAPACITY: usize = 64;
+// Last message of a client when the server stops
+const GOODBYE: &[u8] = b"Server is shutting down, goodbye!";
+const PROMPT: &[u8] = b"> ";
 
+use crate::color::{colorize, ColorMode};
+use crate::outbox::{outbox, OutboxReceiver, OutboxSender, SendPolicy};
 use crate::ClientId;
 use crate::{
-    main_loop::{ServerHandle, ToDelivery},
+    main_loop::{PresentationRequest, ServerHandle, ToDelivery},
     telnet::{Item, TelnetCodec},
 };
 
@@ -32,9 +49,11 @@ pub enum FromDelivery {
     // Should be decrypted data
     Message(Vec<u8>),
     QR(String),
+    // Ask for the next command, after an interrupted operation
+    Prompt,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ClientRole {
     Holder,
     Issuer,
@@ -71,13 +90,27 @@ pub struct ClientInfo {
     pub ip: SocketAddr,
     pub handle: ServerHandle,
     pub tcp: TcpStream,
+    // What to drop when the client can't keep up with its messages
+    pub send_policy: SendPolicy,
+    // Messages queued for the client before the send policy drops some. The
+    // main loop never waits on a client, so a small queue loses messages of
+    // bursty broadcasts to slow clients sooner, while a large one holds more
+    // memory per client.
+    pub outbox_capacity: usize,
+    // Whether replies are colored for the client's terminal
+    pub color_mode: ColorMode,
+    // Connection slot, released when the client disconnects
+    pub permit: Option<OwnedSemaphorePermit>,
 }
 
 struct ClientData {
     id: ClientId,
     handle: ServerHandle,
-    recv: Receiver<FromDelivery>,
+    recv: OutboxReceiver,
     tcp: TcpStream,
+    shutdown: Arc<Notify>,
+    color_mode: ColorMode,
+    _permit: Option<OwnedSemaphorePermit>,
 }
 
 /// A handle to this actor, used by the server.
@@ -85,27 +118,41 @@ struct ClientData {
 pub struct ClientHandle {
     pub id: ClientId,
     ip: SocketAddr,
-    chan: Sender<FromDelivery>,
+    chan: OutboxSender,
     kill: JoinHandle<()>,
+    /// Asks the actor to say goodbye and stop on its own.
+    shutdown: Arc<Notify>,
     pub role: Option<ClientRole>,
+    /// Width and height of the client's terminal, once told with NAWS.
+    pub window_size: Option<(u16, u16)>,
 }
 
 impl ClientHandle {
     pub fn send(&mut self, msg: FromDelivery) -> Result<(), io::Error> {
-        if self.chan.try_send(msg).is_err() {
-            Err(io::Error::new(
-                io::ErrorKind::BrokenPipe,
-                "Can't keep up or dead",
-            ))
-        } else {
-            Ok(())
-        }
+        self.chan.try_send(msg)
+    }
+    /// Whether the actor is gone. Unlike a client that can't keep up, it will
+    /// never read another message.
+    pub fn is_dead(&self) -> bool {
+        self.chan.is_closed()
     }
     /// Kill the actor.
     pub fn kill(self) {
         // run the destructor
         drop(self);
     }
+
+    /// Let the actor flush its output and send a goodbye before it stops,
+    /// killing it if it takes longer than `wait`.
+    pub async fn shutdown(mut self, wait: Duration) {
+        self.shutdown.notify_one();
+        if timeout(wait, &mut self.kill).await.is_err() {
+            eprintln!(
+                "[{}] {} didn't stop in time, aborting it.",
+                CONTEXT, self.id
+            );
+        }
+    }
 }
 
 impl Drop for ClientHandle {
@@ -114,14 +161,36 @@ impl Drop for ClientHandle {
     }
 }
 
+/// Build a `ClientHandle` that is not backed by a tcp connection. Messages
+/// sent to the handle can be read from the returned receiver.
+#[cfg(test)]
+pub(crate) fn test_handle(id: ClientId) -> (ClientHandle, OutboxReceiver) {
+    let (send, recv) = outbox(OUTBOX_CAPACITY, SendPolicy::default());
+    let handle = ClientHandle {
+        id,
+        ip: ([127, 0, 0, 1], 0).into(),
+        chan: send,
+        kill: tokio::spawn(async {}),
+        shutdown: Arc::new(Notify::new()),
+        role: None,
+        window_size: None,
+    };
+
+    (handle, recv)
+}
+
 pub fn spawn_client(info: ClientInfo) {
-    let (send, recv) = channel(64);
+    let (send, recv) = outbox(info.outbox_capacity, info.send_policy);
+    let shutdown = Arc::new(Notify::new());
 
     let data = ClientData {
         id: info.id,
         handle: info.handle.clone(),
         tcp: info.tcp,
         recv,
+        shutdown: shutdown.clone(),
+        color_mode: info.color_mode,
+        _permit: info.permit,
     };
 
     // This spawns the new task.
@@ -135,7 +204,9 @@ pub fn spawn_client(info: ClientInfo) {
         ip: info.ip,
         chan: send,
         kill,
+        shutdown,
         role: None,
+        window_size: None,
     };
 
     // Ignore send errors here. Should only happen if the server is shutting
@@ -157,6 +228,7 @@ async fn start_client(my_handle: oneshot::Receiver<ClientHandle>, mut data: Clie
 
     // We sent the client handle to the main loop. Start talking to the tcp
     // connection.
+    let (id, mut handle) = (data.id, data.handle.clone());
     let res = client_loop(data).await;
     match res {
         Ok(()) => {}
@@ -164,6 +236,9 @@ async fn start_client(my_handle: oneshot::Receiver<ClientHandle>, mut data: Clie
             eprintln!("Something went wrong: {}.", err);
         }
     }
+
+    // Let the main loop clean up after us.
+    handle.send(ToDelivery::ClientLeft(id)).await;
 }
 
 /// This method performs the actual job of running the client actor.
@@ -173,22 +248,378 @@ async fn client_loop(mut data: ClientData) -> Result<(), io::Error> {
     // communication between tcp_read and tcp_write
     let (send, recv) = unbounded_channel();
 
-    let ((), ()) = try_join! {
-        tcp_read(data.id, read, data.handle, send),
-        tcp_write(write, data.recv, recv),
-    }?;
+    {
+        let read = tcp_read(data.id, read, data.handle, send);
+        let write = tcp_write(write, data.recv, recv, data.shutdown, data.color_mode);
+        tokio::pin!(read, write);
+
+        select! {
+            // The client left, let the writer flush what is left
+            res = &mut read => {
+                res?;
+                write.await?;
+            },
+            // Shutting down, nothing more is read from the client
+            res = &mut write => res?,
+        }
+    }
 
     let _ = data.tcp.shutdown().await;
 
     Ok(())
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 enum InternalMsg {
     GotAreYouThere,
     SendDont(u8),
     SendWont(u8),
     SendDo(u8),
+    SendWill(u8),
+    SendSubnegotiation(u8, Vec<u8>),
+    // The client told its terminal type, which decides on colors
+    TerminalType(String),
+    // The client told its window size, which decides how QR codes are drawn
+    WindowSize(u16, u16),
+    // Answer the client directly, without going through the main loop
+    Reply(Vec<u8>),
+}
+
+/// Telnet option SUPPRESS-GO-AHEAD (RFC 858).
+const SUPPRESS_GO_AHEAD: u8 = 3;
+
+/// Telnet option CHARSET (RFC 2066).
+const CHARSET: u8 = 42;
+
+/// CHARSET subnegotiation commands.
+const CHARSET_REQUEST: u8 = 1;
+const CHARSET_ACCEPTED: u8 = 2;
+const CHARSET_REJECTED: u8 = 3;
+
+/// The only character set the server speaks.
+const UTF_8: &str = "UTF-8";
+
+/// Telnet option TERMINAL-TYPE (RFC 1091).
+const TERMINAL_TYPE: u8 = 24;
+
+/// TERMINAL-TYPE subnegotiation commands.
+const TERMINAL_TYPE_IS: u8 = 0;
+const TERMINAL_TYPE_SEND: u8 = 1;
+
+/// Telnet option NAWS, negotiate about window size (RFC 1073).
+const NAWS: u8 = 31;
+
+/// Telnet option LINEMODE (RFC 1184).
+const LINEMODE: u8 = 34;
+
+/// LINEMODE subnegotiation command and the mode bit of local line editing.
+const LINEMODE_MODE: u8 = 1;
+const MODE_EDIT: u8 = 1;
+
+/// Options the server is willing to enable, on either end of the connection.
+const SUPPORTED_OPTIONS: [u8; 2] = [SUPPRESS_GO_AHEAD, CHARSET];
+
+/// Options the server lets the client enable on its own end only.
+const CLIENT_OPTIONS: [u8; 3] = [TERMINAL_TYPE, NAWS, LINEMODE];
+
+/// Where one end of the connection stands on a telnet option.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+enum Side {
+    #[default]
+    Disabled,
+    /// The server asked for the option and waits for an answer.
+    Offered,
+    Enabled,
+    /// The client asked for the option and the server declined.
+    Refused,
+}
+
+/// Negotiated telnet options of a single connection. Per RFC 854 a request is
+/// only answered when it changes the state of an option, so the server and the
+/// client never keep acknowledging each other.
+#[derive(Debug, Default)]
+struct OptionState {
+    /// Options on the server side, requested by the client with DO/DONT.
+    local: HashMap<u8, Side>,
+    /// Options on the client side, announced by the client with WILL/WONT.
+    remote: HashMap<u8, Side>,
+    /// Whether the server already asked the client to use UTF-8.
+    charset_requested: bool,
+    /// Character set agreed on with CHARSET. Clients that never agree on one
+    /// are still read as UTF-8, invalid bytes being replaced.
+    charset: Option<String>,
+    /// Whether the server already asked the client for its terminal type.
+    terminal_type_requested: bool,
+    /// Terminal type the client sent with TERMINAL-TYPE, e.g. xterm-256color.
+    terminal_type: Option<String>,
+    /// Width and height the client sent with NAWS, updated on every resize.
+    window_size: Option<(u16, u16)>,
+    /// Whether the client was switched to a character at a time with
+    /// c#linemode off.
+    character_mode: bool,
+    /// Whether the mode was already sent with LINEMODE MODE.
+    linemode_requested: bool,
+}
+
+impl OptionState {
+    /// Offer to enable an option on the server side.
+    fn offer_will(&mut self, option: u8) -> Option<InternalMsg> {
+        let side = self.local.entry(option).or_default();
+        if *side != Side::Disabled {
+            return None;
+        }
+        *side = Side::Offered;
+        Some(InternalMsg::SendWill(option))
+    }
+
+    /// Ask the client to enable an option on its side.
+    fn offer_do(&mut self, option: u8) -> Option<InternalMsg> {
+        let side = self.remote.entry(option).or_default();
+        if *side != Side::Disabled {
+            return None;
+        }
+        *side = Side::Offered;
+        Some(InternalMsg::SendDo(option))
+    }
+
+    /// Disable an option on the server side, whether the client agreed to it
+    /// or not yet.
+    fn withdraw_will(&mut self, option: u8) -> Option<InternalMsg> {
+        let side = self.local.entry(option).or_default();
+        if !matches!(*side, Side::Enabled | Side::Offered) {
+            return None;
+        }
+        *side = Side::Disabled;
+        Some(InternalMsg::SendWont(option))
+    }
+
+    /// Switch between line and character mode. LINEMODE is used when the
+    /// client supports it, SUPPRESS-GO-AHEAD is toggled until then.
+    fn switch_mode(&mut self, character_mode: bool) -> Vec<InternalMsg> {
+        self.character_mode = character_mode;
+        if self.remote.get(&LINEMODE) == Some(&Side::Enabled) {
+            self.linemode_requested = false;
+            return self.request_linemode().into_iter().collect();
+        }
+
+        let fallback = if character_mode {
+            self.offer_will(SUPPRESS_GO_AHEAD)
+        } else {
+            self.withdraw_will(SUPPRESS_GO_AHEAD)
+        };
+        fallback
+            .into_iter()
+            .chain(self.offer_do(LINEMODE))
+            .collect()
+    }
+
+    /// Send the current mode once the client enabled LINEMODE.
+    fn request_linemode(&mut self) -> Option<InternalMsg> {
+        if self.linemode_requested || self.remote.get(&LINEMODE) != Some(&Side::Enabled) {
+            return None;
+        }
+        self.linemode_requested = true;
+        let mask = if self.character_mode { 0 } else { MODE_EDIT };
+        Some(InternalMsg::SendSubnegotiation(
+            LINEMODE,
+            vec![LINEMODE_MODE, mask],
+        ))
+    }
+
+    /// Update the state from a client request, returning the reply to send.
+    fn negotiate(&mut self, item: &Item) -> Option<InternalMsg> {
+        let supported = |option: &u8| SUPPORTED_OPTIONS.contains(option);
+
+        match item {
+            Item::Will(option) => {
+                let supported = |option: &u8| supported(option) || CLIENT_OPTIONS.contains(option);
+                let side = self.remote.entry(*option).or_default();
+                match *side {
+                    Side::Enabled | Side::Refused => None,
+                    // Answer to our own DO.
+                    Side::Offered if supported(option) => {
+                        *side = Side::Enabled;
+                        None
+                    }
+                    _ if supported(option) => {
+                        *side = Side::Enabled;
+                        Some(InternalMsg::SendDo(*option))
+                    }
+                    _ => {
+                        *side = Side::Refused;
+                        Some(InternalMsg::SendDont(*option))
+                    }
+                }
+            }
+            Item::Do(option) => {
+                let side = self.local.entry(*option).or_default();
+                match *side {
+                    Side::Enabled | Side::Refused => None,
+                    // Answer to our own WILL.
+                    Side::Offered if supported(option) => {
+                        *side = Side::Enabled;
+                        None
+                    }
+                    _ if supported(option) => {
+                        *side = Side::Enabled;
+                        Some(InternalMsg::SendWill(*option))
+                    }
+                    _ => {
+                        *side = Side::Refused;
+                        Some(InternalMsg::SendWont(*option))
+                    }
+                }
+            }
+            Item::Wont(option) => {
+                let side = self.remote.entry(*option).or_default();
+                let was_enabled = *side == Side::Enabled;
+                if matches!(*side, Side::Enabled | Side::Offered) {
+                    *side = Side::Disabled;
+                }
+                was_enabled.then_some(InternalMsg::SendDont(*option))
+            }
+            Item::Dont(option) => {
+                let side = self.local.entry(*option).or_default();
+                let was_enabled = *side == Side::Enabled;
+                if matches!(*side, Side::Enabled | Side::Offered) {
+                    *side = Side::Disabled;
+                }
+                was_enabled.then_some(InternalMsg::SendWont(*option))
+            }
+            _ => None,
+        }
+    }
+
+    /// Advertise UTF-8 once CHARSET is enabled on the server side.
+    fn request_charset(&mut self) -> Option<InternalMsg> {
+        if self.charset_requested || self.local.get(&CHARSET) != Some(&Side::Enabled) {
+            return None;
+        }
+        self.charset_requested = true;
+        let mut data = vec![CHARSET_REQUEST, b';'];
+        data.extend_from_slice(UTF_8.as_bytes());
+        Some(InternalMsg::SendSubnegotiation(CHARSET, data))
+    }
+
+    /// Ask for the terminal type once the client announced it with WILL.
+    fn request_terminal_type(&mut self) -> Option<InternalMsg> {
+        if self.terminal_type_requested || self.remote.get(&TERMINAL_TYPE) != Some(&Side::Enabled) {
+            return None;
+        }
+        self.terminal_type_requested = true;
+        Some(InternalMsg::SendSubnegotiation(
+            TERMINAL_TYPE,
+            vec![TERMINAL_TYPE_SEND],
+        ))
+    }
+
+    /// Handle a CHARSET, TERMINAL-TYPE or NAWS subnegotiation, returning the
+    /// reply to send, or the terminal type or window size for the writer.
+    /// Other subnegotiations are ignored.
+    fn subnegotiate(&mut self, option: u8, data: &[u8]) -> Option<InternalMsg> {
+        if option == NAWS {
+            let (width, height) = parse_window_size(data)?;
+            self.window_size = Some((width, height));
+            return Some(InternalMsg::WindowSize(width, height));
+        }
+        if option == TERMINAL_TYPE {
+            let (&TERMINAL_TYPE_IS, name) = data.split_first()? else {
+                return None;
+            };
+            // Names are case-insensitive ASCII, RFC 1091 sends them upper case
+            let name = String::from_utf8_lossy(name).trim().to_ascii_lowercase();
+            self.terminal_type = (!name.is_empty()).then_some(name);
+            return self.terminal_type.clone().map(InternalMsg::TerminalType);
+        }
+        if option != CHARSET {
+            return None;
+        }
+
+        match data.split_first() {
+            // The client offers a list of character sets, the first byte
+            // being the separator
+            Some((&CHARSET_REQUEST, list)) => {
+                let list = String::from_utf8_lossy(list);
+                let list = list.strip_prefix("[TTABLE]").unwrap_or(&list);
+                let mut chars = list.chars();
+                let separator = chars.next()?;
+                let offers_utf8 = chars
+                    .as_str()
+                    .split(separator)
+                    .any(|charset| charset.eq_ignore_ascii_case(UTF_8));
+                let reply = if offers_utf8 {
+                    self.charset = Some(UTF_8.to_string());
+                    let mut reply = vec![CHARSET_ACCEPTED];
+                    reply.extend_from_slice(UTF_8.as_bytes());
+                    reply
+                } else {
+                    vec![CHARSET_REJECTED]
+                };
+                Some(InternalMsg::SendSubnegotiation(CHARSET, reply))
+            }
+            // Answers to the server's own request
+            Some((&CHARSET_ACCEPTED, charset)) => {
+                self.charset = Some(String::from_utf8_lossy(charset).to_string());
+                None
+            }
+            Some((&CHARSET_REJECTED, _)) => {
+                self.charset = None;
+                None
+            }
+            _ => None,
+        }
+    }
+
+    /// Readable state of every option seen on the connection, WILL being the
+    /// server side and DO the client side.
+    fn describe(&self) -> String {
+        let mut options: Vec<u8> = self
+            .local
+            .keys()
+            .chain(self.remote.keys())
+            .copied()
+            .collect();
+        options.sort();
+        options.dedup();
+        if options.is_empty() {
+            return "No telnet options negotiated".to_string();
+        }
+
+        let state = |sides: &HashMap<u8, Side>, option: u8| {
+            format!("{:?}", sides.get(&option).copied().unwrap_or_default()).to_lowercase()
+        };
+        options
+            .iter()
+            .map(|option| {
+                format!(
+                    "option {}: WILL {}, DO {}",
+                    option_label(*option),
+                    state(&self.local, *option),
+                    state(&self.remote, *option)
+                )
+            })
+            .chain(
+                self.charset
+                    .iter()
+                    .map(|charset| format!("charset: {}", charset)),
+            )
+            .chain(
+                self.terminal_type
+                    .iter()
+                    .map(|terminal_type| format!("terminal type: {}", terminal_type)),
+            )
+            .chain(
+                self.window_size
+                    .iter()
+                    .map(|(width, height)| format!("window: {}x{}", width, height)),
+            )
+            .chain(
+                self.character_mode
+                    .then(|| "mode: character at a time".to_string()),
+            )
+            .collect::<Vec<_>>()
+            .join("\r\n")
+    }
 }
 
 async fn tcp_read(
@@ -199,81 +630,353 @@ async fn tcp_read(
 ) -> Result<(), io::Error> {
     let mut telnet = FramedRead::new(read, TelnetCodec::new());
 
+    // Ask the client for character-at-a-time mode
+    let mut options = OptionState::default();
+    if let Some(msg) = options.offer_will(SUPPRESS_GO_AHEAD) {
+        to_tcp_write.send(msg).expect("Should not be closed.");
+    }
+    // and advertise UTF-8, once the client agrees to talk about charsets
+    if let Some(msg) = options.offer_will(CHARSET) {
+        to_tcp_write.send(msg).expect("Should not be closed.");
+    }
+    // Ask for the window size, QR codes are drawn to fit it
+    if let Some(msg) = options.offer_do(NAWS) {
+        to_tcp_write.send(msg).expect("Should not be closed.");
+    }
+
+    // Items decoded on this connection are echoed back once c#trace is on
+    let mut trace = false;
+    // Signing keys of the DIDs created on this connection, by DID. They
+    // never leave the actor, presentations and documents are signed here.
+    let mut keys: HashMap<String, SigningKey> = HashMap::new();
+
     while let Some(item) = telnet.next().await {
-        match item? {
+        let item = item?;
+        if trace {
+            to_tcp_write
+                .send(InternalMsg::Reply(describe_item(&item).into_bytes()))
+                .expect("Should not be closed.");
+        }
+        if let Some(usage) = missing_argument(&item) {
+            to_tcp_write
+                .send(InternalMsg::Reply(usage.as_bytes().to_vec()))
+                .expect("Should not be closed.");
+            continue;
+        }
+
+        match item {
             Item::AreYouThere => {
                 to_tcp_write
                     .send(InternalMsg::GotAreYouThere)
                     .expect("Should not be closed.");
             }
             Item::GoAhead => { /* ignore */ }
-            Item::InterruptProcess => return Ok(()),
-            Item::Will(3) => {
-                // suppress go-ahead
-                to_tcp_write
-                    .send(InternalMsg::SendDo(3))
-                    .expect("Should not be closed.");
+            Item::InterruptProcess => {
+                // Interrupt what the client is waiting for, not the session
+                println!("[{}] Interrupting {}", CONTEXT, id);
+                handle.send(ToDelivery::Interrupt(id)).await;
             }
-            Item::Will(i) => {
-                to_tcp_write
-                    .send(InternalMsg::SendDont(i))
-                    .expect("Should not be closed.");
+            Item::Will(_) | Item::Wont(_) | Item::Do(_) | Item::Dont(_) => {
+                if let Some(msg) = options.negotiate(&item) {
+                    to_tcp_write.send(msg).expect("Should not be closed.");
+                }
+                if let Some(msg) = options.request_charset() {
+                    to_tcp_write.send(msg).expect("Should not be closed.");
+                }
+                if let Some(msg) = options.request_terminal_type() {
+                    to_tcp_write.send(msg).expect("Should not be closed.");
+                }
+                if let Some(msg) = options.request_linemode() {
+                    to_tcp_write.send(msg).expect("Should not be closed.");
+                }
             }
-            Item::Do(i) => {
-                to_tcp_write
-                    .send(InternalMsg::SendWont(i))
-                    .expect("Should not be closed.");
+            Item::Subnegotiation(option, data) => {
+                if let Some(msg) = options.subnegotiate(option, &data) {
+                    if let InternalMsg::WindowSize(width, height) = msg {
+                        handle.send(ToDelivery::WindowSize(id, width, height)).await;
+                    }
+                    to_tcp_write.send(msg).expect("Should not be closed.");
+                }
             }
             Item::Line(line) => {
                 handle.send(ToDelivery::Message(id, line)).await;
             }
-            Item::CreateDID => {
-                let did = DID::generate();
-
-                println!("[{}] creating did: {}", CONTEXT, did.id);
-                let mut did_doc = DidDocument::new(&did.id);
-                let ver_method_id_1 = format!("{}#key1", did);
-                let verification_method = VerificationMethod {
-                    id: ver_method_id_1.to_string(),
-                    vc_type: "Ed25519VerificationKey2020".to_string(),
-                    controller: did.to_string(),
-                    public_key_hex: None,
-                    public_key_base58: Some("SigningKey".into()),
-                };
-                did_doc.add_verification_method(verification_method);
-
-                // Add authentication
-                did_doc.add_authentication(&ver_method_id_1);
-                println!("[{}] creating did document", CONTEXT);
+            Item::Character(_) => { /* the line is still collected */ }
+            Item::CreateDID {
+                return_key,
+                schema_endpoint,
+            } => {
+                let (mut did_doc, signer) = create_did_document(handle.did_method());
+                if let Some(endpoint) = schema_endpoint {
+                    did_doc.add_service(Service::credential_registry(&did_doc.id, &endpoint));
+                }
+                if return_key {
+                    let msg = signing_key_notice(&did_doc.id, &signer);
+                    to_tcp_write
+                        .send(InternalMsg::Reply(msg.into_bytes()))
+                        .expect("Should not be closed.");
+                }
+                let did = did_doc.id.clone();
+                // Stored documents carry a proof so anyone can check them
+                did_doc
+                    .sign(&signer, &format!("{}#key1", did))
+                    .expect("Failed to sign document");
                 handle.send(ToDelivery::DidDocument(id, did_doc)).await;
+                keys.insert(did, signer);
+            }
+            Item::UpdateDID(request) => {
+                println!("[{}] Updating did document", CONTEXT);
+                handle.send(ToDelivery::UpdateDocument(id, request)).await;
             }
             Item::ShowDID(did) => {
-                let readalbe_string = String::from_utf8(did.clone()).expect("Failed to parsed");
+                let readalbe_string = String::from_utf8_lossy(&did);
                 println!("[{}] show did: {}", CONTEXT, readalbe_string);
                 handle.send(ToDelivery::ShowDocument(id, did)).await;
             }
+            Item::QR(did) => {
+                println!(
+                    "[{}] QR code of did: {}",
+                    CONTEXT,
+                    String::from_utf8_lossy(&did)
+                );
+                handle.send(ToDelivery::QR(id, did)).await;
+            }
+            Item::Resolve(did) => {
+                println!(
+                    "[{}] Resolving did: {}",
+                    CONTEXT,
+                    String::from_utf8_lossy(&did)
+                );
+                handle.send(ToDelivery::Resolve(id, did)).await;
+            }
             Item::AssignRole(role) => {
                 let role = String::from_utf8(role.clone()).expect("Failed to parsed");
                 println!("[{}] Assinging new role: {}", CONTEXT, role);
-                handle
-                    .send(ToDelivery::NewRole(
-                        id,
-                        role.try_into().expect("Failed to parse role"),
-                    ))
-                    .await;
+                match role.try_into() {
+                    Ok(role) => handle.send(ToDelivery::NewRole(id, role)).await,
+                    Err(err) => {
+                        let msg = format!("{}, {}", err, ROLE_USAGE);
+                        to_tcp_write
+                            .send(InternalMsg::Reply(msg.into_bytes()))
+                            .expect("Should not be closed.");
+                    }
+                }
             }
             Item::WhoAmI => {
                 println!("[{}] Asking for who they are", CONTEXT);
                 handle.send(ToDelivery::MyInfo(id)).await;
             }
             Item::VerifyDID(did) => {
-                let readalbe_string = String::from_utf8(did.clone()).expect("Failed to parsed");
+                let readalbe_string = String::from_utf8_lossy(&did);
                 println!("[{}] Verifying did: {}", CONTEXT, readalbe_string);
                 handle.send(ToDelivery::VerifyDID(id, did)).await;
             }
-            Item::ShowVP => {
+            Item::ShowVP(presentation) => {
                 println!("[{}] Verifying Presentation", CONTEXT);
-                handle.send(ToDelivery::ShowVP(id)).await;
+                handle.send(ToDelivery::ShowVP(id, presentation)).await;
+            }
+            Item::RequestPresentation(holder_did) => {
+                println!(
+                    "[{}] Requesting presentation from: {}",
+                    CONTEXT,
+                    String::from_utf8_lossy(&holder_did)
+                );
+                handle
+                    .send(ToDelivery::RequestPresentation(id, holder_did))
+                    .await;
+            }
+            Item::Subscribe => {
+                println!("[{}] Subscribing to new DIDs", CONTEXT);
+                handle.send(ToDelivery::Subscribe(id)).await;
+            }
+            Item::Unsubscribe => {
+                println!("[{}] Unsubscribing from new DIDs", CONTEXT);
+                handle.send(ToDelivery::Unsubscribe(id)).await;
+            }
+            Item::IssueVC(args) => {
+                println!(
+                    "[{}] Issuing credential: {}",
+                    CONTEXT,
+                    String::from_utf8_lossy(&args)
+                );
+                handle.send(ToDelivery::IssueVC(id, args)).await;
+            }
+            Item::RenewVC(credential_id) => {
+                println!(
+                    "[{}] Renewing credential: {}",
+                    CONTEXT,
+                    String::from_utf8_lossy(&credential_id)
+                );
+                handle.send(ToDelivery::RenewVC(id, credential_id)).await;
+            }
+            Item::Resign(did) => {
+                println!("[{}] Re-signing a document", CONTEXT);
+                let (resp, document) = oneshot::channel();
+                handle.send(ToDelivery::Resign(id, did, resp)).await;
+                // The main loop answers itself when there is nothing to sign
+                if let Ok(document) = document.await {
+                    match resign_document(&keys, document) {
+                        Ok(document) => handle.send(ToDelivery::Resigned(id, document)).await,
+                        Err(err) => to_tcp_write
+                            .send(InternalMsg::Reply(err.into_bytes()))
+                            .expect("Should not be closed."),
+                    }
+                }
+            }
+            Item::RotateIssuer => {
+                println!("[{}] Rotating the issuer key", CONTEXT);
+                handle.send(ToDelivery::RotateIssuer(id)).await;
+            }
+            Item::Snapshot => {
+                println!("[{}] Exporting a registry snapshot", CONTEXT);
+                handle.send(ToDelivery::Snapshot(id)).await;
+            }
+            Item::Options => {
+                to_tcp_write
+                    .send(InternalMsg::Reply(options.describe().into_bytes()))
+                    .expect("Should not be closed.");
+            }
+            Item::Trace(switch) => {
+                let msg = match String::from_utf8_lossy(&switch).trim() {
+                    "on" => {
+                        trace = true;
+                        "Tracing on"
+                    }
+                    "off" => {
+                        trace = false;
+                        "Tracing off"
+                    }
+                    _ => TRACE_USAGE,
+                };
+                to_tcp_write
+                    .send(InternalMsg::Reply(msg.as_bytes().to_vec()))
+                    .expect("Should not be closed.");
+            }
+            Item::LineMode(switch) => {
+                let (character_mode, msg) = match String::from_utf8_lossy(&switch).trim() {
+                    "on" => (false, "Line mode on"),
+                    "off" => (true, "Line mode off, characters are sent one at a time"),
+                    _ => {
+                        to_tcp_write
+                            .send(InternalMsg::Reply(LINEMODE_USAGE.as_bytes().to_vec()))
+                            .expect("Should not be closed.");
+                        continue;
+                    }
+                };
+                println!(
+                    "[{}] {} switched character mode to {}",
+                    CONTEXT, id, character_mode
+                );
+                telnet.decoder_mut().set_character_mode(character_mode);
+                for negotiation in options.switch_mode(character_mode) {
+                    to_tcp_write
+                        .send(negotiation)
+                        .expect("Should not be closed.");
+                }
+                to_tcp_write
+                    .send(InternalMsg::Reply(msg.as_bytes().to_vec()))
+                    .expect("Should not be closed.");
+            }
+            Item::Hex(text) => {
+                to_tcp_write
+                    .send(InternalMsg::Reply(describe_bytes(&text).into_bytes()))
+                    .expect("Should not be closed.");
+            }
+            Item::Schema(credential_type) => {
+                let credential_type = String::from_utf8_lossy(&credential_type);
+                let template = credential_template(credential_type.trim());
+                let msg = serde_json::to_string_pretty(&template)
+                    .expect("Failed to serialize the template");
+                to_tcp_write
+                    .send(InternalMsg::Reply(msg.into_bytes()))
+                    .expect("Should not be closed.");
+            }
+            Item::Validate(document) => {
+                println!("[{}] Validating a document", CONTEXT);
+                to_tcp_write
+                    .send(InternalMsg::Reply(
+                        validate_document(&document).into_bytes(),
+                    ))
+                    .expect("Should not be closed.");
+            }
+            Item::VerifySignature(args) => {
+                to_tcp_write
+                    .send(InternalMsg::Reply(verify_signature(&args).into_bytes()))
+                    .expect("Should not be closed.");
+            }
+            Item::Ephemeral => {
+                println!("[{}] Switching to ephemeral mode", CONTEXT);
+                handle.send(ToDelivery::Ephemeral(id)).await;
+            }
+            Item::HashDocument(did) => {
+                println!("[{}] Hashing a document", CONTEXT);
+                handle.send(ToDelivery::HashDocument(id, did)).await;
+            }
+            Item::DirectMessage(args) => {
+                println!("[{}] Sending a direct message", CONTEXT);
+                handle.send(ToDelivery::DirectMessage(id, args)).await;
+            }
+            Item::Expand(did) => {
+                println!("[{}] Expanding a document", CONTEXT);
+                handle.send(ToDelivery::Expand(id, did)).await;
+            }
+            Item::Relationships(did) => {
+                println!("[{}] Listing verification relationships", CONTEXT);
+                handle.send(ToDelivery::Relationships(id, did)).await;
+            }
+            Item::BenchVerify(args) => {
+                println!("[{}] Benchmarking verification", CONTEXT);
+                handle.send(ToDelivery::BenchVerify(id, args)).await;
+            }
+            Item::Methods => {
+                println!("[{}] Listing DID methods", CONTEXT);
+                handle.send(ToDelivery::Methods(id)).await;
+            }
+            Item::Present(args) => {
+                println!("[{}] Presenting a credential", CONTEXT);
+                let (resp, request) = oneshot::channel();
+                handle.send(ToDelivery::Present(id, args, resp)).await;
+                // The main loop answers itself when there is nothing to sign
+                if let Ok(request) = request.await {
+                    match sign_presentation(&keys, request) {
+                        Ok(vp) => handle.send(ToDelivery::ShowVP(id, vp)).await,
+                        Err(err) => to_tcp_write
+                            .send(InternalMsg::Reply(err.into_bytes()))
+                            .expect("Should not be closed."),
+                    }
+                }
+            }
+            Item::Wallet => {
+                println!("[{}] Listing my credentials", CONTEXT);
+                handle.send(ToDelivery::Wallet(id)).await;
+            }
+            Item::Forget(credential_id) => {
+                println!("[{}] Forgetting a credential", CONTEXT);
+                handle.send(ToDelivery::Forget(id, credential_id)).await;
+            }
+            Item::Challenge(args) => {
+                println!("[{}] Challenging a client", CONTEXT);
+                handle.send(ToDelivery::Challenge(id, args)).await;
+            }
+            Item::Respond(args) => {
+                println!("[{}] Responding to a challenge", CONTEXT);
+                handle.send(ToDelivery::Respond(id, args)).await;
+            }
+            Item::ServerLog => {
+                println!("[{}] Asking for the server log", CONTEXT);
+                handle.send(ToDelivery::ServerLog(id)).await;
+            }
+            Item::Stats => {
+                println!("[{}] Asking for statistics", CONTEXT);
+                handle.send(ToDelivery::Stats(id)).await;
+            }
+            Item::Register(request) => {
+                println!("[{}] Registering a client supplied document", CONTEXT);
+                handle.send(ToDelivery::Register(id, request)).await;
+            }
+            Item::Import(documents) => {
+                println!("[{}] Importing documents", CONTEXT);
+                handle.send(ToDelivery::Import(id, documents)).await;
             }
             //Todo: Add command direction to server
             item => {
@@ -290,23 +993,297 @@ async fn tcp_read(
     Ok(())
 }
 
-async fn tcp_write(
-    mut write: WriteHalf<'_>,
-    mut recv: Receiver<FromDelivery>,
+static ROLE_USAGE: &str = "usage: c#ar<holder|issuer|verifier>";
+static TRACE_USAGE: &str = "usage: c#trace on|off";
+static LINEMODE_USAGE: &str = "usage: c#linemode on|off";
+pub(crate) static DM_USAGE: &str = "usage: c#dm<did> <text>";
+
+// Create a new DID of the given method and its document, authenticated by a
+// freshly generated key
+pub(crate) fn create_did_document(method: &str) -> (DidDocument, SigningKey) {
+    let did = DID::generate_with_method(method).expect("DID method is validated at startup");
+    let signer = SigningKey::generate(&mut OsRng);
+
+    println!("[{}] creating did: {}", CONTEXT, did.id);
+    let mut did_doc = DidDocument::new(&did.id);
+    let ver_method_id_1 = format!("{}#key1", did);
+    let verification_method = VerificationMethod {
+        id: ver_method_id_1.to_string(),
+        vc_type: "Ed25519VerificationKey2020".to_string(),
+        controller: did.to_string(),
+        public_key_hex: None,
+        public_key_base58: Some(
+            encode_public_key_to_multibase(&signer.verifying_key()).expect("Failed to encode key"),
+        ),
+        expires: None,
+        public_key_jwk: None,
+    };
+    did_doc.add_verification_method(verification_method);
+
+    // Add authentication
+    did_doc.add_authentication(&ver_method_id_1);
+    println!("[{}] creating did document", CONTEXT);
+
+    (did_doc, signer)
+}
+
+// Presentation of the requested credential, signed with the key of its
+// subject and carrying the verifier's challenge
+pub(crate) fn sign_presentation(
+    keys: &HashMap<String, SigningKey>,
+    request: PresentationRequest,
+) -> Result<Vec<u8>, String> {
+    let holder_did = request.credential.credential_subject.id.clone();
+    let signer = keys.get(&holder_did).ok_or(format!(
+        "No signing key of {} on this connection",
+        holder_did
+    ))?;
+    let vp = VerifiablePresentation::with_challenge(
+        &holder_did,
+        vec![request.credential],
+        signer,
+        Some(&request.challenge),
+    )
+    .map_err(|err| format!("Failed to create presentation: {}", err))?;
+
+    Ok(serde_json::to_vec(&vp).expect("Failed to parsed"))
+}
+
+// Sign a stored document again with the key of its authentication method
+pub(crate) fn resign_document(
+    keys: &HashMap<String, SigningKey>,
+    mut document: DidDocument,
+) -> Result<DidDocument, String> {
+    let did = document.id.clone();
+    let failed = |err: &str| format!("Failed to re-sign {}: {}", did, err);
+    let signer = keys
+        .get(&did)
+        .ok_or_else(|| failed("its key isn't held by this connection"))?;
+    let method_id = document
+        .verification_method
+        .iter()
+        .find(|vm| {
+            document.has_relationship("authentication", &vm.id)
+                && vm.verifying_key() == Some(signer.verifying_key())
+        })
+        .map(|vm| vm.id.clone())
+        .ok_or_else(|| failed("no authentication method matches its key"))?;
+    document
+        .sign(signer, &method_id)
+        .map_err(|err| failed(&err.to_string()))?;
+
+    Ok(document)
+}
+
+// Hand the signing key of a created DID to the client, so it can sign updates
+pub(crate) fn signing_key_notice(did: &str, signer: &SigningKey) -> String {
+    format!(
+        "WARNING: telnet is plaintext, anyone on the network can read this key.\r\n\
+         Signing key of {} (base58): {}",
+        did,
+        signer.to_bytes().to_base58()
+    )
+}
+
+// Usage hint of a command sent without its argument, e.g. a bare c#sdid
+fn missing_argument(item: &Item) -> Option<String> {
+    let (arg, usage) = match item {
+        Item::ShowDID(did) => (did, "missing DID argument, usage: c#sdid<did>".to_string()),
+        Item::VerifyDID(did) => (did, "missing DID argument, usage: c#vdid<did>".to_string()),
+        Item::RequestPresentation(did) => (
+            did,
+            "missing DID argument, usage: c#rvp<holder_did>".to_string(),
+        ),
+        Item::Present(args) => (
+            args,
+            "missing arguments, usage: c#present<verifier_id> <credential_id>".to_string(),
+        ),
+        Item::Forget(credential_id) => (
+            credential_id,
+            "missing credential argument, usage: c#forget<credential_id>".to_string(),
+        ),
+        Item::Challenge(args) => (
+            args,
+            "missing arguments, usage: c#challenge<client_id> <did>".to_string(),
+        ),
+        Item::Respond(args) => (
+            args,
+            "missing arguments, usage: c#respond<verifier_id> <signature>".to_string(),
+        ),
+        Item::Resign(did) => (
+            did,
+            "missing DID argument, usage: c#resign<did>".to_string(),
+        ),
+        Item::HashDocument(did) => (
+            did,
+            "missing DID argument, usage: c#hashdoc<did>".to_string(),
+        ),
+        Item::DirectMessage(args) => (args, format!("missing arguments, {}", DM_USAGE)),
+        Item::Expand(did) => (
+            did,
+            "missing DID argument, usage: c#expand<did>".to_string(),
+        ),
+        Item::Relationships(did) => (did, "missing DID argument, usage: c#rels<did>".to_string()),
+        Item::BenchVerify(args) => (
+            args,
+            "missing DID argument, usage: c#benchverify<did> [iterations]".to_string(),
+        ),
+        Item::QR(did) => (did, "missing DID argument, usage: c#qr<did>".to_string()),
+        Item::Trace(switch) => (switch, format!("missing argument, {}", TRACE_USAGE)),
+        Item::Schema(credential_type) => (
+            credential_type,
+            "missing credential type, usage: c#schema<type>".to_string(),
+        ),
+        Item::LineMode(switch) => (switch, format!("missing argument, {}", LINEMODE_USAGE)),
+        Item::Resolve(did) => (
+            did,
+            "missing DID argument, usage: c#resolve<did>".to_string(),
+        ),
+        Item::AssignRole(role) => (role, format!("missing role argument, {}", ROLE_USAGE)),
+        _ => return None,
+    };
+
+    arg.trim_ascii().is_empty().then_some(usage)
+}
+
+// Every problem of a DID document sent with c#validate, nothing is stored
+fn validate_document(document: &[u8]) -> String {
+    let document: DidDocument = match serde_json::from_slice(document) {
+        Ok(document) => document,
+        Err(err) => return format!("Invalid document: {}", err),
+    };
+
+    let Err(errors) = document.validate() else {
+        return format!("Document {} is valid", document.id);
+    };
+    let mut report = format!("Document {} has {} problem(s):", document.id, errors.len());
+    for err in errors {
+        report.push_str("\r\n- ");
+        report.push_str(&err.to_string());
+    }
+
+    report
+}
+
+// An option number with its name, when the server knows it
+fn option_label(option: u8) -> String {
+    match option {
+        SUPPRESS_GO_AHEAD => format!("{} (suppress-go-ahead)", option),
+        CHARSET => format!("{} (charset)", option),
+        TERMINAL_TYPE => format!("{} (terminal-type)", option),
+        NAWS => format!("{} (naws)", option),
+        LINEMODE => format!("{} (linemode)", option),
+        _ => option.to_string(),
+    }
+}
+
+// Human-readable trace line of an item decoded on a traced connection
+fn describe_item(item: &Item) -> String {
+    let description = match item {
+        Item::Will(option) => format!("IAC WILL {}", option_label(*option)),
+        Item::Wont(option) => format!("IAC WONT {}", option_label(*option)),
+        Item::Do(option) => format!("IAC DO {}", option_label(*option)),
+        Item::Dont(option) => format!("IAC DONT {}", option_label(*option)),
+        Item::Subnegotiation(option, data) => format!(
+            "IAC SB {} with {} bytes of data",
+            option_label(*option),
+            data.len()
+        ),
+        Item::SE => "IAC SE".to_string(),
+        Item::DataMark => "IAC DM".to_string(),
+        Item::Break => "IAC BRK".to_string(),
+        Item::InterruptProcess => "IAC IP".to_string(),
+        Item::AbortOutput => "IAC AO".to_string(),
+        Item::AreYouThere => "IAC AYT".to_string(),
+        Item::GoAhead => "IAC GA".to_string(),
+        Item::Line(line) => format!("line {:?}", String::from_utf8_lossy(line)),
+        Item::Character(byte) => format!("character {:?}", *byte as char),
+        // Commands are named after their variant, arguments are left out
+        command => {
+            let debug = format!("{:?}", command);
+            let name = debug.split(['(', ' ']).next().unwrap_or_default();
+            format!("command {}", name)
+        }
+    };
+
+    format!("[trace] {}", description)
+}
+
+// Hex and UTF-8 rendering of received bytes, to debug encoding issues
+fn describe_bytes(bytes: &[u8]) -> String {
+    let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+
+    format!(
+        "hex: {}\r\nutf8: {}",
+        hex.join(" "),
+        String::from_utf8_lossy(bytes)
+    )
+}
+
+// Check a detached signature given as "<multibase_key> <base58_sig> <message>"
+fn verify_signature(args: &[u8]) -> String {
+    let args = String::from_utf8_lossy(args);
+    let mut parts = args.splitn(3, ' ');
+    let (Some(key), Some(signature), Some(message)) = (parts.next(), parts.next(), parts.next())
+    else {
+        return "usage: c#vsig<multibase_key> <base58_sig> <message>".to_string();
+    };
+
+    match verify_detached_signature(key, signature, message.as_bytes()) {
+        Ok(true) => "Signature is valid".to_string(),
+        Ok(false) => "Signature is invalid".to_string(),
+        Err(err) => format!("Unable to verify signature: {}", err),
+    }
+}
+
+// Largest chunk handed to the tcp connection at once, so that a slow client
+// can't keep the select loop stuck inside a single write.
+const WRITE_CHUNK_SIZE: usize = 1024;
+// Messages longer than this are truncated before being sent to the client.
+const MAX_MESSAGE_SIZE: usize = 64 * 1024;
+// Stop taking messages from the main loop while this much output is still
+// waiting to be written to the client.
+const MAX_PENDING_SIZE: usize = 4 * MAX_MESSAGE_SIZE;
+
+async fn tcp_write<W: AsyncWrite + Unpin>(
+    mut write: W,
+    mut recv: OutboxReceiver,
     mut from_tcp_read: UnboundedReceiver<InternalMsg>,
+    shutdown: Arc<Notify>,
+    color_mode: ColorMode,
 ) -> Result<(), io::Error> {
+    // Output that has been accepted but not yet written to the client
+    let mut pending = BytesMut::new();
+    let mut codec = TelnetCodec::new();
+    // Decided again once the client tells its terminal type
+    let mut color = color_mode.enabled(None);
+    // Width of the client's terminal, once told with NAWS
+    let mut columns = None;
+    let paint = |color: bool, msg: &[u8]| if color { colorize(msg) } else { msg.to_vec() };
+
     loop {
+        let chunk_len = pending.len().min(WRITE_CHUNK_SIZE);
+
         select! {
-            msg = recv.recv() => match msg {
+            written = write.write(&pending[..chunk_len]), if chunk_len > 0 => {
+                let written = written?;
+                if written == 0 {
+                    return Err(io::ErrorKind::WriteZero.into());
+                }
+                pending.advance(written);
+            },
+            msg = recv.recv(), if pending.len() < MAX_PENDING_SIZE => match msg {
                 Some(FromDelivery::Message(msg)) => {
-                    write.write_all(&msg).await?;
-                    write.write_all(&[13, 10]).await?;
+                    queue_message(&mut codec, &mut pending, &paint(color, &msg))?;
                 },
                 Some(FromDelivery::QR(url)) => {
-                    let qr = print_qr_code(&url).unwrap();
+                    let qr = render_qr(&url, columns);
                     println!("[{}] Receving QR which encoded url: {}", CONTEXT, url);
-                    write.write_all(&qr.into_bytes()).await?;
-                    write.write_all(&[13, 10]).await?;
+                    queue_message(&mut codec, &mut pending, qr.as_bytes())?;
+                },
+                Some(FromDelivery::Prompt) => {
+                    // Not a line, the client types right after it
+                    pending.extend_from_slice(PROMPT);
                 },
                 None => {
                     break;
@@ -314,23 +1291,554 @@ async fn tcp_write(
             },
             msg = from_tcp_read.recv() => match msg {
                 Some(InternalMsg::GotAreYouThere) => {
-                    write.write_all(b"Yes.\r\n").await?;
+                    codec.encode(Item::Line(b"Yes.".to_vec()), &mut pending)?;
                 },
                 Some(InternalMsg::SendDont(i)) => {
-                    write.write_all(&[0xff, 254, i]).await?;
+                    codec.encode(Item::Dont(i), &mut pending)?;
                 },
                 Some(InternalMsg::SendWont(i)) => {
-                    write.write_all(&[0xff, 252, i]).await?;
+                    codec.encode(Item::Wont(i), &mut pending)?;
                 },
                 Some(InternalMsg::SendDo(i)) => {
-                    write.write_all(&[0xff, 253, i]).await?;
+                    codec.encode(Item::Do(i), &mut pending)?;
+                },
+                Some(InternalMsg::SendWill(i)) => {
+                    codec.encode(Item::Will(i), &mut pending)?;
+                },
+                Some(InternalMsg::SendSubnegotiation(i, data)) => {
+                    codec.encode(Item::Subnegotiation(i, data), &mut pending)?;
+                },
+                Some(InternalMsg::TerminalType(terminal_type)) => {
+                    color = color_mode.enabled(Some(&terminal_type));
+                },
+                Some(InternalMsg::WindowSize(width, _)) => {
+                    columns = Some(width);
+                },
+                Some(InternalMsg::Reply(msg)) => {
+                    queue_message(&mut codec, &mut pending, &paint(color, &msg))?;
                 },
                 None => {
                     break;
                 },
             },
+            _ = shutdown.notified() => {
+                queue_message(&mut codec, &mut pending, GOODBYE)?;
+                break;
+            },
         };
     }
 
+    // Flush whatever is left before closing the connection.
+    write.write_all(&pending).await?;
+
     Ok(())
 }
+
+// Width and height of a NAWS subnegotiation, two big-endian 16-bit values
+fn parse_window_size(data: &[u8]) -> Option<(u16, u16)> {
+    let [width_high, width_low, height_high, height_low] = data else {
+        return None;
+    };
+
+    Some((
+        u16::from_be_bytes([*width_high, *width_low]),
+        u16::from_be_bytes([*height_high, *height_low]),
+    ))
+}
+
+// Columns taken by the quiet zone around a full QR code
+const QR_QUIET_ZONE: usize = 8;
+
+// How a QR code is drawn on the client's terminal
+#[derive(Debug, PartialEq)]
+enum QrFit {
+    Full,
+    // Without the quiet zone
+    Compact,
+    // Not even the modules fit, the URL is sent as text
+    TooNarrow,
+}
+
+// Pick the QR rendering for a terminal of the given width, unknown widths
+// (no NAWS, or a width of 0) get the full code
+fn qr_fit(columns: Option<u16>, modules: usize) -> QrFit {
+    let Some(columns) = columns.filter(|columns| *columns > 0).map(usize::from) else {
+        return QrFit::Full;
+    };
+    if columns >= modules + QR_QUIET_ZONE {
+        QrFit::Full
+    } else if columns >= modules {
+        QrFit::Compact
+    } else {
+        QrFit::TooNarrow
+    }
+}
+
+fn render_qr(url: &str, columns: Option<u16>) -> String {
+    let modules = qr_code_modules(url).unwrap();
+    match qr_fit(columns, modules) {
+        QrFit::Full => print_qr_code(url).unwrap(),
+        QrFit::Compact => print_compact_qr_code(url).unwrap(),
+        QrFit::TooNarrow => format!(
+            "Terminal too narrow for the QR code ({} columns needed), open {}",
+            modules, url
+        ),
+    }
+}
+
+// Append a message and its line ending to the pending output, truncating
+// messages that exceed `MAX_MESSAGE_SIZE`.
+fn queue_message(codec: &mut TelnetCodec, pending: &mut BytesMut, msg: &[u8]) -> io::Result<()> {
+    if msg.len() > MAX_MESSAGE_SIZE {
+        codec.encode(Item::Line(msg[..MAX_MESSAGE_SIZE].to_vec()), pending)?;
+        let notice = format!("[Truncated {} bytes]", msg.len() - MAX_MESSAGE_SIZE);
+        codec.encode(Item::Line(notice.into_bytes()), pending)
+    } else {
+        codec.encode(Item::Line(msg.to_vec()), pending)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::io::{duplex, AsyncReadExt};
+    use tokio_util::codec::Decoder;
+
+    use super::*;
+    use crate::main_loop::test_server;
+    use did::fixtures::{deterministic_keypair, FIXTURE_SEED};
+    use ed25519_dalek::Signer;
+
+    #[tokio::test]
+    async fn test_outbox_capacity_is_applied_to_spawned_clients() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let _peer = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (tcp, ip) = listener.accept().await.unwrap();
+        let (server, mut server_recv) = test_server();
+        spawn_client(ClientInfo {
+            id: server.next_id(),
+            ip,
+            handle: server,
+            tcp,
+            send_policy: SendPolicy::DropNewest,
+            outbox_capacity: 1,
+            color_mode: ColorMode::Never,
+            permit: None,
+        });
+        let Some(ToDelivery::NewClient(mut handle)) = server_recv.recv().await else {
+            panic!("Expected the client to register");
+        };
+
+        // Nothing yields in between, the actor can't take the first message
+        handle
+            .send(FromDelivery::Message(b"first".to_vec()))
+            .unwrap();
+        let err = handle
+            .send(FromDelivery::Message(b"second".to_vec()))
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Can't keep up or dead");
+    }
+
+    #[tokio::test]
+    async fn test_tcp_write_makes_progress_with_slow_client() {
+        // A tiny duplex buffer behaves like a client that reads slowly.
+        let (writer, mut reader) = duplex(16);
+        let (send, recv) = outbox(OUTBOX_CAPACITY, SendPolicy::default());
+        let (to_tcp_write, from_tcp_read) = unbounded_channel();
+        let write = tokio::spawn(tcp_write(
+            writer,
+            recv,
+            from_tcp_read,
+            Arc::new(Notify::new()),
+            ColorMode::Never,
+        ));
+
+        let document = vec![b'a'; 10_000];
+        send.try_send(FromDelivery::Message(document.clone()))
+            .unwrap();
+        send.try_send(FromDelivery::Message(b"after".to_vec()))
+            .unwrap();
+
+        // Nobody is reading yet, but the loop should still have taken both
+        // messages off the channel and keep answering the client.
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(send.is_empty());
+        to_tcp_write.send(InternalMsg::GotAreYouThere).unwrap();
+
+        // Closing the internal channel makes the loop flush and stop.
+        drop(to_tcp_write);
+
+        let mut output = Vec::new();
+        reader.read_to_end(&mut output).await.unwrap();
+        write.await.unwrap().unwrap();
+        drop(send);
+
+        let mut expected = document;
+        expected.extend_from_slice(b"\r\nafter\r\nYes.\r\n");
+        assert_eq!(output, expected);
+    }
+
+    // Output of tcp_write for a verification result, on a client that told
+    // its terminal type
+    async fn write_result(color_mode: ColorMode) -> String {
+        let (writer, mut reader) = duplex(1024);
+        let (send, recv) = outbox(OUTBOX_CAPACITY, SendPolicy::default());
+        let (to_tcp_write, from_tcp_read) = unbounded_channel();
+        let write = tokio::spawn(tcp_write(
+            writer,
+            recv,
+            from_tcp_read,
+            Arc::new(Notify::new()),
+            color_mode,
+        ));
+
+        to_tcp_write
+            .send(InternalMsg::TerminalType("xterm-256color".to_string()))
+            .unwrap();
+        to_tcp_write
+            .send(InternalMsg::Reply(b"Signature is valid".to_vec()))
+            .unwrap();
+        drop(to_tcp_write);
+        let mut output = Vec::new();
+        reader.read_to_end(&mut output).await.unwrap();
+        write.await.unwrap().unwrap();
+        drop(send);
+
+        String::from_utf8(output).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_tcp_write_colors_results_when_enabled() {
+        let plain = write_result(ColorMode::Never).await;
+        assert_eq!(plain, "Signature is valid\r\n");
+        assert!(!plain.contains('\x1b'));
+
+        let colored = write_result(ColorMode::Auto).await;
+        assert!(colored.contains("\x1b[32mvalid"), "{:?}", colored);
+    }
+
+    #[test]
+    fn test_queue_message_truncates_large_messages() {
+        let mut pending = BytesMut::new();
+        queue_message(
+            &mut TelnetCodec::new(),
+            &mut pending,
+            &vec![b'a'; MAX_MESSAGE_SIZE + 10],
+        )
+        .unwrap();
+
+        assert_eq!(pending.len(), MAX_MESSAGE_SIZE + 24);
+        assert!(pending.ends_with(b"\r\n[Truncated 10 bytes]\r\n"));
+    }
+
+    #[test]
+    fn test_describe_negotiated_options() {
+        let mut options = OptionState::default();
+        assert_eq!(options.describe(), "No telnet options negotiated");
+
+        options.offer_will(SUPPRESS_GO_AHEAD);
+        options.negotiate(&Item::Do(SUPPRESS_GO_AHEAD));
+        options.negotiate(&Item::Will(1));
+        assert_eq!(
+            options.describe(),
+            "option 1: WILL disabled, DO refused\r\n\
+             option 3 (suppress-go-ahead): WILL enabled, DO disabled"
+        );
+    }
+
+    #[test]
+    fn test_switch_mode_uses_linemode_once_enabled() {
+        let mut options = OptionState::default();
+        options.offer_will(SUPPRESS_GO_AHEAD);
+        options.negotiate(&Item::Do(SUPPRESS_GO_AHEAD));
+
+        // Until the client supports LINEMODE, SUPPRESS-GO-AHEAD is toggled
+        assert_eq!(
+            options.switch_mode(false),
+            vec![
+                InternalMsg::SendWont(SUPPRESS_GO_AHEAD),
+                InternalMsg::SendDo(LINEMODE)
+            ]
+        );
+        assert_eq!(options.negotiate(&Item::Will(LINEMODE)), None);
+        assert_eq!(
+            options.request_linemode(),
+            Some(InternalMsg::SendSubnegotiation(
+                LINEMODE,
+                vec![LINEMODE_MODE, MODE_EDIT]
+            ))
+        );
+        assert_eq!(options.request_linemode(), None);
+
+        assert_eq!(
+            options.switch_mode(true),
+            vec![InternalMsg::SendSubnegotiation(
+                LINEMODE,
+                vec![LINEMODE_MODE, 0]
+            )]
+        );
+        assert!(options.describe().ends_with("mode: character at a time"));
+    }
+
+    #[test]
+    fn test_naws_window_size() {
+        assert_eq!(parse_window_size(&[0, 80, 0, 24]), Some((80, 24)));
+        assert_eq!(parse_window_size(&[1, 0, 0, 50]), Some((256, 50)));
+        assert_eq!(parse_window_size(&[0, 80, 0]), None);
+
+        let mut options = OptionState::default();
+        assert!(options.offer_do(NAWS).is_some());
+        assert_eq!(options.negotiate(&Item::Will(NAWS)), None);
+        assert_eq!(
+            options.subnegotiate(NAWS, &[0, 100, 0, 40]),
+            Some(InternalMsg::WindowSize(100, 40))
+        );
+        assert!(options.describe().ends_with("window: 100x40"));
+    }
+
+    #[test]
+    fn test_small_windows_shrink_the_qr_code() {
+        assert_eq!(qr_fit(None, 25), QrFit::Full);
+        assert_eq!(qr_fit(Some(0), 25), QrFit::Full);
+        assert_eq!(qr_fit(Some(80), 25), QrFit::Full);
+        assert_eq!(qr_fit(Some(30), 25), QrFit::Compact);
+        assert_eq!(qr_fit(Some(20), 25), QrFit::TooNarrow);
+
+        let url = "http://192.168.1.2:8080/dids/did:example:alice";
+        assert!(render_qr(url, Some(20)).contains(url));
+        assert!(!render_qr(url, None).contains(url));
+    }
+
+    #[test]
+    fn test_charset_subnegotiation_agrees_on_utf8() {
+        let mut options = OptionState::default();
+
+        // The server's request goes out once the client agrees with DO
+        assert_eq!(options.request_charset(), None);
+        assert!(options.offer_will(CHARSET).is_some());
+        assert_eq!(options.negotiate(&Item::Do(CHARSET)), None);
+        assert_eq!(
+            options.request_charset(),
+            Some(InternalMsg::SendSubnegotiation(
+                CHARSET,
+                b"\x01;UTF-8".to_vec()
+            ))
+        );
+        assert_eq!(options.request_charset(), None);
+
+        // The client asks for its own list, UTF-8 is picked from it
+        assert_eq!(
+            options.subnegotiate(CHARSET, b"\x01 ISO-8859-1 utf-8"),
+            Some(InternalMsg::SendSubnegotiation(
+                CHARSET,
+                b"\x02UTF-8".to_vec()
+            ))
+        );
+        assert_eq!(options.charset.as_deref(), Some("UTF-8"));
+        assert!(options.describe().ends_with("charset: UTF-8"));
+
+        // A list without UTF-8 is rejected, keeping what was agreed before
+        assert_eq!(
+            options.subnegotiate(CHARSET, b"\x01;ISO-8859-1"),
+            Some(InternalMsg::SendSubnegotiation(CHARSET, vec![3]))
+        );
+        assert_eq!(options.charset.as_deref(), Some("UTF-8"));
+
+        // The client's answers to the server's request
+        assert_eq!(options.subnegotiate(CHARSET, b"\x03"), None);
+        assert_eq!(options.charset, None);
+        assert_eq!(options.subnegotiate(CHARSET, b"\x02UTF-8"), None);
+        assert_eq!(options.charset.as_deref(), Some("UTF-8"));
+
+        // Malformed or unrelated subnegotiations are ignored
+        assert_eq!(options.subnegotiate(CHARSET, b""), None);
+        assert_eq!(options.subnegotiate(CHARSET, b"\x01"), None);
+        assert_eq!(options.subnegotiate(31, b"\x00\x50"), None);
+    }
+
+    #[test]
+    fn test_terminal_type_is_requested_and_stored() {
+        let mut options = OptionState::default();
+
+        // The client announces it, the server agrees and asks for the type
+        assert_eq!(
+            options.negotiate(&Item::Will(TERMINAL_TYPE)),
+            Some(InternalMsg::SendDo(TERMINAL_TYPE))
+        );
+        assert_eq!(
+            options.request_terminal_type(),
+            Some(InternalMsg::SendSubnegotiation(TERMINAL_TYPE, vec![1]))
+        );
+        assert_eq!(options.request_terminal_type(), None);
+
+        assert_eq!(
+            options.subnegotiate(TERMINAL_TYPE, b"\x00XTERM-256COLOR"),
+            Some(InternalMsg::TerminalType("xterm-256color".to_string()))
+        );
+        assert_eq!(options.terminal_type.as_deref(), Some("xterm-256color"));
+        assert!(options
+            .describe()
+            .ends_with("terminal type: xterm-256color"));
+
+        // The server has no terminal type of its own to send
+        assert_eq!(
+            options.negotiate(&Item::Do(TERMINAL_TYPE)),
+            Some(InternalMsg::SendWont(TERMINAL_TYPE))
+        );
+    }
+
+    #[test]
+    fn test_suppress_go_ahead_server_offer_does_not_loop() {
+        let mut options = OptionState::default();
+
+        // Server offers WILL, the client agrees with DO.
+        assert_eq!(
+            options.offer_will(SUPPRESS_GO_AHEAD),
+            Some(InternalMsg::SendWill(SUPPRESS_GO_AHEAD))
+        );
+        assert_eq!(options.negotiate(&Item::Do(SUPPRESS_GO_AHEAD)), None);
+
+        // Already agreed, so neither a new offer nor a repeated DO is answered.
+        assert_eq!(options.offer_will(SUPPRESS_GO_AHEAD), None);
+        assert_eq!(options.negotiate(&Item::Do(SUPPRESS_GO_AHEAD)), None);
+
+        // Client turns it off, which is acknowledged once.
+        assert_eq!(
+            options.negotiate(&Item::Dont(SUPPRESS_GO_AHEAD)),
+            Some(InternalMsg::SendWont(SUPPRESS_GO_AHEAD))
+        );
+        assert_eq!(options.negotiate(&Item::Dont(SUPPRESS_GO_AHEAD)), None);
+    }
+
+    #[test]
+    fn test_suppress_go_ahead_client_offer_does_not_loop() {
+        let mut options = OptionState::default();
+
+        assert_eq!(
+            options.negotiate(&Item::Will(SUPPRESS_GO_AHEAD)),
+            Some(InternalMsg::SendDo(SUPPRESS_GO_AHEAD))
+        );
+        assert_eq!(options.negotiate(&Item::Will(SUPPRESS_GO_AHEAD)), None);
+
+        // Client asks the server to suppress too, without a prior offer.
+        assert_eq!(
+            options.negotiate(&Item::Do(SUPPRESS_GO_AHEAD)),
+            Some(InternalMsg::SendWill(SUPPRESS_GO_AHEAD))
+        );
+        assert_eq!(options.negotiate(&Item::Do(SUPPRESS_GO_AHEAD)), None);
+
+        assert_eq!(
+            options.negotiate(&Item::Wont(SUPPRESS_GO_AHEAD)),
+            Some(InternalMsg::SendDont(SUPPRESS_GO_AHEAD))
+        );
+        assert_eq!(options.negotiate(&Item::Wont(SUPPRESS_GO_AHEAD)), None);
+    }
+
+    #[test]
+    fn test_suppress_go_ahead_refused_offer() {
+        let mut options = OptionState::default();
+
+        assert!(options.offer_will(SUPPRESS_GO_AHEAD).is_some());
+        assert_eq!(options.negotiate(&Item::Dont(SUPPRESS_GO_AHEAD)), None);
+        assert_eq!(options.local[&SUPPRESS_GO_AHEAD], Side::Disabled);
+    }
+
+    #[test]
+    fn test_repeated_requests_for_unsupported_option_are_answered_once() {
+        let mut options = OptionState::default();
+        let echo = 1;
+
+        assert_eq!(
+            options.negotiate(&Item::Will(echo)),
+            Some(InternalMsg::SendDont(echo))
+        );
+        assert_eq!(options.negotiate(&Item::Will(echo)), None);
+        assert_eq!(options.negotiate(&Item::Wont(echo)), None);
+
+        assert_eq!(
+            options.negotiate(&Item::Do(echo)),
+            Some(InternalMsg::SendWont(echo))
+        );
+        assert_eq!(options.negotiate(&Item::Do(echo)), None);
+    }
+
+    #[test]
+    fn test_missing_argument_returns_usage() {
+        let mut codec = TelnetCodec::new();
+        let mut src = BytesMut::from(&b"c#sdid\nc#vdid  \nc#ar\t\nc#sdiddid:example:123\n"[..]);
+        let mut next = || codec.decode(&mut src).unwrap().unwrap();
+
+        assert_eq!(
+            missing_argument(&next()).unwrap(),
+            "missing DID argument, usage: c#sdid<did>"
+        );
+        assert_eq!(
+            missing_argument(&next()).unwrap(),
+            "missing DID argument, usage: c#vdid<did>"
+        );
+        assert_eq!(
+            missing_argument(&next()).unwrap(),
+            "missing role argument, usage: c#ar<holder|issuer|verifier>"
+        );
+        assert_eq!(missing_argument(&next()), None);
+    }
+
+    #[test]
+    fn test_hex_describes_received_bytes() {
+        let mut codec = TelnetCodec::new();
+        // "é" followed by an escaped IAC byte
+        let mut src = BytesMut::from(&b"c#hexa \xc3\xa9\xff\xff\r\n"[..]);
+
+        match codec.decode(&mut src).unwrap() {
+            Some(Item::Hex(text)) => assert_eq!(
+                describe_bytes(&text),
+                "hex: 61 20 c3 a9 ff\r\nutf8: a \u{e9}\u{fffd}"
+            ),
+            other => panic!("Expected c#hex, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_verify_signature_command() {
+        let signer = deterministic_keypair(FIXTURE_SEED);
+        let key = encode_public_key_to_multibase(&signer.verifying_key()).unwrap();
+        let signature = signer.sign(b"hello world").to_bytes().to_base58();
+
+        let args = format!("{} {} hello world", key, signature);
+        assert_eq!(verify_signature(args.as_bytes()), "Signature is valid");
+        let args = format!("{} {} hello there", key, signature);
+        assert_eq!(verify_signature(args.as_bytes()), "Signature is invalid");
+        assert!(verify_signature(key.as_bytes()).starts_with("usage"));
+    }
+
+    #[test]
+    fn test_validate_document_reports_every_problem() {
+        let (mut document, _) = create_did_document("example");
+        assert_eq!(
+            validate_document(document.to_json().unwrap().as_bytes()),
+            format!("Document {} is valid", document.id)
+        );
+
+        document.context.reverse();
+        document
+            .authentication
+            .push(format!("{}#missing", document.id));
+        let report = validate_document(document.to_json().unwrap().as_bytes());
+        assert_eq!(
+            report,
+            format!(
+                "Document {id} has 2 problem(s):\r\n\
+                 - The first @context must be {}, got {}\r\n\
+                 - Unknown authentication method: {id}#missing",
+                did::DID_CONTEXT,
+                document.context[0],
+                id = document.id
+            )
+        );
+
+        assert!(validate_document(b"{").starts_with("Invalid document"));
+    }
+}
@@ -0,0 +1,147 @@
+// ANSI colors for terminals that can show them.
+//
+// Only the presentation of replies changes: verification results are
+// highlighted and the keys of JSON documents stand out. Terminals that can't
+// show colors, or clients that never tell their terminal type, get the plain
+// text.
+
+const GREEN: &str = "\x1b[32m";
+const RED: &str = "\x1b[31m";
+const CYAN: &str = "\x1b[36m";
+const RESET: &str = "\x1b[0m";
+
+// Terminal types known to understand ANSI colors, by prefix
+const COLOR_TERMINALS: [&str; 7] = ["xterm", "screen", "tmux", "rxvt", "linux", "ansi", "vt220"];
+
+// Whether replies are colored
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum ColorMode {
+    // Colored when the client's terminal type supports colors
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl TryFrom<String> for ColorMode {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "auto" => Ok(ColorMode::Auto),
+            "always" => Ok(ColorMode::Always),
+            "never" => Ok(ColorMode::Never),
+            _ => Err(format!(
+                "Invalid color mode {}, expected auto, always or never",
+                value
+            )),
+        }
+    }
+}
+
+impl ColorMode {
+    // Whether to color the replies to a client with the given terminal type,
+    // `None` until the client tells it
+    pub fn enabled(&self, terminal_type: Option<&str>) -> bool {
+        match self {
+            ColorMode::Always => true,
+            ColorMode::Never => false,
+            ColorMode::Auto => terminal_type.is_some_and(supports_color),
+        }
+    }
+}
+
+fn supports_color(terminal_type: &str) -> bool {
+    let terminal_type = terminal_type.to_ascii_lowercase();
+    terminal_type.contains("color")
+        || COLOR_TERMINALS
+            .iter()
+            .any(|prefix| terminal_type.starts_with(prefix))
+}
+
+// Color a reply: "valid" in green, "invalid" and its variants in red, and
+// the keys of pretty-printed JSON in cyan. Replies that aren't UTF-8 are
+// left alone.
+pub fn colorize(msg: &[u8]) -> Vec<u8> {
+    let Ok(msg) = std::str::from_utf8(msg) else {
+        return msg.to_vec();
+    };
+
+    msg.split('\n')
+        .map(colorize_line)
+        .collect::<Vec<_>>()
+        .join("\n")
+        .into_bytes()
+}
+
+fn colorize_line(line: &str) -> String {
+    let trimmed = line.trim_start();
+    if trimmed.starts_with('"') {
+        if let Some(end) = trimmed.find("\":") {
+            let indent = &line[..line.len() - trimmed.len()];
+            let (key, rest) = trimmed.split_at(end + 1);
+            return format!("{}{}{}{}{}", indent, CYAN, key, RESET, rest);
+        }
+    }
+
+    let mut colored = String::with_capacity(line.len());
+    let mut word_start = 0;
+    for (i, c) in line.char_indices() {
+        if !c.is_alphanumeric() {
+            push_word(&mut colored, &line[word_start..i]);
+            colored.push(c);
+            word_start = i + c.len_utf8();
+        }
+    }
+    push_word(&mut colored, &line[word_start..]);
+
+    colored
+}
+
+fn push_word(colored: &mut String, word: &str) {
+    let lower = word.to_lowercase();
+    let color = if lower == "valid" {
+        GREEN
+    } else if lower.starts_with("invalid") {
+        RED
+    } else {
+        colored.push_str(word);
+        return;
+    };
+    colored.push_str(color);
+    colored.push_str(word);
+    colored.push_str(RESET);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_color_mode_follows_the_terminal_type() {
+        assert!(ColorMode::Auto.enabled(Some("xterm-256color")));
+        assert!(!ColorMode::Auto.enabled(Some("dumb")));
+        assert!(!ColorMode::Auto.enabled(None));
+        assert!(ColorMode::Always.enabled(None));
+        assert!(!ColorMode::Never.enabled(Some("xterm-256color")));
+    }
+
+    #[test]
+    fn test_colorize_highlights_results_and_keys() {
+        let colored = colorize(b"Holder did:example:alice: Valid\r\nPresentation is invalid");
+        assert_eq!(
+            String::from_utf8(colored).unwrap(),
+            "Holder did:example:alice: \x1b[32mValid\x1b[0m\r\n\
+             Presentation is \x1b[31minvalid\x1b[0m"
+        );
+
+        let colored = colorize(b"{\n  \"id\": \"did:example:alice\"\n}");
+        assert_eq!(
+            String::from_utf8(colored).unwrap(),
+            "{\n  \x1b[36m\"id\"\x1b[0m: \"did:example:alice\"\n}"
+        );
+
+        // Words merely containing "valid" are left alone
+        assert_eq!(colorize(b"validated"), b"validated");
+    }
+}
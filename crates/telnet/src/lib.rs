@@ -7,7 +7,10 @@
 // Client will be spawned thread
 pub mod accept;
 pub mod client;
+pub mod color;
 pub mod main_loop;
+pub mod outbox;
+pub mod server_log;
 pub mod telnet;
 pub mod util;
 
@@ -139,6 +142,20 @@ pub struct ClientId(pub usize);
 
 impl Display for ClientId {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Client({})", self.0)
+        write!(f, "client#{}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_id_display() {
+        assert_eq!(ClientId(3).to_string(), "client#3");
+        assert_eq!(
+            format!("{} created did:example:alice", ClientId(12)),
+            "client#12 created did:example:alice"
+        );
     }
 }
@@ -1,19 +1,96 @@
-use telnet::{accept::start_accept, main_loop::spawn_main_loop};
+use std::time::Duration;
+
+use did::MethodRouter;
+use telnet::{
+    accept::{start_accept, AcceptLimits},
+    main_loop::{spawn_main_loop_for, MainLoopConfig, ToDelivery},
+    util::{decode_hex, default_gateway},
+};
 
 #[tokio::main]
 async fn main() {
-    let (handle, join) = spawn_main_loop();
+    // Look the gateway up before clients ask for QR codes
+    let gateway = tokio::task::spawn_blocking(default_gateway);
+
+    let (config, limits) = config_from_args();
+    let did_method = config.did_method.clone();
+    let (handle, join) = match spawn_main_loop_for(MethodRouter::new(), config) {
+        Ok(spawned) => spawned,
+        Err(err) => {
+            eprintln!("[Server] {}", err);
+            std::process::exit(1);
+        }
+    };
     let port = 3456;
 
+    let mut shutdown = handle.clone();
     tokio::spawn(async move {
         let bind = ([0, 0, 0, 0], port.clone()).into();
-        start_accept(bind, handle).await;
+        start_accept(bind, handle, limits).await;
+    });
+    // Clients get a goodbye instead of a reset on Ctrl-C
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            println!("[Server] Shutting down");
+            shutdown.send(ToDelivery::Shutdown).await;
+        }
     });
 
     println!("[Server] Starting on port {}", port);
+    println!("[Server] Creating DIDs as did:{}:...", did_method);
     println!("[Server] Use:");
     println!("[Server]      telnet 127.0.0.1 {}", port);
     println!("[Server] to connect.");
+    if let Ok(Some(gateway)) = gateway.await {
+        println!("[Server] Default gateway: {}", gateway);
+    }
 
     join.await.unwrap();
 }
+
+// Settings from the command line:
+//   --did-method <method>              method of the DIDs created for clients
+//   --presentation-timeout <seconds>   how long holders have to answer
+//   --contexts <strict|permissive>     whether unknown @context URLs are accepted
+//   --issuer-seed <hex>                same issuer DID and key on every run
+//   --outbox-capacity <messages>       messages queued per client before
+//                                      dropping some for slow clients
+//   --color <auto|always|never>        whether replies are colored, auto
+//                                      going by the client's terminal type
+fn config_from_args() -> (MainLoopConfig, AcceptLimits) {
+    let mut config = MainLoopConfig::default();
+    let mut limits = AcceptLimits::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match (arg.as_str(), args.next()) {
+            ("--did-method", Some(method)) => config.did_method = method,
+            ("--presentation-timeout", Some(seconds)) => match seconds.parse() {
+                Ok(seconds) => config.presentation_timeout = Duration::from_secs(seconds),
+                Err(_) => eprintln!("[Server] Ignoring invalid timeout {}", seconds),
+            },
+            ("--contexts", Some(policy)) => match policy.try_into() {
+                Ok(policy) => config.context_policy = policy,
+                Err(err) => eprintln!("[Server] Ignoring {}", err),
+            },
+            ("--issuer-seed", Some(hex)) => match decode_hex(&hex) {
+                Some(seed) => config.issuer_seed = Some(seed),
+                None => eprintln!("[Server] Ignoring invalid issuer seed {}", hex),
+            },
+            ("--broadcast", Some(scope)) => match scope.try_into() {
+                Ok(scope) => config.creation_broadcast = scope,
+                Err(err) => eprintln!("[Server] Ignoring {}", err),
+            },
+            ("--outbox-capacity", Some(capacity)) => match capacity.parse() {
+                Ok(capacity) if capacity > 0 => limits.outbox_capacity = capacity,
+                _ => eprintln!("[Server] Ignoring invalid outbox capacity {}", capacity),
+            },
+            ("--color", Some(mode)) => match mode.try_into() {
+                Ok(mode) => limits.color_mode = mode,
+                Err(err) => eprintln!("[Server] Ignoring {}", err),
+            },
+            (arg, _) => eprintln!("[Server] Ignoring unknown argument {}", arg),
+        }
+    }
+
+    (config, limits)
+}
@@ -1,33 +1,94 @@
-use did::{DidDocument, DidStorage};
+use did::{
+    encode_public_key_to_multibase, generate_document, generate_nonce, validate_method,
+    ContextPolicy, CreateRequest, DidDocument, DidResolver, DidStorage, IssuanceParams,
+    MethodRouter, SharedStorage, VCCreator, VerifiableCredential, VerifiablePresentation,
+    VerificationOptions, CREDENTIAL_REGISTRY_TYPE,
+};
+use futures::future::join_all;
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     io,
     sync::{
         atomic::{AtomicUsize, Ordering},
         Arc,
     },
+    time::Duration,
+};
+use tokio::select;
+use tokio::sync::{
+    mpsc::{channel, Receiver, Sender},
+    oneshot,
 };
-use tokio::sync::mpsc::{channel, Receiver, Sender};
 use tokio::task::JoinHandle;
+use tokio::time::{sleep_until, Instant};
 
 use crate::{
-    client::{ClientHandle, ClientRole, FromDelivery},
-    util::get_ipv4_info,
+    client::{ClientHandle, ClientRole, FromDelivery, DM_USAGE},
+    server_log::{log_line, ServerLog},
+    util::{build_server_url, server_ip},
     ClientId,
 };
 
 static CONTEXT: &str = "Verifiable Registry";
+// The DID the server issues credentials under
+static ISSUER_DID: &str = "did:example:issuer";
+// The method of the DIDs created for clients, unless configured otherwise
+pub static DEFAULT_DID_METHOD: &str = "example";
+// Answer to commands needing a role, sent by a client without one
+static ASSIGN_ROLE_FIRST: &str = "assign a role first (c#ar<holder|issuer|verifier>)";
+// How long a client gets to say goodbye when the server stops
+const CLIENT_SHUTDOWN_WAIT: Duration = Duration::from_secs(2);
+// Most iterations c#benchverify runs, so it can't stall the main loop
+pub const MAX_BENCH_ITERATIONS: usize = 1000;
 
 // Define the messages the actor can handle
 pub enum ToDelivery {
     NewClient(ClientHandle),
     NewRole(ClientId, ClientRole),
     MyInfo(ClientId),
-    ShowVP(ClientId),
+    ShowVP(ClientId, Vec<u8>),
+    RequestPresentation(ClientId, Vec<u8>),
+    // The holder's client signs the request it gets back and answers with
+    // ShowVP, its keys never reach the main loop
+    Present(ClientId, Vec<u8>, oneshot::Sender<PresentationRequest>),
     Message(ClientId, Vec<u8>),
     ShowDocument(ClientId, Vec<u8>),
+    Resolve(ClientId, Vec<u8>),
+    QR(ClientId, Vec<u8>),
     VerifyDID(ClientId, Vec<u8>),
     DidDocument(ClientId, DidDocument),
+    // The owner's client signs the stored document it gets back and answers
+    // with Resigned
+    Resign(ClientId, Vec<u8>, oneshot::Sender<DidDocument>),
+    Resigned(ClientId, DidDocument),
+    Subscribe(ClientId),
+    Unsubscribe(ClientId),
+    IssueVC(ClientId, Vec<u8>),
+    RenewVC(ClientId, Vec<u8>),
+    RotateIssuer(ClientId),
+    Snapshot(ClientId),
+    Import(ClientId, Vec<u8>),
+    Register(ClientId, Vec<u8>),
+    Methods(ClientId),
+    Wallet(ClientId),
+    Forget(ClientId, Vec<u8>),
+    Challenge(ClientId, Vec<u8>),
+    BenchVerify(ClientId, Vec<u8>),
+    HashDocument(ClientId, Vec<u8>),
+    Expand(ClientId, Vec<u8>),
+    Relationships(ClientId, Vec<u8>),
+    Respond(ClientId, Vec<u8>),
+    DirectMessage(ClientId, Vec<u8>),
+    ServerLog(ClientId),
+    Stats(ClientId),
+    Ephemeral(ClientId),
+    // Terminal width and height a client told with NAWS
+    WindowSize(ClientId, u16, u16),
+    UpdateDocument(ClientId, Vec<u8>),
+    ClientLeft(ClientId),
+    Interrupt(ClientId),
+    // Say goodbye to every client and stop the main loop
+    Shutdown,
     FatalError(io::Error),
 }
 
@@ -37,6 +98,7 @@ pub enum ToDelivery {
 pub struct ServerHandle {
     chan: Sender<ToDelivery>,
     next_id: Arc<AtomicUsize>,
+    did_method: Arc<str>,
 }
 
 impl ServerHandle {
@@ -50,23 +112,165 @@ impl ServerHandle {
         let id = self.next_id.fetch_add(1, Ordering::Relaxed);
         ClientId(id)
     }
+
+    // Method of the DIDs the server creates for clients
+    pub fn did_method(&self) -> &str {
+        &self.did_method
+    }
+}
+
+// Who is told when a client creates a DID
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum BroadcastScope {
+    None,
+    #[default]
+    Creator,
+    // The creator and the clients with the given role
+    Role(ClientRole),
+    All,
+}
+
+impl TryFrom<String> for BroadcastScope {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "none" => Ok(BroadcastScope::None),
+            "creator" => Ok(BroadcastScope::Creator),
+            "all" => Ok(BroadcastScope::All),
+            _ => match value.strip_prefix("role:") {
+                Some(role) => ClientRole::try_from(role.to_string())
+                    .map(BroadcastScope::Role)
+                    .map_err(|err| format!("{}: {}", err, role)),
+                None => Err(format!(
+                    "Invalid broadcast scope {}, expected none, creator, role:<role> or all",
+                    value
+                )),
+            },
+        }
+    }
+}
+
+// Settings of the main loop
+#[derive(Debug, Clone)]
+pub struct MainLoopConfig {
+    // Method of the DIDs created for clients
+    pub did_method: String,
+    // How long a holder has to answer a verifier's presentation request
+    pub presentation_timeout: Duration,
+    // How long the issuer's previous key is still accepted after a rotation
+    pub issuer_key_grace: Duration,
+    // Who is told about newly created DIDs
+    pub creation_broadcast: BroadcastScope,
+    // Iterations of c#benchverify when the client doesn't give a count
+    pub bench_iterations: usize,
+    // Contexts accepted in imported and registered documents
+    pub context_policy: ContextPolicy,
+    // Seed of the issuer's key and DID, a random issuer key when unset
+    pub issuer_seed: Option<Vec<u8>>,
+}
+
+impl Default for MainLoopConfig {
+    fn default() -> Self {
+        MainLoopConfig {
+            did_method: DEFAULT_DID_METHOD.to_string(),
+            presentation_timeout: Duration::from_secs(60),
+            issuer_key_grace: Duration::from_secs(7 * 24 * 60 * 60),
+            creation_broadcast: BroadcastScope::default(),
+            bench_iterations: 100,
+            context_policy: ContextPolicy::default(),
+            issuer_seed: None,
+        }
+    }
+}
+
+// A verifier waiting for a holder's presentation
+#[derive(Debug)]
+struct PendingPresentation {
+    verifier: ClientId,
+    holder: ClientId,
+    // The holder's presentation must carry it, so it can't be replayed
+    challenge: String,
+    deadline: Instant,
+}
+
+// What a holder's client signs to answer a verifier's presentation request
+#[derive(Debug)]
+pub struct PresentationRequest {
+    pub credential: VerifiableCredential,
+    pub challenge: String,
+}
+
+// A verifier waiting for a client to prove control of a DID
+#[derive(Debug)]
+struct PendingChallenge {
+    verifier: ClientId,
+    target: ClientId,
+    did: String,
+    // Random value the target signs with the DID's key
+    nonce: String,
 }
 
 #[derive(Default, Debug)]
 struct Data {
     clients: HashMap<ClientId, ClientHandle>,
+    // Clients that want to be notified about newly created DIDs
+    subscribers: HashSet<ClientId>,
+    // Credentials issued by the server, by credential id
+    credentials: HashMap<String, VerifiableCredential>,
+    // Client that registered each DID
+    owners: HashMap<String, ClientId>,
+    // Clients whose DIDs are deleted when they disconnect
+    ephemeral: HashSet<ClientId>,
+    // Presentation requests waiting for the holder's answer
+    pending_presentations: Vec<PendingPresentation>,
+    // Ids of the credentials each holder received, in order of issuance
+    wallets: HashMap<ClientId, Vec<String>>,
+    // Proof of control challenges waiting for the target's response
+    pending_challenges: Vec<PendingChallenge>,
 }
 
 pub fn spawn_main_loop() -> (ServerHandle, JoinHandle<()>) {
+    spawn_main_loop_with(MethodRouter::new())
+}
+
+// Handle of a server whose messages are read by the test instead of a main
+// loop
+#[cfg(test)]
+pub(crate) fn test_server() -> (ServerHandle, Receiver<ToDelivery>) {
+    let (send, recv) = channel(64);
+    let handle = ServerHandle {
+        chan: send,
+        next_id: Default::default(),
+        did_method: DEFAULT_DID_METHOD.into(),
+    };
+
+    (handle, recv)
+}
+
+// Spawn the main loop with resolvers for additional DID methods, the example
+// method is always served from the server's own registry
+pub fn spawn_main_loop_with(router: MethodRouter) -> (ServerHandle, JoinHandle<()>) {
+    spawn_main_loop_for(router, MainLoopConfig::default()).expect("Invalid default config")
+}
+
+// Spawn the main loop with the given settings, DIDs of the configured method
+// are served from the registry next to the example method
+pub fn spawn_main_loop_for(
+    router: MethodRouter,
+    config: MainLoopConfig,
+) -> Result<(ServerHandle, JoinHandle<()>), String> {
+    validate_method(&config.did_method).map_err(|err| err.to_string())?;
     let (send, recv) = channel(64);
 
     let handle = ServerHandle {
         chan: send,
         next_id: Default::default(),
+        did_method: config.did_method.as_str().into(),
     };
 
     let join = tokio::spawn(async move {
-        let res = main_loop(recv).await;
+        let res = main_loop(recv, router, config).await;
         match res {
             Ok(()) => {}
             Err(err) => {
@@ -75,44 +279,85 @@ pub fn spawn_main_loop() -> (ServerHandle, JoinHandle<()>) {
         }
     });
 
-    (handle, join)
+    Ok((handle, join))
 }
 
-async fn main_loop(mut recv: Receiver<ToDelivery>) -> Result<(), io::Error> {
+async fn main_loop(
+    mut recv: Receiver<ToDelivery>,
+    mut router: MethodRouter,
+    config: MainLoopConfig,
+) -> Result<(), io::Error> {
     let mut data = Data::default();
-    let mut did_storage = DidStorage::new();
-
-    while let Some(msg) = recv.recv().await {
-        match msg {
-            ToDelivery::NewClient(handle) => {
-                println!("[{}] received new client", CONTEXT);
-                data.clients.insert(handle.id, handle);
+    let mut server_log = ServerLog::default();
+    let storage = SharedStorage::default();
+    storage
+        .write()
+        .expect("Registry lock poisoned")
+        .set_context_policy(config.context_policy.clone());
+    router.register("example", Box::new(storage.clone()));
+    router.register(&config.did_method, Box::new(storage.clone()));
 
-                let msg_to_client = "Welcome!";
-                let msg = FromDelivery::Message(msg_to_client.as_bytes().to_vec());
+    // Register the server's issuer so its credentials can be verified
+    // A seeded issuer keeps its DID and key across runs
+    let mut issuer = match &config.issuer_seed {
+        Some(seed) => VCCreator::from_seed("example", seed),
+        None => VCCreator::new(ISSUER_DID),
+    }
+    .expect("Invalid issuer DID");
+    let issuer_did = issuer.issuer_did.clone();
+    let issuer_key =
+        encode_public_key_to_multibase(&issuer.verifying_key()).expect("Failed to encode key");
+    let mut issuer_document =
+        generate_document(&issuer_did, Some(issuer_key)).expect("Failed to generate document");
+    issuer_document.add_assertion_method(&issuer.verification_method_id());
+    storage
+        .write()
+        .expect("Registry lock poisoned")
+        .store(issuer_did.clone(), issuer_document)
+        .expect("Failed to store issuer document");
 
-                for (id, handle) in data.clients.iter_mut() {
-                    let id = *id;
+    loop {
+        let next_deadline = data
+            .pending_presentations
+            .iter()
+            .map(|pending| pending.deadline)
+            .min();
+        let msg = match next_deadline {
+            Some(deadline) => select! {
+                msg = recv.recv() => msg,
+                _ = sleep_until(deadline) => {
+                    expire_presentation_requests(&mut data);
+                    continue;
+                }
+            },
+            None => recv.recv().await,
+        };
+        let Some(msg) = msg else {
+            break;
+        };
+        if let Some(from_id) = needs_role(&msg) {
+            if !has_any_role(&data, from_id) {
+                reply(&mut data, from_id, ASSIGN_ROLE_FIRST);
+                continue;
+            }
+        }
 
-                    // Don't send it to the client who sent it to us.
-                    if id == handle.id {
-                        match handle.send(msg) {
-                            Ok(()) => {}
-                            Err(err) => {
-                                eprintln!("[{}] Something went wrong: {}.", CONTEXT, err);
-                            }
-                        };
+        let mut did_storage = storage.write().expect("Registry lock poisoned");
+        match msg {
+            ToDelivery::NewClient(handle) => {
+                log_line!(server_log, "[{}] received new client", CONTEXT);
+                let id = handle.id;
+                data.clients.insert(id, handle);
 
-                        break;
-                    }
-                }
+                // Only the client who just joined is welcomed
+                reply(&mut data, id, "Welcome!");
             }
             ToDelivery::Message(from_id, msg) => {
                 // If we fail to send messages to any actor, we need to remove
                 // it, but we can't do so while iterating.
-                // let mut to_remove = Vec::new();
+                let mut to_remove = Vec::new();
 
-                println!("[{}] received message", CONTEXT);
+                log_line!(server_log, "[{}] received message", CONTEXT);
                 // Iterate through clients so we can send the message.
                 for (id, handle) in data.clients.iter_mut() {
                     let id = *id;
@@ -128,16 +373,69 @@ async fn main_loop(mut recv: Receiver<ToDelivery>) -> Result<(), io::Error> {
                         Ok(()) => {}
                         Err(err) => {
                             eprintln!("[{}] Something went wrong: {}.", CONTEXT, err);
+                            // Clients that merely can't keep up stay connected
+                            if handle.is_dead() {
+                                to_remove.push(id);
+                            }
                         }
                     };
                 }
+                for id in to_remove {
+                    log_line!(server_log, "[{}] Removing dead {}", CONTEXT, id);
+                    data.clients.remove(&id);
+                }
+            }
+            ToDelivery::DidDocument(from_id, document)
+                if document
+                    .get_service_by_type(CREDENTIAL_REGISTRY_TYPE)
+                    .is_some()
+                    && !has_role(&data, from_id, ClientRole::Issuer) =>
+            {
+                reply(
+                    &mut data,
+                    from_id,
+                    "Only issuers can publish a credential registry",
+                );
             }
             ToDelivery::DidDocument(from_id, document) => {
-                println!("[{}] insert document with id: {}", CONTEXT, document.id);
+                log_line!(
+                    server_log,
+                    "[{}] insert document with id: {}",
+                    CONTEXT,
+                    document.id
+                );
                 let doc_id = document.id.clone();
-                match did_storage.store(doc_id, document) {
-                    Ok(_) => println!("[{}] Insert successfully", CONTEXT),
-                    Err(_) => println!("[{}] Failed to insert", CONTEXT),
+                let actor = from_id.to_string();
+                let mut created = false;
+                if did_storage.get(&doc_id).is_some() {
+                    match did_storage.update_by(&doc_id, document, Some(&actor)) {
+                        Ok(_) => {
+                            let version = did_storage.version(&doc_id).unwrap_or_default();
+                            notify_updated(&mut data, from_id, &doc_id, version);
+                        }
+                        Err(_) => log_line!(server_log, "[{}] Failed to update", CONTEXT),
+                    }
+                } else {
+                    match did_storage.store_by(doc_id.clone(), document, Some(&actor)) {
+                        Ok(_) => {
+                            log_line!(server_log, "[{}] Insert successfully", CONTEXT);
+                            data.owners.insert(doc_id.clone(), from_id);
+                            created = true;
+                            let msg = format!("New DID created: {}", doc_id);
+                            notify_subscribers(&mut data, from_id, &msg);
+                            let msg = format!("{} created {}", from_id, doc_id);
+                            broadcast_creation(
+                                &mut data,
+                                from_id,
+                                &config.creation_broadcast,
+                                &msg,
+                            );
+                        }
+                        Err(_) => log_line!(server_log, "[{}] Failed to insert", CONTEXT),
+                    }
+                }
+                if created && config.creation_broadcast == BroadcastScope::None {
+                    continue;
                 }
                 for (id, handle) in data.clients.iter_mut() {
                     let id = *id;
@@ -157,11 +455,23 @@ async fn main_loop(mut recv: Receiver<ToDelivery>) -> Result<(), io::Error> {
                 }
             }
             ToDelivery::ShowDocument(from_id, did) => {
-                let did = String::from_utf8(did).expect("Failed to parsed");
-                println!("[{}] look up document with id: {}", CONTEXT, did);
-                let msg_to_client = match did_storage.get(&did) {
-                    Some(doc) => doc.to_json().expect("Failed to parsed"),
-                    None => "Not found".into(),
+                let Some(did) = utf8_argument(&mut data, from_id, did) else {
+                    continue;
+                };
+                log_line!(
+                    server_log,
+                    "[{}] look up document with id: {}",
+                    CONTEXT,
+                    did
+                );
+                let msg_to_client = if did.contains('?') {
+                    // DID URL, e.g. did:example:123?service=messaging
+                    match did_storage.dereference_service(&did) {
+                        Some(endpoint) => endpoint.to_string(),
+                        None => "Not found".into(),
+                    }
+                } else {
+                    show_document(&did_storage, &did)
                 };
                 for (id, handle) in data.clients.iter_mut() {
                     let id = *id;
@@ -180,7 +490,12 @@ async fn main_loop(mut recv: Receiver<ToDelivery>) -> Result<(), io::Error> {
                 }
             }
             ToDelivery::NewRole(from_id, role) => {
-                println!("[{}] Updating role: {:?}", CONTEXT, role.clone());
+                log_line!(
+                    server_log,
+                    "[{}] Updating role: {:?}",
+                    CONTEXT,
+                    role.clone()
+                );
                 let msg_to_client = format!("Hello {:?}", role.clone());
                 for (id, handle) in data.clients.iter_mut() {
                     let id = *id;
@@ -200,7 +515,7 @@ async fn main_loop(mut recv: Receiver<ToDelivery>) -> Result<(), io::Error> {
                 }
             }
             ToDelivery::MyInfo(from_id) => {
-                println!("[{}] Responding to who you are", CONTEXT);
+                log_line!(server_log, "[{}] Responding to who you are", CONTEXT);
                 for (id, handle) in data.clients.iter_mut() {
                     let id = *id;
 
@@ -222,16 +537,106 @@ async fn main_loop(mut recv: Receiver<ToDelivery>) -> Result<(), io::Error> {
                     }
                 }
             }
-            ToDelivery::ShowVP(from_id) => {
-                println!("[{}] Responding to show verifiable presentation", CONTEXT);
+            ToDelivery::ShowVP(from_id, presentation)
+                if !presentation.is_empty() && has_role(&data, from_id, ClientRole::Verifier) =>
+            {
+                log_line!(server_log, "[{}] Verifying presentation", CONTEXT);
+                let msg_to_client =
+                    match serde_json::from_slice::<VerifiablePresentation>(&presentation) {
+                        Ok(vp) => vp.verify_full(&*did_storage).to_string(),
+                        Err(err) => format!("Invalid presentation: {}", err),
+                    };
+                reply(&mut data, from_id, &msg_to_client);
+            }
+            ToDelivery::ShowVP(from_id, presentation)
+                if !presentation.is_empty() && is_requested_holder(&data, from_id) =>
+            {
+                log_line!(
+                    server_log,
+                    "[{}] Forwarding presentation of {}",
+                    CONTEXT,
+                    from_id
+                );
+                let msg_to_client =
+                    match serde_json::from_slice::<VerifiablePresentation>(&presentation) {
+                        Ok(vp) => {
+                            let verifiers =
+                                answer_presentation_requests(&mut data, &did_storage, from_id, &vp);
+                            presentation_sent(&verifiers)
+                        }
+                        Err(err) => format!("Invalid presentation: {}", err),
+                    };
+                reply(&mut data, from_id, &msg_to_client);
+            }
+            ToDelivery::RequestPresentation(from_id, args) => {
+                let args = String::from_utf8_lossy(&args).to_string();
+                let mut args = args.split_whitespace();
+                let holder_did = args.next().unwrap_or_default().to_string();
+                let challenge = args
+                    .next()
+                    .map(str::to_string)
+                    .unwrap_or_else(generate_nonce);
+                log_line!(
+                    server_log,
+                    "[{}] {} requests a presentation from {}",
+                    CONTEXT,
+                    from_id,
+                    holder_did
+                );
+                let holder = data.owners.get(&holder_did).copied();
+                let msg_to_client = match holder {
+                    _ if !has_role(&data, from_id, ClientRole::Verifier) => {
+                        "Only verifiers can request presentations".to_string()
+                    }
+                    Some(holder) if data.clients.contains_key(&holder) => {
+                        let msg_to_holder = format!(
+                            "Presentation requested for {} by {} with challenge {}, \
+                             answer with c#svp<presentation> or c#present{} <credential_id>",
+                            holder_did, from_id, challenge, from_id.0
+                        );
+                        data.pending_presentations.push(PendingPresentation {
+                            verifier: from_id,
+                            holder,
+                            challenge,
+                            deadline: Instant::now() + config.presentation_timeout,
+                        });
+                        reply(&mut data, holder, &msg_to_holder);
+                        format!("Presentation requested from {}", holder_did)
+                    }
+                    _ => format!("No connected holder of {}", holder_did),
+                };
+                reply(&mut data, from_id, &msg_to_client);
+            }
+            ToDelivery::Present(from_id, args, resp) => {
+                log_line!(
+                    server_log,
+                    "[{}] {} presents a credential",
+                    CONTEXT,
+                    from_id
+                );
+                let args = String::from_utf8_lossy(&args).to_string();
+                match presentation_request(&data, from_id, &args) {
+                    Ok(request) => {
+                        // A client gone meanwhile has nothing to sign
+                        let _ = resp.send(request);
+                    }
+                    Err(err) => reply(&mut data, from_id, &err),
+                }
+            }
+            ToDelivery::ShowVP(from_id, _) => {
+                log_line!(
+                    server_log,
+                    "[{}] Responding to show verifiable presentation",
+                    CONTEXT
+                );
                 for (id, handle) in data.clients.iter_mut() {
                     let id = *id;
 
                     // Don't send it to the client who sent it to us.
                     if id == from_id {
-                        let ip = get_ipv4_info().unwrap()[0].ip;
-                        println!("[{}] Current ip is {}", CONTEXT, ip);
-                        let url = format!("http://{}:8000/qr", ip);
+                        let ip = server_ip();
+                        log_line!(server_log, "[{}] Current ip is {}", CONTEXT, ip);
+                        let url = build_server_url(ip, "qr");
                         let msg = FromDelivery::QR(url);
 
                         match handle.send(msg) {
@@ -243,9 +648,33 @@ async fn main_loop(mut recv: Receiver<ToDelivery>) -> Result<(), io::Error> {
                     }
                 }
             }
+            ToDelivery::QR(from_id, did) => {
+                let Some(did) = utf8_argument(&mut data, from_id, did) else {
+                    continue;
+                };
+                let did = did.trim();
+                log_line!(server_log, "[{}] QR code for {}", CONTEXT, did);
+                if did_storage.get(did).is_none() {
+                    reply(&mut data, from_id, &format!("{} is not registered", did));
+                    continue;
+                }
+                let url = build_server_url(server_ip(), &format!("1.0/identifiers/{}", did));
+                if let Some(handle) = data.clients.get_mut(&from_id) {
+                    if let Err(err) = handle.send(FromDelivery::QR(url)) {
+                        eprintln!("[{}] Something went wrong: {}.", CONTEXT, err);
+                    }
+                }
+            }
             ToDelivery::VerifyDID(from_id, did) => {
-                let did = String::from_utf8(did).expect("Failed to parsed");
-                println!("[{}] verifying document with id: {}", CONTEXT, did);
+                let Some(did) = utf8_argument(&mut data, from_id, did) else {
+                    continue;
+                };
+                log_line!(
+                    server_log,
+                    "[{}] verifying document with id: {}",
+                    CONTEXT,
+                    did
+                );
                 let msg_to_client = match did_storage.get(&did) {
                     Some(doc) => doc.to_json().expect("Failed to parsed"),
                     None => "Not found".into(),
@@ -266,6 +695,527 @@ async fn main_loop(mut recv: Receiver<ToDelivery>) -> Result<(), io::Error> {
                     }
                 }
             }
+            ToDelivery::Resign(from_id, did, resp) => {
+                let Some(did) = utf8_argument(&mut data, from_id, did) else {
+                    continue;
+                };
+                let did = did.trim();
+                log_line!(server_log, "[{}] {} re-signs {}", CONTEXT, from_id, did);
+                match owned_document(&data, &did_storage, from_id, did) {
+                    Ok(document) => {
+                        let _ = resp.send(document);
+                    }
+                    Err(err) => reply(
+                        &mut data,
+                        from_id,
+                        &format!("Failed to re-sign {}: {}", did, err),
+                    ),
+                }
+            }
+            ToDelivery::Resigned(from_id, document) => {
+                let did = document.id.clone();
+                let msg_to_client = match store_resigned(&data, &mut did_storage, from_id, document)
+                {
+                    Ok(version) => {
+                        notify_updated(&mut data, from_id, &did, version);
+                        format!("{} re-signed, now at version {}", did, version)
+                    }
+                    Err(err) => format!("Failed to re-sign {}: {}", did, err),
+                };
+                reply(&mut data, from_id, &msg_to_client);
+            }
+            ToDelivery::Subscribe(from_id) => {
+                log_line!(
+                    server_log,
+                    "[{}] Subscribing {} to new DIDs",
+                    CONTEXT,
+                    from_id
+                );
+                data.subscribers.insert(from_id);
+                reply(&mut data, from_id, "Subscribed to new DIDs");
+            }
+            ToDelivery::Unsubscribe(from_id) => {
+                log_line!(
+                    server_log,
+                    "[{}] Unsubscribing {} from new DIDs",
+                    CONTEXT,
+                    from_id
+                );
+                data.subscribers.remove(&from_id);
+                reply(&mut data, from_id, "Unsubscribed from new DIDs");
+            }
+            ToDelivery::IssueVC(from_id, args) => {
+                let args = String::from_utf8_lossy(&args).to_string();
+                log_line!(server_log, "[{}] Issuing credential: {}", CONTEXT, args);
+                let msg_to_client = if !has_role(&data, from_id, ClientRole::Issuer) {
+                    "Only issuers can issue credentials".to_string()
+                } else {
+                    match parse_issue_args(&args) {
+                        Ok((subject_did, score, params)) => {
+                            match issuer.generate_vc_with(subject_did, score, &params) {
+                                Ok(vc) => {
+                                    let json = vc.to_json().expect("Failed to parsed");
+                                    deliver_credential(&mut data, &vc);
+                                    data.credentials.insert(vc.id.clone(), vc);
+                                    json
+                                }
+                                Err(err) => format!("Failed to issue credential: {}", err),
+                            }
+                        }
+                        Err(err) => err,
+                    }
+                };
+                reply(&mut data, from_id, &msg_to_client);
+            }
+            ToDelivery::RenewVC(from_id, credential_id) => {
+                let credential_id = String::from_utf8_lossy(&credential_id).to_string();
+                log_line!(
+                    server_log,
+                    "[{}] Renewing credential: {}",
+                    CONTEXT,
+                    credential_id
+                );
+                let msg_to_client = if !has_role(&data, from_id, ClientRole::Issuer) {
+                    "Only issuers can renew credentials".to_string()
+                } else {
+                    match data.credentials.get_mut(&credential_id) {
+                        Some(vc) => {
+                            *vc = issuer.renew_vc(vc);
+                            vc.to_json().expect("Failed to parsed")
+                        }
+                        None => "Not found".into(),
+                    }
+                };
+                reply(&mut data, from_id, &msg_to_client);
+            }
+            ToDelivery::RotateIssuer(from_id) => {
+                log_line!(server_log, "[{}] Rotating the issuer key", CONTEXT);
+                let msg_to_client = if !has_role(&data, from_id, ClientRole::Issuer) {
+                    "Only issuers can rotate the issuer key".to_string()
+                } else {
+                    let previous = issuer.verification_method_id();
+                    let grace = chrono::Duration::from_std(config.issuer_key_grace)
+                        .expect("Grace period out of range");
+                    let mut document = did_storage
+                        .get(&issuer_did)
+                        .cloned()
+                        .expect("Issuer document is registered at startup");
+                    match issuer
+                        .rotate_key(&mut document, grace)
+                        .map_err(|err| err.to_string())
+                        .and_then(|()| {
+                            did_storage.update_by(&issuer_did, document, Some(&from_id.to_string()))
+                        }) {
+                        Ok(()) => {
+                            let version = did_storage.version(&issuer_did).unwrap_or_default();
+                            notify_updated(&mut data, from_id, &issuer_did, version);
+                            format!(
+                                "Issuer key rotated to {}, {} is accepted for {} more seconds",
+                                issuer.verification_method_id(),
+                                previous,
+                                config.issuer_key_grace.as_secs()
+                            )
+                        }
+                        Err(err) => format!("Failed to rotate the issuer key: {}", err),
+                    }
+                };
+                reply(&mut data, from_id, &msg_to_client);
+            }
+            ToDelivery::Snapshot(from_id) => {
+                log_line!(server_log, "[{}] Exporting a registry snapshot", CONTEXT);
+                // There are no admins, the issuer role operates the server
+                let msg_to_client = if !has_role(&data, from_id, ClientRole::Issuer) {
+                    "Only issuers can export the registry".to_string()
+                } else {
+                    issuer
+                        .sign_snapshot(&did_storage)
+                        .to_json()
+                        .expect("Failed to serialize snapshot")
+                };
+                reply(&mut data, from_id, &msg_to_client);
+            }
+            ToDelivery::Import(from_id, documents) => {
+                let documents = String::from_utf8_lossy(&documents).to_string();
+                log_line!(server_log, "[{}] Importing documents", CONTEXT);
+                if !has_role(&data, from_id, ClientRole::Issuer) {
+                    reply(&mut data, from_id, "Only issuers can import documents");
+                    continue;
+                }
+                let msg_to_client = match did_storage.import_json(&documents) {
+                    Ok(outcomes) => {
+                        let mut summary = Vec::new();
+                        for (index, outcome) in outcomes.iter().enumerate() {
+                            match outcome {
+                                Ok(did) => {
+                                    summary.push(format!("Document {}: imported {}", index, did));
+                                    data.owners.insert(did.clone(), from_id);
+                                    notify_subscribers(
+                                        &mut data,
+                                        from_id,
+                                        &format!("New DID created: {}", did),
+                                    );
+                                }
+                                Err(err) => {
+                                    summary.push(format!("Document {}: failed, {}", index, err))
+                                }
+                            }
+                        }
+                        let imported = outcomes.iter().filter(|outcome| outcome.is_ok()).count();
+                        summary.push(format!(
+                            "Imported {} of {} documents",
+                            imported,
+                            outcomes.len()
+                        ));
+                        summary.join("\n")
+                    }
+                    Err(err) => format!("Import failed: {}", err),
+                };
+                reply(&mut data, from_id, &msg_to_client);
+            }
+            ToDelivery::Register(from_id, request) => {
+                log_line!(server_log, "[{}] {} registers a document", CONTEXT, from_id);
+                let msg_to_client = match serde_json::from_slice::<CreateRequest>(&request) {
+                    Ok(request)
+                        if request
+                            .document
+                            .get_service_by_type(CREDENTIAL_REGISTRY_TYPE)
+                            .is_some()
+                            && !has_role(&data, from_id, ClientRole::Issuer) =>
+                    {
+                        "Only issuers can publish a credential registry".to_string()
+                    }
+                    Ok(request) => match did_storage.apply_signed_create(&request) {
+                        Ok(()) => {
+                            data.owners.insert(request.did.clone(), from_id);
+                            let msg = format!("New DID created: {}", request.did);
+                            notify_subscribers(&mut data, from_id, &msg);
+                            let msg = format!("{} created {}", from_id, request.did);
+                            broadcast_creation(
+                                &mut data,
+                                from_id,
+                                &config.creation_broadcast,
+                                &msg,
+                            );
+                            format!("Registered {}", request.did)
+                        }
+                        Err(err) => format!("Registration rejected: {}", err),
+                    },
+                    Err(err) => format!("Invalid create request: {}", err),
+                };
+                reply(&mut data, from_id, &msg_to_client);
+            }
+            ToDelivery::Resolve(from_id, did) => {
+                let did = String::from_utf8_lossy(&did).trim().to_string();
+                log_line!(server_log, "[{}] resolving did: {}", CONTEXT, did);
+                // The router reads the registry, release it first
+                drop(did_storage);
+                let msg_to_client = router
+                    .resolve_with_metadata(&did)
+                    .to_json()
+                    .expect("Failed to serialize resolution result");
+                reply(&mut data, from_id, &msg_to_client);
+            }
+            ToDelivery::HashDocument(from_id, did) => {
+                let Some(did) = utf8_argument(&mut data, from_id, did) else {
+                    continue;
+                };
+                let did = did.trim();
+                log_line!(server_log, "[{}] Hashing document of {}", CONTEXT, did);
+                // The router reads the registry, release it first
+                drop(did_storage);
+                let msg_to_client = match router.resolve(did) {
+                    Some(document) => {
+                        format!("Content hash of {}: {}", did, document.content_hash())
+                    }
+                    None => "Not found".to_string(),
+                };
+                reply(&mut data, from_id, &msg_to_client);
+            }
+            ToDelivery::Expand(from_id, did) => {
+                let Some(did) = utf8_argument(&mut data, from_id, did) else {
+                    continue;
+                };
+                let did = did.trim();
+                log_line!(server_log, "[{}] Expanding document of {}", CONTEXT, did);
+                // The router reads the registry, release it first
+                drop(did_storage);
+                let msg_to_client = match router.resolve(did) {
+                    Some(document) => expand(&document).await,
+                    None => "Not found".to_string(),
+                };
+                reply(&mut data, from_id, &msg_to_client);
+            }
+            ToDelivery::Relationships(from_id, did) => {
+                let Some(did) = utf8_argument(&mut data, from_id, did) else {
+                    continue;
+                };
+                let did = did.trim();
+                log_line!(
+                    server_log,
+                    "[{}] Listing verification relationships of {}",
+                    CONTEXT,
+                    did
+                );
+                // The router reads the registry, release it first
+                drop(did_storage);
+                let msg_to_client = match router.resolve(did) {
+                    Some(document) => serde_json::to_string_pretty(&document.relationships())
+                        .expect("Failed to parsed"),
+                    None => "Not found".to_string(),
+                };
+                reply(&mut data, from_id, &msg_to_client);
+            }
+            ToDelivery::BenchVerify(from_id, args) => {
+                let args = String::from_utf8_lossy(&args).to_string();
+                let mut args = args.split_whitespace();
+                let did = args.next().unwrap_or_default().to_string();
+                let iterations = bench_iterations(args.next(), config.bench_iterations);
+                log_line!(
+                    server_log,
+                    "[{}] Benchmarking verification of {}",
+                    CONTEXT,
+                    did
+                );
+                // The router reads the registry, release it first
+                drop(did_storage);
+                let msg_to_client = match iterations {
+                    Ok(iterations) => bench_verify(&router, &did, iterations),
+                    Err(usage) => usage,
+                };
+                reply(&mut data, from_id, &msg_to_client);
+            }
+            ToDelivery::Methods(from_id) => {
+                log_line!(server_log, "[{}] Listing supported DID methods", CONTEXT);
+                let msg_to_client =
+                    format!("Supported DID methods: {}", router.methods().join(", "));
+                reply(&mut data, from_id, &msg_to_client);
+            }
+            ToDelivery::UpdateDocument(from_id, request) => {
+                let msg_to_client = match serde_json::from_slice::<CreateRequest>(&request) {
+                    Ok(request) => match did_storage.apply_signed_update(&request) {
+                        Ok(()) => {
+                            let version = did_storage.version(&request.did).unwrap_or_default();
+                            notify_updated(&mut data, from_id, &request.did, version);
+                            format!("Your Did Document is updated to version {}", version)
+                        }
+                        Err(err) => format!("Update rejected: {}", err),
+                    },
+                    Err(err) => format!("Invalid update request: {}", err),
+                };
+                reply(&mut data, from_id, &msg_to_client);
+            }
+            ToDelivery::Wallet(from_id) => {
+                log_line!(
+                    server_log,
+                    "[{}] Listing credentials of {}",
+                    CONTEXT,
+                    from_id
+                );
+                let credentials: Vec<String> = data
+                    .wallets
+                    .get(&from_id)
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|credential_id| data.credentials.get(credential_id))
+                    .enumerate()
+                    .map(|(index, vc)| {
+                        format!(
+                            "[{}] {} ({}) from {}, expires {}",
+                            index,
+                            vc.id,
+                            vc.credential_type.join(", "),
+                            vc.issuer,
+                            vc.expiration_date.as_deref().unwrap_or("never")
+                        )
+                    })
+                    .collect();
+                let msg_to_client = if credentials.is_empty() {
+                    "Your wallet is empty".to_string()
+                } else {
+                    credentials.join("\r\n")
+                };
+                reply(&mut data, from_id, &msg_to_client);
+            }
+            ToDelivery::Forget(from_id, credential_id) => {
+                let credential_id = String::from_utf8_lossy(&credential_id).trim().to_string();
+                log_line!(
+                    server_log,
+                    "[{}] {} forgets credential {}",
+                    CONTEXT,
+                    from_id,
+                    credential_id
+                );
+                let wallet = data.wallets.entry(from_id).or_default();
+                let msg_to_client = match wallet.iter().position(|id| *id == credential_id) {
+                    Some(index) => {
+                        wallet.remove(index);
+                        format!("Credential {} removed from your wallet", credential_id)
+                    }
+                    None => format!("Credential {} is not in your wallet", credential_id),
+                };
+                reply(&mut data, from_id, &msg_to_client);
+            }
+            ToDelivery::Challenge(from_id, args) => {
+                let args = String::from_utf8_lossy(&args).to_string();
+                log_line!(
+                    server_log,
+                    "[{}] {} sends a challenge: {}",
+                    CONTEXT,
+                    from_id,
+                    args
+                );
+                let msg_to_client = if !has_role(&data, from_id, ClientRole::Verifier) {
+                    "Only verifiers can send challenges".to_string()
+                } else {
+                    match send_challenge(&mut data, from_id, &args) {
+                        Ok((target, did)) => {
+                            format!("Challenged {} to prove control of {}", target, did)
+                        }
+                        Err(err) => err,
+                    }
+                };
+                reply(&mut data, from_id, &msg_to_client);
+            }
+            ToDelivery::Respond(from_id, args) => {
+                log_line!(
+                    server_log,
+                    "[{}] {} responds to a challenge",
+                    CONTEXT,
+                    from_id
+                );
+                let args = String::from_utf8_lossy(&args).to_string();
+                let msg_to_client = match take_challenge(&mut data, from_id, &args) {
+                    Ok((pending, signature)) => {
+                        let outcome = match did_storage.verify_control(
+                            &pending.did,
+                            pending.nonce.as_bytes(),
+                            &signature,
+                        ) {
+                            Ok(true) => format!("{} proved control of {}", from_id, pending.did),
+                            Ok(false) => format!(
+                                "{} failed to prove control of {}: signature does not match its keys",
+                                from_id, pending.did
+                            ),
+                            Err(err) => format!(
+                                "{} failed to prove control of {}: {}",
+                                from_id, pending.did, err
+                            ),
+                        };
+                        reply(&mut data, pending.verifier, &outcome);
+                        format!("Response sent to {}", pending.verifier)
+                    }
+                    Err(err) => err,
+                };
+                reply(&mut data, from_id, &msg_to_client);
+            }
+            ToDelivery::DirectMessage(from_id, args) => {
+                let args = String::from_utf8_lossy(&args).to_string();
+                let msg_to_client = match args.trim().split_once(' ') {
+                    Some((did, text)) => {
+                        log_line!(
+                            server_log,
+                            "[{}] Direct message from {} to {}",
+                            CONTEXT,
+                            from_id,
+                            did
+                        );
+                        // Owners are forgotten when they disconnect
+                        match data.owners.get(did).copied() {
+                            Some(owner) if data.clients.contains_key(&owner) => {
+                                let msg = format!("Message for {} from {}: {}", did, from_id, text);
+                                reply(&mut data, owner, &msg);
+                                format!("Message delivered to {}", did)
+                            }
+                            _ if did_storage.get(did).is_some() => "recipient offline".to_string(),
+                            _ => "unknown DID".to_string(),
+                        }
+                    }
+                    None => DM_USAGE.to_string(),
+                };
+                reply(&mut data, from_id, &msg_to_client);
+            }
+            ToDelivery::ServerLog(from_id) => {
+                let msg_to_client = if has_role(&data, from_id, ClientRole::Verifier) {
+                    server_log.lines().collect::<Vec<_>>().join("\r\n")
+                } else {
+                    "Only verifiers can read the server log".to_string()
+                };
+                reply(&mut data, from_id, &msg_to_client);
+            }
+            ToDelivery::Stats(from_id) => {
+                log_line!(server_log, "[{}] Reporting statistics", CONTEXT);
+                let msg_to_client = stats(&data, &did_storage);
+                reply(&mut data, from_id, &msg_to_client);
+            }
+            ToDelivery::Ephemeral(from_id) => {
+                log_line!(server_log, "[{}] {} is ephemeral", CONTEXT, from_id);
+                data.ephemeral.insert(from_id);
+                let msg_to_client = "Ephemeral mode on, your DIDs are deleted when you disconnect";
+                reply(&mut data, from_id, msg_to_client);
+            }
+            ToDelivery::WindowSize(from_id, width, height) => {
+                log_line!(
+                    server_log,
+                    "[{}] {} has a {}x{} window",
+                    CONTEXT,
+                    from_id,
+                    width,
+                    height
+                );
+                if let Some(handle) = data.clients.get_mut(&from_id) {
+                    handle.window_size = Some((width, height));
+                }
+            }
+            ToDelivery::Interrupt(from_id) => {
+                log_line!(server_log, "[{}] Interrupting {}", CONTEXT, from_id);
+                let cancelled = cancel_pending_operations(&mut data, from_id);
+                let msg_to_client =
+                    format!("Interrupted, cancelled {} pending operation(s)", cancelled);
+                reply(&mut data, from_id, &msg_to_client);
+                if let Some(handle) = data.clients.get_mut(&from_id) {
+                    if let Err(err) = handle.send(FromDelivery::Prompt) {
+                        eprintln!("[{}] Something went wrong: {}.", CONTEXT, err);
+                    }
+                }
+            }
+            ToDelivery::Shutdown => {
+                log_line!(server_log, "[{}] Shutting down", CONTEXT);
+                drop(did_storage);
+                let clients = std::mem::take(&mut data.clients);
+                join_all(
+                    clients
+                        .into_values()
+                        .map(|client| client.shutdown(CLIENT_SHUTDOWN_WAIT)),
+                )
+                .await;
+                break;
+            }
+            ToDelivery::ClientLeft(from_id) => {
+                log_line!(server_log, "[{}] {} left", CONTEXT, from_id);
+                data.clients.remove(&from_id);
+                data.subscribers.remove(&from_id);
+                data.wallets.remove(&from_id);
+                data.pending_presentations
+                    .retain(|pending| pending.verifier != from_id);
+                // Verifiers waiting on it hear now rather than at the timeout
+                cancel_holder_presentations(&mut data, from_id, "disconnected");
+                data.pending_challenges
+                    .retain(|pending| pending.verifier != from_id && pending.target != from_id);
+                let ephemeral = data.ephemeral.remove(&from_id);
+                let owned: Vec<String> = data
+                    .owners
+                    .iter()
+                    .filter(|(_, owner)| **owner == from_id)
+                    .map(|(did, _)| did.clone())
+                    .collect();
+                for did in owned {
+                    data.owners.remove(&did);
+                    if ephemeral {
+                        log_line!(server_log, "[{}] Deleting ephemeral DID {}", CONTEXT, did);
+                        did_storage.delete(&did);
+                    }
+                }
+            }
             //Todo: add server logic
             ToDelivery::FatalError(err) => return Err(err),
         }
@@ -273,3 +1223,2374 @@ async fn main_loop(mut recv: Receiver<ToDelivery>) -> Result<(), io::Error> {
 
     Ok(())
 }
+
+// Decode a command argument, telling the client when it isn't valid UTF-8
+fn utf8_argument(data: &mut Data, from_id: ClientId, arg: Vec<u8>) -> Option<String> {
+    match String::from_utf8(arg) {
+        Ok(arg) => Some(arg),
+        Err(_) => {
+            reply(data, from_id, "Invalid argument: not valid UTF-8");
+            None
+        }
+    }
+}
+
+// Drop everything a client is waiting for or was asked to answer, returning
+// how many operations were cancelled. Verifiers whose request the client was
+// asked to answer are told it won't come.
+fn cancel_pending_operations(data: &mut Data, id: ClientId) -> usize {
+    let challenges = data.pending_challenges.len();
+    data.pending_challenges
+        .retain(|pending| pending.verifier != id && pending.target != id);
+    let challenges = challenges - data.pending_challenges.len();
+
+    let presentations = data.pending_presentations.len();
+    data.pending_presentations
+        .retain(|pending| pending.verifier != id);
+    let presentations = presentations - data.pending_presentations.len();
+
+    challenges + presentations + cancel_holder_presentations(data, id, "interrupted")
+}
+
+// Drop the presentation requests to a holder and tell each verifier why,
+// returning how many were cancelled
+fn cancel_holder_presentations(data: &mut Data, holder: ClientId, reason: &str) -> usize {
+    let (cancelled, waiting): (Vec<_>, Vec<_>) = data
+        .pending_presentations
+        .drain(..)
+        .partition(|pending| pending.holder == holder);
+    data.pending_presentations = waiting;
+
+    let msg_to_verifier = format!(
+        "Holder {} {}, presentation request cancelled",
+        holder, reason
+    );
+    for pending in &cancelled {
+        reply(data, pending.verifier, &msg_to_verifier);
+    }
+
+    cancelled.len()
+}
+
+fn is_requested_holder(data: &Data, id: ClientId) -> bool {
+    data.pending_presentations
+        .iter()
+        .any(|pending| pending.holder == id)
+}
+
+// Answer the presentation requests to a holder that the presentation is bound
+// to by their challenge, returning the verifiers answered. Requests made with
+// another challenge stay pending, so an old presentation can't answer them.
+fn answer_presentation_requests(
+    data: &mut Data,
+    storage: &DidStorage,
+    holder: ClientId,
+    vp: &VerifiablePresentation,
+) -> Vec<ClientId> {
+    let (answered, waiting): (Vec<_>, Vec<_>) =
+        data.pending_presentations.drain(..).partition(|pending| {
+            pending.holder == holder && vp.proof.nonce.as_ref() == Some(&pending.challenge)
+        });
+    data.pending_presentations = waiting;
+
+    let json = vp.to_json().expect("Failed to parsed");
+    answered
+        .into_iter()
+        .map(|pending| {
+            let options = VerificationOptions {
+                challenge: Some(pending.challenge),
+                ..Default::default()
+            };
+            let report = vp.verify_full_with(storage, &options);
+            reply(data, pending.verifier, &format!("{}\r\n{}", json, report));
+            pending.verifier
+        })
+        .collect()
+}
+
+// Answer to a holder whose presentation went to the given verifiers
+fn presentation_sent(verifiers: &[ClientId]) -> String {
+    if verifiers.is_empty() {
+        return "Presentation doesn't answer any request, \
+                it must carry the challenge it was requested with"
+            .to_string();
+    }
+
+    let verifiers: Vec<String> = verifiers.iter().map(ClientId::to_string).collect();
+    format!("Presentation sent to {}", verifiers.join(", "))
+}
+
+// The credential of the holder's wallet and the challenge to sign into a
+// presentation answering the given verifier's pending request, from the
+// arguments of c#present: <verifier_id> <credential_id>
+fn presentation_request(
+    data: &Data,
+    holder: ClientId,
+    args: &str,
+) -> Result<PresentationRequest, String> {
+    let usage = "Usage: c#present<verifier_id> <credential_id>".to_string();
+    let (verifier, credential_id) = args.trim().split_once(' ').ok_or(usage.clone())?;
+    let verifier = ClientId(verifier.parse().map_err(|_| usage)?);
+    let credential_id = credential_id.trim();
+
+    let Some(pending) = data
+        .pending_presentations
+        .iter()
+        .find(|pending| pending.verifier == verifier && pending.holder == holder)
+    else {
+        return Err(format!("No presentation request from {}", verifier));
+    };
+    let in_wallet = data
+        .wallets
+        .get(&holder)
+        .is_some_and(|wallet| wallet.iter().any(|id| id == credential_id));
+    match data.credentials.get(credential_id) {
+        Some(vc) if in_wallet => Ok(PresentationRequest {
+            credential: vc.clone(),
+            challenge: pending.challenge.clone(),
+        }),
+        _ => Err(format!(
+            "Credential {} is not in your wallet",
+            credential_id
+        )),
+    }
+}
+
+// Iterations asked for with c#benchverify, capped at MAX_BENCH_ITERATIONS
+fn bench_iterations(arg: Option<&str>, default: usize) -> Result<usize, String> {
+    match arg.map(str::parse::<usize>) {
+        None => Ok(default),
+        Some(Ok(n)) if n > 0 => Ok(n.min(MAX_BENCH_ITERATIONS)),
+        Some(_) => Err("Usage: c#benchverify<did> [iterations]".to_string()),
+    }
+}
+
+// Resolve the DID and verify the proof of its document the given number of
+// times, reporting the total and average duration
+fn bench_verify(router: &MethodRouter, did: &str, iterations: usize) -> String {
+    let start = Instant::now();
+    let mut valid = 0;
+    for _ in 0..iterations {
+        let Some(document) = router.resolve(did) else {
+            return "Not found".to_string();
+        };
+        if document.proof.is_none() {
+            return format!("{} has no proof to verify", did);
+        }
+        if document.verify_proof() {
+            valid += 1;
+        }
+    }
+    let total = start.elapsed();
+
+    format!(
+        "Verified {} {} times ({} valid) in {:?}, {:?} on average",
+        did,
+        iterations,
+        valid,
+        total,
+        total / iterations as u32
+    )
+}
+
+// JSON-LD expanded form of a document, as shown by c#expand
+#[cfg(feature = "ssi")]
+async fn expand(document: &DidDocument) -> String {
+    match did::expand_document(document).await {
+        Ok(expanded) => serde_json::to_string_pretty(&expanded).expect("Failed to parsed"),
+        Err(err) => format!("Failed to expand {}: {}", document.id, err),
+    }
+}
+
+#[cfg(not(feature = "ssi"))]
+async fn expand(_document: &DidDocument) -> String {
+    "JSON-LD expansion needs the server built with the ssi feature".to_string()
+}
+
+// Send a random nonce to the client a verifier challenges, from the
+// arguments of c#challenge: <client_id> <did>
+fn send_challenge(
+    data: &mut Data,
+    verifier: ClientId,
+    args: &str,
+) -> Result<(ClientId, String), String> {
+    let usage = "Usage: c#challenge<client_id> <did>".to_string();
+    let (target, did) = args.trim().split_once(' ').ok_or(usage.clone())?;
+    let target = ClientId(target.parse().map_err(|_| usage)?);
+    let did = did.trim().to_string();
+    if !data.clients.contains_key(&target) {
+        return Err(format!("{} is not connected", target));
+    }
+
+    let nonce = generate_nonce();
+    let msg_to_target = format!(
+        "{} challenges you to prove control of {}, sign {} with its key \
+         and answer with c#respond{} <signature>",
+        verifier, did, nonce, verifier.0
+    );
+    data.pending_challenges.push(PendingChallenge {
+        verifier,
+        target,
+        did: did.clone(),
+        nonce,
+    });
+    reply(data, target, &msg_to_target);
+
+    Ok((target, did))
+}
+
+// The challenge a client answers, from the arguments of c#respond:
+// <verifier_id> <signature>
+fn take_challenge(
+    data: &mut Data,
+    target: ClientId,
+    args: &str,
+) -> Result<(PendingChallenge, String), String> {
+    let usage = "Usage: c#respond<verifier_id> <signature>".to_string();
+    let (verifier, signature) = args.trim().split_once(' ').ok_or(usage.clone())?;
+    let verifier = ClientId(verifier.parse().map_err(|_| usage)?);
+
+    let index = data
+        .pending_challenges
+        .iter()
+        .position(|pending| pending.verifier == verifier && pending.target == target)
+        .ok_or(format!("No challenge from {}", verifier))?;
+
+    Ok((
+        data.pending_challenges.remove(index),
+        signature.trim().to_string(),
+    ))
+}
+
+// Split the arguments of c#issue, <subject_did>:<score> with the default
+// range and confidence or <subject_did>:<score>:<range>:<confidence>. The
+// subject DID itself contains ':' so the parts are taken from the end
+fn parse_issue_args(args: &str) -> Result<(&str, u32, IssuanceParams), String> {
+    let usage = "Usage: c#issue<subject_did>:<score>[:<range>:<confidence>]".to_string();
+    let (rest, last) = args.rsplit_once(':').ok_or(usage.clone())?;
+    if let Ok(score) = last.parse::<u32>() {
+        return Ok((rest, score, IssuanceParams::default()));
+    }
+
+    let mut parts = rest.rsplitn(3, ':');
+    let (Some(range), Some(score), Some(subject_did)) = (parts.next(), parts.next(), parts.next())
+    else {
+        return Err(usage);
+    };
+    let score = score
+        .parse::<u32>()
+        .map_err(|_| format!("Invalid credit score: {}", score))?;
+    let params = IssuanceParams::parse(range, last).map_err(|err| err.to_string())?;
+
+    Ok((subject_did, score, params))
+}
+
+// Hand a newly issued credential to the connected client owning its subject
+fn deliver_credential(data: &mut Data, vc: &VerifiableCredential) {
+    let Some(holder) = data.owners.get(&vc.credential_subject.id).copied() else {
+        return;
+    };
+    if !data.clients.contains_key(&holder) {
+        return;
+    }
+
+    data.wallets.entry(holder).or_default().push(vc.id.clone());
+    let msg_to_holder = format!("You received credential {} from {}", vc.id, vc.issuer);
+    reply(data, holder, &msg_to_holder);
+}
+
+// Cancel the presentation requests past their deadline
+fn expire_presentation_requests(data: &mut Data) {
+    let now = Instant::now();
+    let (expired, waiting): (Vec<_>, Vec<_>) = data
+        .pending_presentations
+        .drain(..)
+        .partition(|pending| pending.deadline <= now);
+    data.pending_presentations = waiting;
+
+    for pending in expired {
+        println!(
+            "[{}] Presentation request of {} timed out",
+            CONTEXT, pending.verifier
+        );
+        reply(data, pending.verifier, "presentation request timed out");
+    }
+}
+
+// Send a message to a single client, removing it if its actor is gone
+fn reply(data: &mut Data, to: ClientId, msg_to_client: &str) {
+    if let Some(handle) = data.clients.get_mut(&to) {
+        let msg = FromDelivery::Message(msg_to_client.as_bytes().to_vec());
+
+        match handle.send(msg) {
+            Ok(()) => {}
+            Err(err) => {
+                eprintln!("[{}] Something went wrong: {}.", CONTEXT, err);
+                if handle.is_dead() {
+                    println!("[{}] Removing dead {}", CONTEXT, to);
+                    data.clients.remove(&to);
+                }
+            }
+        };
+    }
+}
+
+fn has_role(data: &Data, id: ClientId, role: ClientRole) -> bool {
+    data.clients
+        .get(&id)
+        .is_some_and(|handle| handle.role == Some(role))
+}
+
+fn has_any_role(data: &Data, id: ClientId) -> bool {
+    data.clients
+        .get(&id)
+        .is_some_and(|handle| handle.role.is_some())
+}
+
+// The sender of a command only clients of some role may run, those checking
+// the exact role once a role is assigned
+fn needs_role(msg: &ToDelivery) -> Option<ClientId> {
+    match msg {
+        ToDelivery::IssueVC(from_id, _)
+        | ToDelivery::RenewVC(from_id, _)
+        | ToDelivery::RotateIssuer(from_id)
+        | ToDelivery::Snapshot(from_id)
+        | ToDelivery::RequestPresentation(from_id, _)
+        | ToDelivery::Challenge(from_id, _)
+        | ToDelivery::ServerLog(from_id) => Some(*from_id),
+        _ => None,
+    }
+}
+
+// Document shown by c#sdid, of the DID itself or of the only stored DID
+// starting with it, so interactive users can type a prefix
+fn show_document(storage: &DidStorage, did: &str) -> String {
+    if let Some(document) = storage.get(did) {
+        return document.to_json().expect("Failed to parsed");
+    }
+
+    match storage.find_by_prefix(did)[..] {
+        [] => "no match".to_string(),
+        [did] => storage
+            .get(did)
+            .map(|document| document.to_json().expect("Failed to parsed"))
+            .unwrap_or_default(),
+        ref matches => format!("ambiguous prefix ({} matches)", matches.len()),
+    }
+}
+
+// Stored document of a DID for its owner to sign again, once edits left its
+// embedded proof stale
+fn owned_document(
+    data: &Data,
+    storage: &DidStorage,
+    from_id: ClientId,
+    did: &str,
+) -> Result<DidDocument, String> {
+    if data.owners.get(did) != Some(&from_id) {
+        return Err("only its owner can re-sign it".to_string());
+    }
+
+    storage.get(did).cloned().ok_or("not found".to_string())
+}
+
+// Store a document its owner signed again, returning the new version
+fn store_resigned(
+    data: &Data,
+    storage: &mut DidStorage,
+    from_id: ClientId,
+    document: DidDocument,
+) -> Result<u64, String> {
+    let did = document.id.clone();
+    if data.owners.get(&did) != Some(&from_id) {
+        return Err("only its owner can re-sign it".to_string());
+    }
+    if !document.verify_proof() {
+        return Err("its proof doesn't verify".to_string());
+    }
+    storage.update_by(&did, document, Some(&from_id.to_string()))?;
+
+    Ok(storage.version(&did).unwrap_or_default())
+}
+
+// Counts shown by c#stats, the server's own issuer DID included
+fn stats(data: &Data, storage: &DidStorage) -> String {
+    let with_role = |role| {
+        data.clients
+            .values()
+            .filter(|handle| handle.role == role)
+            .count()
+    };
+
+    [
+        format!("DIDs: {}", storage.len()),
+        format!("Credentials: {}", data.credentials.len()),
+        format!(
+            "Clients: {} (holders {}, issuers {}, verifiers {}, no role {})",
+            data.clients.len(),
+            with_role(Some(ClientRole::Holder)),
+            with_role(Some(ClientRole::Issuer)),
+            with_role(Some(ClientRole::Verifier)),
+            with_role(None)
+        ),
+        format!("Registry size: ~{} bytes", storage.estimated_size()),
+    ]
+    .join("\r\n")
+}
+
+// Tell subscribers to drop verifications made against an older version
+fn notify_updated(data: &mut Data, from_id: ClientId, did: &str, version: u64) {
+    println!("[{}] {} updated to version {}", CONTEXT, did, version);
+    let msg_to_client = format!("DID updated, re-verify: {} version {}", did, version);
+    notify_subscribers(data, from_id, &msg_to_client);
+}
+
+// Let every subscribed client, except the creator, know about a new DID
+fn notify_subscribers(data: &mut Data, from_id: ClientId, msg_to_client: &str) {
+    let subscribers: Vec<ClientId> = data.subscribers.iter().copied().collect();
+    for id in subscribers {
+        // Don't send it to the client who caused it.
+        if id == from_id {
+            continue;
+        }
+
+        reply(data, id, msg_to_client);
+    }
+}
+
+// Tell the clients in scope about a new DID, subscribers are already told
+fn broadcast_creation(data: &mut Data, from_id: ClientId, scope: &BroadcastScope, msg: &str) {
+    let recipients: Vec<ClientId> = data
+        .clients
+        .iter()
+        .filter(|(id, handle)| {
+            **id != from_id
+                && !data.subscribers.contains(id)
+                && match scope {
+                    BroadcastScope::None | BroadcastScope::Creator => false,
+                    BroadcastScope::Role(role) => handle.role.as_ref() == Some(role),
+                    BroadcastScope::All => true,
+                }
+        })
+        .map(|(id, _)| *id)
+        .collect();
+    for id in recipients {
+        reply(data, id, msg);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::time::timeout;
+
+    use super::*;
+    use crate::client::{
+        create_did_document, resign_document, sign_presentation, signing_key_notice, test_handle,
+    };
+    use crate::outbox::OutboxReceiver;
+    use base58::{FromBase58, ToBase58};
+    use did::fixtures::{deterministic_keypair, sample_document, FIXTURE_SEED};
+    use did::DidStorage;
+    use did::{
+        create_signed_update, decode_multibase_to_public_key, verify_snapshot, Service,
+        SignedSnapshot,
+    };
+    use ed25519_dalek::{Signer, SigningKey};
+
+    // Present a credential the way the holder's client actor does, signing
+    // the request the main loop hands back with the holder's key
+    async fn present_credential(server: &mut ServerHandle, holder_id: ClientId, args: &str) {
+        let (resp, request) = oneshot::channel();
+        server
+            .send(ToDelivery::Present(
+                holder_id,
+                args.as_bytes().to_vec(),
+                resp,
+            ))
+            .await;
+        if let Ok(request) = request.await {
+            let holder_did = request.credential.credential_subject.id.clone();
+            let keys = HashMap::from([(holder_did, deterministic_keypair(FIXTURE_SEED))]);
+            let vp = sign_presentation(&keys, request).unwrap();
+            server.send(ToDelivery::ShowVP(holder_id, vp)).await;
+        }
+    }
+
+    // Re-sign a document the way its owner's client actor does
+    async fn resign(server: &mut ServerHandle, from_id: ClientId, did: &str) {
+        let (resp, document) = oneshot::channel();
+        server
+            .send(ToDelivery::Resign(from_id, did.as_bytes().to_vec(), resp))
+            .await;
+        if let Ok(document) = document.await {
+            let keys = HashMap::from([(did.to_string(), deterministic_keypair(FIXTURE_SEED))]);
+            let document = resign_document(&keys, document).unwrap();
+            server.send(ToDelivery::Resigned(from_id, document)).await;
+        }
+    }
+
+    // Collect every message delivered to a client until the channel goes quiet
+    async fn drain(recv: &mut OutboxReceiver) -> Vec<String> {
+        let mut messages = Vec::new();
+        while let Ok(Some(msg)) = timeout(Duration::from_millis(100), recv.recv()).await {
+            if let FromDelivery::Message(bytes) = msg {
+                messages.push(String::from_utf8_lossy(&bytes).to_string());
+            }
+        }
+
+        messages
+    }
+
+    #[tokio::test]
+    async fn test_subscribed_verifier_is_notified_of_new_did() {
+        let (mut server, _join) = spawn_main_loop();
+
+        let (holder, mut holder_recv) = test_handle(server.next_id());
+        let (verifier, mut verifier_recv) = test_handle(server.next_id());
+        let (other, mut other_recv) = test_handle(server.next_id());
+        let (holder_id, verifier_id, other_id) = (holder.id, verifier.id, other.id);
+
+        server.send(ToDelivery::NewClient(holder)).await;
+        server.send(ToDelivery::NewClient(verifier)).await;
+        server.send(ToDelivery::NewClient(other)).await;
+        server
+            .send(ToDelivery::NewRole(verifier_id, ClientRole::Verifier))
+            .await;
+        server
+            .send(ToDelivery::NewRole(other_id, ClientRole::Verifier))
+            .await;
+        server.send(ToDelivery::Subscribe(verifier_id)).await;
+        server.send(ToDelivery::Subscribe(other_id)).await;
+        server.send(ToDelivery::Unsubscribe(other_id)).await;
+
+        let did = "did:example:123456789abcdefghi";
+        server
+            .send(ToDelivery::DidDocument(holder_id, sample_document(did)))
+            .await;
+
+        let notification = format!("New DID created: {}", did);
+        assert!(drain(&mut verifier_recv).await.contains(&notification));
+        assert!(!drain(&mut other_recv).await.contains(&notification));
+        assert!(!drain(&mut holder_recv).await.contains(&notification));
+    }
+
+    // Issuer DID of the credentials issued by a server started with a config
+    async fn issued_by(config: MainLoopConfig) -> String {
+        let (mut server, _join) = spawn_main_loop_for(MethodRouter::new(), config).unwrap();
+        let (issuer, mut issuer_recv) = test_handle(server.next_id());
+        let issuer_id = issuer.id;
+        server.send(ToDelivery::NewClient(issuer)).await;
+        server
+            .send(ToDelivery::NewRole(issuer_id, ClientRole::Issuer))
+            .await;
+        server
+            .send(ToDelivery::IssueVC(
+                issuer_id,
+                b"did:example:alice:750".to_vec(),
+            ))
+            .await;
+
+        let issued: VerifiableCredential = drain(&mut issuer_recv)
+            .await
+            .iter()
+            .find_map(|msg| serde_json::from_str(msg).ok())
+            .expect("Issuer should receive the credential");
+        issued.issuer
+    }
+
+    #[tokio::test]
+    async fn test_issuer_seed_gives_the_same_issuer() {
+        let seeded = |seed: &[u8]| MainLoopConfig {
+            issuer_seed: Some(seed.to_vec()),
+            ..Default::default()
+        };
+
+        let first = issued_by(seeded(b"workshop")).await;
+        let second = issued_by(seeded(b"workshop")).await;
+        assert_eq!(first, second);
+        assert_ne!(first, ISSUER_DID);
+        assert_ne!(issued_by(seeded(b"another")).await, first);
+    }
+
+    #[tokio::test]
+    async fn test_issuer_renews_credential() {
+        let (mut server, _join) = spawn_main_loop();
+
+        let (issuer, mut issuer_recv) = test_handle(server.next_id());
+        let issuer_id = issuer.id;
+        server.send(ToDelivery::NewClient(issuer)).await;
+        server
+            .send(ToDelivery::NewRole(issuer_id, ClientRole::Issuer))
+            .await;
+        server
+            .send(ToDelivery::IssueVC(
+                issuer_id,
+                b"did:example:alice:750".to_vec(),
+            ))
+            .await;
+
+        let messages = drain(&mut issuer_recv).await;
+        let issued: VerifiableCredential = messages
+            .iter()
+            .find_map(|msg| serde_json::from_str(msg).ok())
+            .expect("Issuer should receive the credential");
+        assert_eq!(issued.credential_subject.id, "did:example:alice");
+
+        server
+            .send(ToDelivery::RenewVC(
+                issuer_id,
+                issued.id.clone().into_bytes(),
+            ))
+            .await;
+        let messages = drain(&mut issuer_recv).await;
+        let renewed: VerifiableCredential = serde_json::from_str(&messages[0]).unwrap();
+        assert_eq!(renewed.id, issued.id);
+        assert!(renewed.expiration_date >= issued.expiration_date);
+    }
+
+    #[tokio::test]
+    async fn test_issue_with_score_range_and_confidence() {
+        let (mut server, _join) = spawn_main_loop();
+
+        let (issuer, mut issuer_recv) = test_handle(server.next_id());
+        let issuer_id = issuer.id;
+        server.send(ToDelivery::NewClient(issuer)).await;
+        server
+            .send(ToDelivery::NewRole(issuer_id, ClientRole::Issuer))
+            .await;
+        drain(&mut issuer_recv).await;
+
+        server
+            .send(ToDelivery::IssueVC(
+                issuer_id,
+                b"did:example:alice:550:300-600:Medium".to_vec(),
+            ))
+            .await;
+        let messages = drain(&mut issuer_recv).await;
+        let issued: VerifiableCredential = serde_json::from_str(&messages[0]).unwrap();
+        assert_eq!(issued.credential_subject.id, "did:example:alice");
+        assert_eq!(issued.credential_subject.credit_score, 550);
+        assert_eq!(issued.credential_subject.score_range, "300-600");
+        assert_eq!(issued.credential_subject.confidence_level, "Medium");
+
+        server
+            .send(ToDelivery::IssueVC(
+                issuer_id,
+                b"did:example:alice:750:300-600:Medium".to_vec(),
+            ))
+            .await;
+        assert_eq!(
+            drain(&mut issuer_recv).await,
+            vec![
+                "Failed to issue credential: VC Error: Credit score 750 is outside the range 300-600"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_creation_broadcast_scope() {
+        for (scope, verifier_told, holder_told) in [
+            ("all", true, true),
+            ("role:verifier", true, false),
+            ("creator", false, false),
+        ] {
+            let config = MainLoopConfig {
+                creation_broadcast: scope.to_string().try_into().unwrap(),
+                ..Default::default()
+            };
+            let (mut server, _join) = spawn_main_loop_for(MethodRouter::new(), config).unwrap();
+
+            let (creator, mut creator_recv) = test_handle(server.next_id());
+            let creator_id = creator.id;
+            server.send(ToDelivery::NewClient(creator)).await;
+            let (verifier, mut verifier_recv) = test_handle(server.next_id());
+            let verifier_id = verifier.id;
+            server.send(ToDelivery::NewClient(verifier)).await;
+            server
+                .send(ToDelivery::NewRole(verifier_id, ClientRole::Verifier))
+                .await;
+            let (holder, mut holder_recv) = test_handle(server.next_id());
+            server.send(ToDelivery::NewClient(holder)).await;
+            drain(&mut creator_recv).await;
+            drain(&mut verifier_recv).await;
+            drain(&mut holder_recv).await;
+
+            server
+                .send(ToDelivery::DidDocument(
+                    creator_id,
+                    sample_document("did:example:alice"),
+                ))
+                .await;
+            let notice = format!("{} created did:example:alice", creator_id);
+            assert_eq!(
+                drain(&mut creator_recv).await,
+                vec!["Your Did Document is saved!"]
+            );
+            assert_eq!(
+                drain(&mut verifier_recv).await.contains(&notice),
+                verifier_told,
+                "{}",
+                scope
+            );
+            assert_eq!(
+                drain(&mut holder_recv).await.contains(&notice),
+                holder_told,
+                "{}",
+                scope
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_qr_code_of_registered_did() {
+        let (mut server, _join) = spawn_main_loop();
+
+        let (client, mut client_recv) = test_handle(server.next_id());
+        let client_id = client.id;
+        server.send(ToDelivery::NewClient(client)).await;
+        server
+            .send(ToDelivery::DidDocument(
+                client_id,
+                sample_document("did:example:alice"),
+            ))
+            .await;
+        drain(&mut client_recv).await;
+
+        server
+            .send(ToDelivery::QR(client_id, b"did:example:alice".to_vec()))
+            .await;
+        match timeout(Duration::from_secs(1), client_recv.recv()).await {
+            Ok(Some(FromDelivery::QR(url))) => {
+                assert!(url.ends_with(":8000/1.0/identifiers/did:example:alice"));
+                assert!(!did::print_qr_code(&url).unwrap().is_empty());
+            }
+            _ => panic!("Expected a QR code"),
+        }
+
+        server
+            .send(ToDelivery::QR(client_id, b"did:example:bob".to_vec()))
+            .await;
+        assert_eq!(
+            drain(&mut client_recv).await,
+            vec!["did:example:bob is not registered"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_wallet_lists_issued_credentials() {
+        let (mut server, _join) = spawn_main_loop();
+
+        let (holder, mut holder_recv) = test_handle(server.next_id());
+        let holder_id = holder.id;
+        server.send(ToDelivery::NewClient(holder)).await;
+        server
+            .send(ToDelivery::DidDocument(
+                holder_id,
+                sample_document("did:example:alice"),
+            ))
+            .await;
+        let (issuer, mut issuer_recv) = test_handle(server.next_id());
+        let issuer_id = issuer.id;
+        server.send(ToDelivery::NewClient(issuer)).await;
+        server
+            .send(ToDelivery::NewRole(issuer_id, ClientRole::Issuer))
+            .await;
+        server.send(ToDelivery::Wallet(holder_id)).await;
+        assert!(drain(&mut holder_recv)
+            .await
+            .contains(&"Your wallet is empty".to_string()));
+
+        for score in ["750", "800"] {
+            server
+                .send(ToDelivery::IssueVC(
+                    issuer_id,
+                    format!("did:example:alice:{}", score).into_bytes(),
+                ))
+                .await;
+        }
+        let issued: Vec<VerifiableCredential> = drain(&mut issuer_recv)
+            .await
+            .iter()
+            .filter_map(|msg| serde_json::from_str(msg).ok())
+            .collect();
+        assert_eq!(issued.len(), 2);
+        let received = drain(&mut holder_recv).await;
+        assert_eq!(received.len(), 2);
+        assert!(received[0].starts_with("You received credential"));
+
+        server.send(ToDelivery::Wallet(holder_id)).await;
+        let wallet = drain(&mut holder_recv).await;
+        let lines: Vec<&str> = wallet[0].split("\r\n").collect();
+        assert_eq!(lines.len(), 2);
+        for (index, (line, vc)) in lines.iter().zip(issued.iter()).enumerate() {
+            assert!(line.starts_with(&format!("[{}] {}", index, vc.id)));
+            assert!(line.contains(&format!("from {}", ISSUER_DID)));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_holder_presents_wallet_credential() {
+        let (mut server, _join) = spawn_main_loop();
+
+        let (holder, mut holder_recv) = test_handle(server.next_id());
+        let holder_id = holder.id;
+        server.send(ToDelivery::NewClient(holder)).await;
+        server
+            .send(ToDelivery::DidDocument(
+                holder_id,
+                sample_document("did:example:alice"),
+            ))
+            .await;
+        let (issuer, mut issuer_recv) = test_handle(server.next_id());
+        let issuer_id = issuer.id;
+        server.send(ToDelivery::NewClient(issuer)).await;
+        server
+            .send(ToDelivery::NewRole(issuer_id, ClientRole::Issuer))
+            .await;
+        server
+            .send(ToDelivery::IssueVC(
+                issuer_id,
+                b"did:example:alice:750".to_vec(),
+            ))
+            .await;
+        let issued: VerifiableCredential = drain(&mut issuer_recv)
+            .await
+            .iter()
+            .find_map(|msg| serde_json::from_str(msg).ok())
+            .expect("Issuer should receive the credential");
+        let (verifier, mut verifier_recv) = test_handle(server.next_id());
+        let verifier_id = verifier.id;
+        server.send(ToDelivery::NewClient(verifier)).await;
+        server
+            .send(ToDelivery::NewRole(verifier_id, ClientRole::Verifier))
+            .await;
+        server
+            .send(ToDelivery::RequestPresentation(
+                verifier_id,
+                b"did:example:alice challenge-1".to_vec(),
+            ))
+            .await;
+        drain(&mut holder_recv).await;
+        drain(&mut verifier_recv).await;
+
+        let present = format!("{} {}", verifier_id.0, issued.id);
+        present_credential(&mut server, holder_id, &present).await;
+        assert_eq!(
+            drain(&mut holder_recv).await,
+            vec![format!("Presentation sent to {}", verifier_id)]
+        );
+
+        let received = drain(&mut verifier_recv).await;
+        assert_eq!(received.len(), 1);
+        let (vp, report) = received[0].rsplit_once("\r\nHolder ").unwrap();
+        let vp: VerifiablePresentation = serde_json::from_str(vp).unwrap();
+        assert_eq!(vp.holder, "did:example:alice");
+        assert_eq!(vp.proof.nonce.as_deref(), Some("challenge-1"));
+        assert_eq!(vp.verifiable_credential[0].id, issued.id);
+        assert!(report.ends_with("Presentation is valid"));
+
+        // The request is answered, presenting again is refused
+        present_credential(&mut server, holder_id, &present).await;
+        assert_eq!(
+            drain(&mut holder_recv).await,
+            vec![format!("No presentation request from {}", verifier_id)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_holder_presentation_must_carry_the_requested_challenge() {
+        let (mut server, _join) = spawn_main_loop();
+
+        let (holder, mut holder_recv) = test_handle(server.next_id());
+        let holder_id = holder.id;
+        server.send(ToDelivery::NewClient(holder)).await;
+        server
+            .send(ToDelivery::DidDocument(
+                holder_id,
+                sample_document("did:example:alice"),
+            ))
+            .await;
+        let mut verifiers = Vec::new();
+        for challenge in ["challenge-1", "challenge-2"] {
+            let (verifier, verifier_recv) = test_handle(server.next_id());
+            let verifier_id = verifier.id;
+            server.send(ToDelivery::NewClient(verifier)).await;
+            server
+                .send(ToDelivery::NewRole(verifier_id, ClientRole::Verifier))
+                .await;
+            server
+                .send(ToDelivery::RequestPresentation(
+                    verifier_id,
+                    format!("did:example:alice {}", challenge).into_bytes(),
+                ))
+                .await;
+            verifiers.push((verifier_id, verifier_recv));
+        }
+        drain(&mut holder_recv).await;
+        for (_, verifier_recv) in verifiers.iter_mut() {
+            drain(verifier_recv).await;
+        }
+
+        let signer = deterministic_keypair(FIXTURE_SEED);
+        let present = |challenge: &str| {
+            let vp = VerifiablePresentation::with_challenge(
+                "did:example:alice",
+                vec![],
+                &signer,
+                Some(challenge),
+            )
+            .unwrap();
+            ToDelivery::ShowVP(holder_id, serde_json::to_vec(&vp).unwrap())
+        };
+
+        // A presentation made for an old request answers none of them
+        server.send(present("old-challenge")).await;
+        assert_eq!(
+            drain(&mut holder_recv).await,
+            vec![
+                "Presentation doesn't answer any request, \
+                 it must carry the challenge it was requested with"
+            ]
+        );
+        for (_, verifier_recv) in verifiers.iter_mut() {
+            assert!(drain(verifier_recv).await.is_empty());
+        }
+
+        // Only the verifier whose challenge it carries is answered
+        server.send(present("challenge-2")).await;
+        let (first_id, first_recv) = &mut verifiers[0];
+        let first_id = *first_id;
+        assert!(drain(first_recv).await.is_empty());
+        let (second_id, second_recv) = &mut verifiers[1];
+        let received = drain(second_recv).await;
+        assert_eq!(received.len(), 1);
+        assert!(received[0].contains("\"nonce\": \"challenge-2\""));
+        assert_eq!(
+            drain(&mut holder_recv).await,
+            vec![format!("Presentation sent to {}", second_id)]
+        );
+
+        // The other request is still pending
+        server.send(present("challenge-1")).await;
+        assert_eq!(
+            drain(&mut holder_recv).await,
+            vec![format!("Presentation sent to {}", first_id)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_forgotten_credential_cannot_be_presented() {
+        let (mut server, _join) = spawn_main_loop();
+
+        let (holder, mut holder_recv) = test_handle(server.next_id());
+        let holder_id = holder.id;
+        server.send(ToDelivery::NewClient(holder)).await;
+        server
+            .send(ToDelivery::DidDocument(
+                holder_id,
+                sample_document("did:example:alice"),
+            ))
+            .await;
+        let (issuer, mut issuer_recv) = test_handle(server.next_id());
+        let issuer_id = issuer.id;
+        server.send(ToDelivery::NewClient(issuer)).await;
+        server
+            .send(ToDelivery::NewRole(issuer_id, ClientRole::Issuer))
+            .await;
+        server
+            .send(ToDelivery::IssueVC(
+                issuer_id,
+                b"did:example:alice:750".to_vec(),
+            ))
+            .await;
+        let issued: VerifiableCredential = drain(&mut issuer_recv)
+            .await
+            .iter()
+            .find_map(|msg| serde_json::from_str(msg).ok())
+            .expect("Issuer should receive the credential");
+        let (verifier, mut verifier_recv) = test_handle(server.next_id());
+        let verifier_id = verifier.id;
+        server.send(ToDelivery::NewClient(verifier)).await;
+        server
+            .send(ToDelivery::NewRole(verifier_id, ClientRole::Verifier))
+            .await;
+        server
+            .send(ToDelivery::RequestPresentation(
+                verifier_id,
+                b"did:example:alice".to_vec(),
+            ))
+            .await;
+        drain(&mut holder_recv).await;
+        drain(&mut verifier_recv).await;
+
+        server
+            .send(ToDelivery::Forget(
+                holder_id,
+                issued.id.clone().into_bytes(),
+            ))
+            .await;
+        server.send(ToDelivery::Wallet(holder_id)).await;
+        assert_eq!(
+            drain(&mut holder_recv).await,
+            vec![
+                format!("Credential {} removed from your wallet", issued.id),
+                "Your wallet is empty".to_string(),
+            ]
+        );
+
+        let present = format!("{} {}", verifier_id.0, issued.id);
+        present_credential(&mut server, holder_id, &present).await;
+        assert_eq!(
+            drain(&mut holder_recv).await,
+            vec![format!("Credential {} is not in your wallet", issued.id)]
+        );
+        assert!(drain(&mut verifier_recv).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_rotated_issuer_key_signs_renewals() {
+        let (mut server, _join) = spawn_main_loop();
+
+        let (issuer, mut issuer_recv) = test_handle(server.next_id());
+        let issuer_id = issuer.id;
+        server.send(ToDelivery::NewClient(issuer)).await;
+        server.send(ToDelivery::RotateIssuer(issuer_id)).await;
+        assert_eq!(
+            drain(&mut issuer_recv).await.last().unwrap(),
+            ASSIGN_ROLE_FIRST
+        );
+
+        server
+            .send(ToDelivery::NewRole(issuer_id, ClientRole::Issuer))
+            .await;
+        server
+            .send(ToDelivery::IssueVC(
+                issuer_id,
+                b"did:example:alice:750".to_vec(),
+            ))
+            .await;
+        let issued: VerifiableCredential = drain(&mut issuer_recv)
+            .await
+            .iter()
+            .find_map(|msg| serde_json::from_str(msg).ok())
+            .unwrap();
+        assert_eq!(issued.proof.verification_method, "did:example:issuer#key1");
+
+        server.send(ToDelivery::RotateIssuer(issuer_id)).await;
+        assert!(drain(&mut issuer_recv).await[0]
+            .starts_with("Issuer key rotated to did:example:issuer#key2"));
+
+        server
+            .send(ToDelivery::RenewVC(issuer_id, issued.id.into_bytes()))
+            .await;
+        let renewed: VerifiableCredential =
+            serde_json::from_str(&drain(&mut issuer_recv).await[0]).unwrap();
+        assert_eq!(renewed.proof.verification_method, "did:example:issuer#key2");
+
+        // Both keys are published while the old one is in its grace period
+        server
+            .send(ToDelivery::ShowDocument(
+                issuer_id,
+                ISSUER_DID.as_bytes().to_vec(),
+            ))
+            .await;
+        let document = DidDocument::from_json(&drain(&mut issuer_recv).await[0]).unwrap();
+        assert_eq!(document.verification_method.len(), 2);
+        assert!(document.verification_method[0].expires.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_only_issuers_publish_a_credential_registry() {
+        let (mut server, _join) = spawn_main_loop();
+        let (client, mut client_recv) = test_handle(server.next_id());
+        let client_id = client.id;
+        server.send(ToDelivery::NewClient(client)).await;
+        drain(&mut client_recv).await;
+
+        let (mut document, _) = create_did_document(server.did_method());
+        document.add_service(Service::credential_registry(
+            &document.id,
+            "https://example.com/schemas/",
+        ));
+        server
+            .send(ToDelivery::DidDocument(client_id, document.clone()))
+            .await;
+        assert_eq!(
+            drain(&mut client_recv).await,
+            vec!["Only issuers can publish a credential registry"]
+        );
+
+        server
+            .send(ToDelivery::NewRole(client_id, ClientRole::Issuer))
+            .await;
+        server
+            .send(ToDelivery::DidDocument(client_id, document.clone()))
+            .await;
+        server
+            .send(ToDelivery::ShowDocument(
+                client_id,
+                document.id.clone().into_bytes(),
+            ))
+            .await;
+        let stored = DidDocument::from_json(drain(&mut client_recv).await.last().unwrap()).unwrap();
+        assert_eq!(
+            stored
+                .get_service_by_type(CREDENTIAL_REGISTRY_TYPE)
+                .unwrap()
+                .service_endpoint,
+            "https://example.com/schemas/"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_non_utf8_argument_keeps_main_loop_running() {
+        let (mut server, join) = spawn_main_loop();
+        let (client, mut client_recv) = test_handle(server.next_id());
+        let client_id = client.id;
+        server.send(ToDelivery::NewClient(client)).await;
+        drain(&mut client_recv).await;
+
+        server
+            .send(ToDelivery::ShowDocument(client_id, vec![0xff, 0xfe]))
+            .await;
+        server
+            .send(ToDelivery::VerifyDID(client_id, vec![b'd', 0xc3]))
+            .await;
+        assert_eq!(
+            drain(&mut client_recv).await,
+            vec![
+                "Invalid argument: not valid UTF-8",
+                "Invalid argument: not valid UTF-8"
+            ]
+        );
+
+        // Still serving requests
+        server.send(ToDelivery::MyInfo(client_id)).await;
+        assert_eq!(drain(&mut client_recv).await.len(), 1);
+        assert!(!join.is_finished());
+    }
+
+    #[tokio::test]
+    async fn test_created_documents_are_stored_with_a_valid_proof() {
+        let (mut server, _join) = spawn_main_loop();
+        let (client, mut client_recv) = test_handle(server.next_id());
+        let client_id = client.id;
+        server.send(ToDelivery::NewClient(client)).await;
+        drain(&mut client_recv).await;
+
+        let mut dids = Vec::new();
+        for _ in 0..3 {
+            // Same steps as the CreateDID handler
+            let (mut document, signer) = create_did_document("example");
+            let did = document.id.clone();
+            document.sign(&signer, &format!("{}#key1", did)).unwrap();
+            server
+                .send(ToDelivery::DidDocument(client_id, document))
+                .await;
+            dids.push(did);
+        }
+        drain(&mut client_recv).await;
+
+        for did in dids {
+            server
+                .send(ToDelivery::VerifyDID(client_id, did.clone().into_bytes()))
+                .await;
+            let stored = DidDocument::from_json(&drain(&mut client_recv).await[0]).unwrap();
+            assert_eq!(stored.id, did);
+            assert!(stored.verify_proof(), "{} has no valid proof", did);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_is_signed_by_the_issuer() {
+        let (mut server, _join) = spawn_main_loop();
+        let (client, mut client_recv) = test_handle(server.next_id());
+        let client_id = client.id;
+        server.send(ToDelivery::NewClient(client)).await;
+        server
+            .send(ToDelivery::NewRole(client_id, ClientRole::Issuer))
+            .await;
+        drain(&mut client_recv).await;
+
+        server.send(ToDelivery::Snapshot(client_id)).await;
+        let snapshot: SignedSnapshot =
+            serde_json::from_str(&drain(&mut client_recv).await[0]).unwrap();
+        let issuer_document = snapshot
+            .snapshot
+            .documents
+            .iter()
+            .find(|doc| doc.id == ISSUER_DID)
+            .unwrap();
+        let issuer_key = decode_multibase_to_public_key(
+            issuer_document.verification_method[0]
+                .public_key_base58
+                .as_ref()
+                .unwrap(),
+        )
+        .unwrap();
+        assert!(verify_snapshot(&snapshot, &issuer_key));
+    }
+
+    // Challenge a client for its DID and answer with the nonce signed by the
+    // given key, returning what the verifier is told
+    async fn challenge_response(signer_of: impl FnOnce(SigningKey) -> SigningKey) -> String {
+        let (mut server, _join) = spawn_main_loop();
+        let (verifier, mut verifier_recv) = test_handle(server.next_id());
+        let verifier_id = verifier.id;
+        let (target, mut target_recv) = test_handle(server.next_id());
+        let target_id = target.id;
+        server.send(ToDelivery::NewClient(verifier)).await;
+        server.send(ToDelivery::NewClient(target)).await;
+        server
+            .send(ToDelivery::NewRole(verifier_id, ClientRole::Verifier))
+            .await;
+        let (document, signer) = create_did_document("example");
+        let did = document.id.clone();
+        server
+            .send(ToDelivery::DidDocument(target_id, document))
+            .await;
+        drain(&mut verifier_recv).await;
+        drain(&mut target_recv).await;
+
+        let args = format!("{} {}", target_id.0, did);
+        server
+            .send(ToDelivery::Challenge(verifier_id, args.into_bytes()))
+            .await;
+        assert_eq!(
+            drain(&mut verifier_recv).await,
+            vec![format!(
+                "Challenged {} to prove control of {}",
+                target_id, did
+            )]
+        );
+        let challenge = drain(&mut target_recv).await.remove(0);
+        let nonce = challenge
+            .split_once("sign ")
+            .and_then(|(_, rest)| rest.split_whitespace().next())
+            .unwrap()
+            .to_string();
+
+        let signature = signer_of(signer).sign(nonce.as_bytes());
+        let args = format!("{} {}", verifier_id.0, signature.to_bytes().to_base58());
+        server
+            .send(ToDelivery::Respond(target_id, args.clone().into_bytes()))
+            .await;
+        assert_eq!(
+            drain(&mut target_recv).await,
+            vec![format!("Response sent to {}", verifier_id)]
+        );
+
+        // A challenge is answered only once
+        server
+            .send(ToDelivery::Respond(target_id, args.into_bytes()))
+            .await;
+        assert_eq!(
+            drain(&mut target_recv).await,
+            vec![format!("No challenge from {}", verifier_id)]
+        );
+
+        drain(&mut verifier_recv).await.remove(0)
+    }
+
+    #[tokio::test]
+    async fn test_direct_message_reaches_the_did_owner() {
+        let (mut server, _join) = spawn_main_loop();
+        let (sender, mut sender_recv) = test_handle(server.next_id());
+        let sender_id = sender.id;
+        let (owner, mut owner_recv) = test_handle(server.next_id());
+        let owner_id = owner.id;
+        server.send(ToDelivery::NewClient(sender)).await;
+        server.send(ToDelivery::NewClient(owner)).await;
+        let did = "did:example:alice";
+        server
+            .send(ToDelivery::DidDocument(owner_id, sample_document(did)))
+            .await;
+        drain(&mut sender_recv).await;
+        drain(&mut owner_recv).await;
+
+        let dm = |text: &str| ToDelivery::DirectMessage(sender_id, text.as_bytes().to_vec());
+        server.send(dm("did:example:alice hello there")).await;
+        assert_eq!(
+            drain(&mut sender_recv).await,
+            vec!["Message delivered to did:example:alice"]
+        );
+        assert_eq!(
+            drain(&mut owner_recv).await,
+            vec![format!(
+                "Message for did:example:alice from {}: hello there",
+                sender_id
+            )]
+        );
+
+        server.send(dm("did:example:nobody hello")).await;
+        assert_eq!(drain(&mut sender_recv).await, vec!["unknown DID"]);
+
+        server.send(ToDelivery::ClientLeft(owner_id)).await;
+        server.send(dm("did:example:alice are you there?")).await;
+        assert_eq!(drain(&mut sender_recv).await, vec!["recipient offline"]);
+    }
+
+    #[tokio::test]
+    async fn test_challenge_answered_with_the_did_key_proves_control() {
+        let outcome = challenge_response(|signer| signer).await;
+        assert!(
+            outcome.contains("proved control of did:example:"),
+            "{}",
+            outcome
+        );
+    }
+
+    #[tokio::test]
+    async fn test_challenge_answered_with_another_key_fails() {
+        let outcome = challenge_response(|_| deterministic_keypair(FIXTURE_SEED)).await;
+        assert!(
+            outcome.contains("failed to prove control of did:example:"),
+            "{}",
+            outcome
+        );
+        assert!(outcome.ends_with("signature does not match its keys"));
+    }
+
+    #[tokio::test]
+    async fn test_only_issuers_renew_credentials() {
+        let (mut server, _join) = spawn_main_loop();
+
+        let (holder, mut holder_recv) = test_handle(server.next_id());
+        let holder_id = holder.id;
+        server.send(ToDelivery::NewClient(holder)).await;
+        server
+            .send(ToDelivery::NewRole(holder_id, ClientRole::Holder))
+            .await;
+        server
+            .send(ToDelivery::RenewVC(holder_id, b"urn:uuid:1".to_vec()))
+            .await;
+
+        assert!(drain(&mut holder_recv)
+            .await
+            .contains(&"Only issuers can renew credentials".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_show_document_resolves_unambiguous_prefixes() {
+        let (mut server, _join) = spawn_main_loop();
+        let (client, mut client_recv) = test_handle(server.next_id());
+        let client_id = client.id;
+        server.send(ToDelivery::NewClient(client)).await;
+        for did in ["did:example:alice", "did:example:albert", "did:example:bob"] {
+            server
+                .send(ToDelivery::DidDocument(client_id, sample_document(did)))
+                .await;
+        }
+        drain(&mut client_recv).await;
+
+        let mut show = async |prefix: &str| {
+            server
+                .send(ToDelivery::ShowDocument(
+                    client_id,
+                    prefix.as_bytes().to_vec(),
+                ))
+                .await;
+            drain(&mut client_recv).await.join("\n")
+        };
+
+        let found = DidDocument::from_json(&show("did:example:b").await).unwrap();
+        assert_eq!(found.id, "did:example:bob");
+        assert_eq!(show("did:example:al").await, "ambiguous prefix (2 matches)");
+        assert_eq!(show("did:example:carol").await, "no match");
+    }
+
+    #[tokio::test]
+    async fn test_resign_refreshes_the_proof_after_an_edit() {
+        let (mut server, _join) = spawn_main_loop();
+        let (owner, mut owner_recv) = test_handle(server.next_id());
+        let owner_id = owner.id;
+        let (other, mut other_recv) = test_handle(server.next_id());
+        let other_id = other.id;
+        server.send(ToDelivery::NewClient(owner)).await;
+        server.send(ToDelivery::NewClient(other)).await;
+        let signer = deterministic_keypair(FIXTURE_SEED);
+        let mut document = sample_document("did:example:alice");
+        document.sign(&signer, "did:example:alice#key1").unwrap();
+        server
+            .send(ToDelivery::DidDocument(owner_id, document.clone()))
+            .await;
+
+        // Adding a service leaves the embedded proof stale
+        document.add_service(Service {
+            id: "did:example:alice#messaging".to_string(),
+            type_: "MessagingService".to_string(),
+            service_endpoint: "https://example.com/messages".to_string(),
+        });
+        server
+            .send(ToDelivery::DidDocument(owner_id, document))
+            .await;
+        drain(&mut owner_recv).await;
+        drain(&mut other_recv).await;
+        let stored = |data: &[String]| DidDocument::from_json(data.last().unwrap()).unwrap();
+        server
+            .send(ToDelivery::ShowDocument(
+                owner_id,
+                b"did:example:alice".to_vec(),
+            ))
+            .await;
+        assert!(!stored(&drain(&mut owner_recv).await).verify_proof());
+
+        resign(&mut server, other_id, "did:example:alice").await;
+        assert_eq!(
+            drain(&mut other_recv).await,
+            vec!["Failed to re-sign did:example:alice: only its owner can re-sign it"]
+        );
+        // Nor can it store a document it signed itself
+        let mut signed = sample_document("did:example:alice");
+        signed.sign(&signer, "did:example:alice#key1").unwrap();
+        server.send(ToDelivery::Resigned(other_id, signed)).await;
+        assert_eq!(
+            drain(&mut other_recv).await,
+            vec!["Failed to re-sign did:example:alice: only its owner can re-sign it"]
+        );
+
+        resign(&mut server, owner_id, "did:example:alice").await;
+        assert_eq!(
+            drain(&mut owner_recv).await,
+            vec!["did:example:alice re-signed, now at version 3"]
+        );
+        server
+            .send(ToDelivery::ShowDocument(
+                owner_id,
+                b"did:example:alice".to_vec(),
+            ))
+            .await;
+        let resigned = stored(&drain(&mut owner_recv).await);
+        assert!(resigned.verify_proof());
+        assert_eq!(resigned.service.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_only_the_joining_client_is_welcomed() {
+        let (mut server, _join) = spawn_main_loop();
+        let (first, mut first_recv) = test_handle(server.next_id());
+        server.send(ToDelivery::NewClient(first)).await;
+        assert_eq!(drain(&mut first_recv).await, vec!["Welcome!"]);
+
+        let (second, mut second_recv) = test_handle(server.next_id());
+        server.send(ToDelivery::NewClient(second)).await;
+        assert_eq!(drain(&mut second_recv).await, vec!["Welcome!"]);
+        assert!(drain(&mut first_recv).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_dead_clients_are_evicted_after_a_failed_broadcast() {
+        let (mut server, _join) = spawn_main_loop();
+        let (sender, mut sender_recv) = test_handle(server.next_id());
+        let sender_id = sender.id;
+        let (dead, mut dead_recv) = test_handle(server.next_id());
+        server.send(ToDelivery::NewClient(sender)).await;
+        server.send(ToDelivery::NewClient(dead)).await;
+        drain(&mut sender_recv).await;
+        drain(&mut dead_recv).await;
+
+        // The actor is gone without telling the main loop
+        drop(dead_recv);
+        server
+            .send(ToDelivery::Message(sender_id, b"anyone there?".to_vec()))
+            .await;
+
+        server.send(ToDelivery::Stats(sender_id)).await;
+        let stats = drain(&mut sender_recv).await.join("\r\n");
+        assert!(stats.contains("Clients: 1 "), "{}", stats);
+    }
+
+    #[tokio::test]
+    async fn test_stats_counts_dids_and_clients() {
+        let (mut server, _join) = spawn_main_loop();
+        let (holder, mut holder_recv) = test_handle(server.next_id());
+        let holder_id = holder.id;
+        let (visitor, mut visitor_recv) = test_handle(server.next_id());
+        let visitor_id = visitor.id;
+        server.send(ToDelivery::NewClient(holder)).await;
+        server.send(ToDelivery::NewClient(visitor)).await;
+        server
+            .send(ToDelivery::NewRole(holder_id, ClientRole::Holder))
+            .await;
+        server.send(ToDelivery::Ephemeral(visitor_id)).await;
+        server
+            .send(ToDelivery::DidDocument(
+                holder_id,
+                sample_document("did:example:alice"),
+            ))
+            .await;
+        server
+            .send(ToDelivery::DidDocument(
+                visitor_id,
+                sample_document("did:example:bob"),
+            ))
+            .await;
+        drain(&mut holder_recv).await;
+        drain(&mut visitor_recv).await;
+
+        // The server's issuer DID is counted too
+        server.send(ToDelivery::Stats(holder_id)).await;
+        let stats = drain(&mut holder_recv).await.join("\r\n");
+        assert!(stats.contains("DIDs: 3"), "{}", stats);
+        assert!(stats.contains("Credentials: 0"), "{}", stats);
+        assert!(
+            stats.contains("Clients: 2 (holders 1, issuers 0, verifiers 0, no role 1)"),
+            "{}",
+            stats
+        );
+
+        // The ephemeral client's DID is deleted when it leaves
+        server.send(ToDelivery::ClientLeft(visitor_id)).await;
+        server.send(ToDelivery::Stats(holder_id)).await;
+        let stats = drain(&mut holder_recv).await.join("\r\n");
+        assert!(stats.contains("DIDs: 2"), "{}", stats);
+        assert!(
+            stats.contains("Clients: 1 (holders 1, issuers 0, verifiers 0, no role 0)"),
+            "{}",
+            stats
+        );
+    }
+
+    #[tokio::test]
+    async fn test_roleless_clients_are_asked_to_assign_a_role() {
+        let (mut server, _join) = spawn_main_loop();
+        let (client, mut client_recv) = test_handle(server.next_id());
+        let client_id = client.id;
+        server.send(ToDelivery::NewClient(client)).await;
+        drain(&mut client_recv).await;
+
+        server
+            .send(ToDelivery::IssueVC(
+                client_id,
+                b"did:example:alice 700".to_vec(),
+            ))
+            .await;
+        assert_eq!(drain(&mut client_recv).await, vec![ASSIGN_ROLE_FIRST]);
+
+        // Role-agnostic commands still work
+        server.send(ToDelivery::MyInfo(client_id)).await;
+        assert_eq!(drain(&mut client_recv).await, vec!["Hello \"Anonymous\""]);
+    }
+
+    #[tokio::test]
+    async fn test_verifier_verifies_presentation() {
+        let (mut server, _join) = spawn_main_loop();
+
+        let (issuer, mut issuer_recv) = test_handle(server.next_id());
+        let (verifier, mut verifier_recv) = test_handle(server.next_id());
+        let (issuer_id, verifier_id) = (issuer.id, verifier.id);
+        server.send(ToDelivery::NewClient(issuer)).await;
+        server.send(ToDelivery::NewClient(verifier)).await;
+        server
+            .send(ToDelivery::NewRole(issuer_id, ClientRole::Issuer))
+            .await;
+        server
+            .send(ToDelivery::NewRole(verifier_id, ClientRole::Verifier))
+            .await;
+
+        // The holder's document is registered, the credential issued by the server
+        let holder = "did:example:alice";
+        server
+            .send(ToDelivery::DidDocument(issuer_id, sample_document(holder)))
+            .await;
+        server
+            .send(ToDelivery::IssueVC(
+                issuer_id,
+                b"did:example:alice:750".to_vec(),
+            ))
+            .await;
+        let vc: VerifiableCredential = drain(&mut issuer_recv)
+            .await
+            .iter()
+            .find_map(|msg| serde_json::from_str(msg).ok())
+            .unwrap();
+        let mut tampered = vc.clone();
+        tampered.credential_subject.credit_score = 850;
+
+        let signer = deterministic_keypair(FIXTURE_SEED);
+        let vp = VerifiablePresentation::new(holder, vec![vc, tampered], &signer).unwrap();
+        drain(&mut verifier_recv).await;
+        server
+            .send(ToDelivery::ShowVP(
+                verifier_id,
+                serde_json::to_vec(&vp).unwrap(),
+            ))
+            .await;
+
+        let report = drain(&mut verifier_recv).await.join("\n");
+        assert!(report.contains(": Valid"));
+        assert!(report.contains(": InvalidProof"));
+        assert!(report.ends_with("Presentation is invalid"));
+    }
+
+    #[tokio::test]
+    async fn test_import_stores_only_valid_documents() {
+        let (mut server, _join) = spawn_main_loop();
+
+        let (operator, mut operator_recv) = test_handle(server.next_id());
+        let operator_id = operator.id;
+        server.send(ToDelivery::NewClient(operator)).await;
+        server
+            .send(ToDelivery::NewRole(operator_id, ClientRole::Issuer))
+            .await;
+        drain(&mut operator_recv).await;
+
+        let json = format!(
+            "[{}, {{\"id\": \"did:example:broken\"}}, {}]",
+            sample_document("did:example:alice")
+                .to_json_compact()
+                .unwrap(),
+            sample_document("did:example:bob")
+                .to_json_compact()
+                .unwrap(),
+        );
+        server
+            .send(ToDelivery::Import(operator_id, json.into_bytes()))
+            .await;
+
+        let summary = drain(&mut operator_recv).await.join("\n");
+        assert!(summary.contains("Document 0: imported did:example:alice"));
+        assert!(summary.contains("Document 1: failed"));
+        assert!(summary.contains("Document 2: imported did:example:bob"));
+        assert!(summary.ends_with("Imported 2 of 3 documents"));
+
+        for (did, stored) in [
+            ("did:example:alice", true),
+            ("did:example:broken", false),
+            ("did:example:bob", true),
+        ] {
+            server
+                .send(ToDelivery::ShowDocument(
+                    operator_id,
+                    did.as_bytes().to_vec(),
+                ))
+                .await;
+            let found = drain(&mut operator_recv).await.join("\n");
+            assert_eq!(found != "no match", stored, "{}", did);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_import_never_replaces_a_registered_did() {
+        let (mut server, _join) = spawn_main_loop();
+        let (owner, mut owner_recv) = test_handle(server.next_id());
+        let owner_id = owner.id;
+        let (operator, mut operator_recv) = test_handle(server.next_id());
+        let operator_id = operator.id;
+        server.send(ToDelivery::NewClient(owner)).await;
+        server.send(ToDelivery::NewClient(operator)).await;
+        let (document, _) = create_did_document("example");
+        let did = document.id.clone();
+        let stored = document.to_json().unwrap();
+        server
+            .send(ToDelivery::DidDocument(owner_id, document))
+            .await;
+        drain(&mut owner_recv).await;
+        drain(&mut operator_recv).await;
+
+        // Same DID, another key
+        let json = format!("[{}]", sample_document(&did).to_json_compact().unwrap());
+        server
+            .send(ToDelivery::Import(operator_id, json.clone().into_bytes()))
+            .await;
+        assert_eq!(
+            drain(&mut operator_recv).await,
+            vec!["Only issuers can import documents"]
+        );
+
+        server
+            .send(ToDelivery::NewRole(operator_id, ClientRole::Issuer))
+            .await;
+        drain(&mut operator_recv).await;
+        server
+            .send(ToDelivery::Import(operator_id, json.into_bytes()))
+            .await;
+        let summary = drain(&mut operator_recv).await.join("\n");
+        assert!(summary.contains(&format!(
+            "Document 0: failed, {} is already registered",
+            did
+        )));
+        assert!(summary.ends_with("Imported 0 of 1 documents"));
+
+        server
+            .send(ToDelivery::ShowDocument(
+                operator_id,
+                did.as_bytes().to_vec(),
+            ))
+            .await;
+        assert_eq!(drain(&mut operator_recv).await, vec![stored]);
+
+        // Messages to the DID still reach its owner
+        let dm = format!("{} hello", did);
+        server
+            .send(ToDelivery::DirectMessage(operator_id, dm.into_bytes()))
+            .await;
+        assert_eq!(
+            drain(&mut owner_recv).await,
+            vec![format!("Message for {} from {}: hello", did, operator_id)]
+        );
+    }
+
+    // Resolver of a single signed document counting how often it's asked
+    struct CountingResolver {
+        document: DidDocument,
+        calls: Arc<AtomicUsize>,
+    }
+
+    impl DidResolver for CountingResolver {
+        fn resolve(&self, did: &str) -> Option<DidDocument> {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+            (did == self.document.id).then(|| self.document.clone())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_benchverify_runs_the_configured_iterations() {
+        let did = "did:stub:bench";
+        let signer = deterministic_keypair(FIXTURE_SEED);
+        let key = encode_public_key_to_multibase(&signer.verifying_key()).unwrap();
+        let mut document = generate_document(did, Some(key)).unwrap();
+        document.sign(&signer, &format!("{}#key1", did)).unwrap();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut router = MethodRouter::new();
+        router.register(
+            "stub",
+            Box::new(CountingResolver {
+                document,
+                calls: calls.clone(),
+            }),
+        );
+        let config = MainLoopConfig {
+            bench_iterations: 7,
+            ..Default::default()
+        };
+        let (mut server, _join) = spawn_main_loop_for(router, config).unwrap();
+        let (client, mut client_recv) = test_handle(server.next_id());
+        let client_id = client.id;
+        server.send(ToDelivery::NewClient(client)).await;
+        drain(&mut client_recv).await;
+
+        server
+            .send(ToDelivery::BenchVerify(client_id, did.as_bytes().to_vec()))
+            .await;
+        let report = drain(&mut client_recv).await.remove(0);
+        assert!(
+            report.starts_with("Verified did:stub:bench 7 times (7 valid) in "),
+            "{}",
+            report
+        );
+        assert!(report.ends_with("on average"), "{}", report);
+        assert_eq!(calls.load(Ordering::Relaxed), 7);
+
+        let args = format!("{} 3", did);
+        server
+            .send(ToDelivery::BenchVerify(client_id, args.into_bytes()))
+            .await;
+        drain(&mut client_recv).await;
+        assert_eq!(calls.load(Ordering::Relaxed), 10);
+    }
+
+    #[tokio::test]
+    async fn test_hashdoc_returns_the_content_hash() {
+        let (mut server, _join) = spawn_main_loop();
+        let (client, mut client_recv) = test_handle(server.next_id());
+        let client_id = client.id;
+        server.send(ToDelivery::NewClient(client)).await;
+        let document = sample_document("did:example:alice");
+        server
+            .send(ToDelivery::DidDocument(client_id, document.clone()))
+            .await;
+        drain(&mut client_recv).await;
+
+        let expected = format!(
+            "Content hash of did:example:alice: {}",
+            document.content_hash()
+        );
+        for _ in 0..2 {
+            server
+                .send(ToDelivery::HashDocument(
+                    client_id,
+                    b"did:example:alice".to_vec(),
+                ))
+                .await;
+            assert_eq!(drain(&mut client_recv).await, vec![expected.clone()]);
+        }
+
+        server
+            .send(ToDelivery::HashDocument(
+                client_id,
+                b"did:example:nobody".to_vec(),
+            ))
+            .await;
+        assert_eq!(drain(&mut client_recv).await, vec!["Not found"]);
+    }
+
+    #[tokio::test]
+    async fn test_rels_lists_methods_under_each_relationship() {
+        let (mut server, _join) = spawn_main_loop();
+        let (client, mut client_recv) = test_handle(server.next_id());
+        let client_id = client.id;
+        server.send(ToDelivery::NewClient(client)).await;
+        // key1 is both an authentication and an assertion method
+        server
+            .send(ToDelivery::DidDocument(
+                client_id,
+                sample_document("did:example:alice"),
+            ))
+            .await;
+        drain(&mut client_recv).await;
+
+        server
+            .send(ToDelivery::Relationships(
+                client_id,
+                b"did:example:alice".to_vec(),
+            ))
+            .await;
+        let msgs = drain(&mut client_recv).await;
+        let rels: HashMap<String, Vec<String>> = serde_json::from_str(&msgs[0]).unwrap();
+        assert_eq!(rels["authentication"], vec!["did:example:alice#key1"]);
+        assert_eq!(rels["assertionMethod"], vec!["did:example:alice#key1"]);
+
+        server
+            .send(ToDelivery::Relationships(
+                client_id,
+                b"did:example:nobody".to_vec(),
+            ))
+            .await;
+        assert_eq!(drain(&mut client_recv).await, vec!["Not found"]);
+    }
+
+    #[cfg(not(feature = "ssi"))]
+    #[tokio::test]
+    async fn test_expand_needs_the_ssi_feature() {
+        let (mut server, _join) = spawn_main_loop();
+        let (client, mut client_recv) = test_handle(server.next_id());
+        let client_id = client.id;
+        server.send(ToDelivery::NewClient(client)).await;
+        server
+            .send(ToDelivery::DidDocument(
+                client_id,
+                sample_document("did:example:alice"),
+            ))
+            .await;
+        drain(&mut client_recv).await;
+
+        server
+            .send(ToDelivery::Expand(client_id, b"did:example:alice".to_vec()))
+            .await;
+        assert_eq!(
+            drain(&mut client_recv).await,
+            vec!["JSON-LD expansion needs the server built with the ssi feature"]
+        );
+    }
+
+    #[test]
+    fn test_bench_iterations_are_capped() {
+        assert_eq!(bench_iterations(None, 7), Ok(7));
+        assert_eq!(bench_iterations(Some("3"), 7), Ok(3));
+        assert_eq!(
+            bench_iterations(Some("1000000"), 7),
+            Ok(MAX_BENCH_ITERATIONS)
+        );
+        assert!(bench_iterations(Some("0"), 7).is_err());
+        assert!(bench_iterations(Some("many"), 7).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_methods_lists_registered_resolvers() {
+        let mut router = MethodRouter::new();
+        router.register("web", Box::new(DidStorage::new()));
+        let (mut server, _join) = spawn_main_loop_with(router);
+
+        let (client, mut client_recv) = test_handle(server.next_id());
+        let client_id = client.id;
+        server.send(ToDelivery::NewClient(client)).await;
+        drain(&mut client_recv).await;
+
+        server.send(ToDelivery::Methods(client_id)).await;
+        assert_eq!(
+            drain(&mut client_recv).await,
+            vec!["Supported DID methods: example, web"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unanswered_presentation_request_times_out() {
+        let config = MainLoopConfig {
+            presentation_timeout: Duration::from_millis(200),
+            ..Default::default()
+        };
+        let (mut server, _join) = spawn_main_loop_for(MethodRouter::new(), config).unwrap();
+
+        let (holder, mut holder_recv) = test_handle(server.next_id());
+        let holder_id = holder.id;
+        server.send(ToDelivery::NewClient(holder)).await;
+        server
+            .send(ToDelivery::DidDocument(
+                holder_id,
+                sample_document("did:example:alice"),
+            ))
+            .await;
+        let (verifier, mut verifier_recv) = test_handle(server.next_id());
+        let verifier_id = verifier.id;
+        server.send(ToDelivery::NewClient(verifier)).await;
+        server
+            .send(ToDelivery::NewRole(verifier_id, ClientRole::Verifier))
+            .await;
+        drain(&mut holder_recv).await;
+        drain(&mut verifier_recv).await;
+
+        server
+            .send(ToDelivery::RequestPresentation(
+                verifier_id,
+                b"did:example:alice challenge-1".to_vec(),
+            ))
+            .await;
+        assert_eq!(
+            drain(&mut holder_recv).await,
+            vec![format!(
+                "Presentation requested for did:example:alice by {} with challenge {}, \
+                 answer with c#svp<presentation> or c#present{} <credential_id>",
+                verifier_id, "challenge-1", verifier_id.0
+            )]
+        );
+
+        // The holder never answers
+        let replies = timeout(Duration::from_secs(2), async {
+            let mut replies = Vec::new();
+            while !replies.contains(&"presentation request timed out".to_string()) {
+                replies.extend(drain(&mut verifier_recv).await);
+            }
+            replies
+        })
+        .await
+        .expect("The request should time out");
+        assert_eq!(
+            replies,
+            vec![
+                "Presentation requested from did:example:alice",
+                "presentation request timed out"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_interrupt_cancels_presentation_request() {
+        let config = MainLoopConfig {
+            presentation_timeout: Duration::from_millis(500),
+            ..Default::default()
+        };
+        let (mut server, _join) = spawn_main_loop_for(MethodRouter::new(), config).unwrap();
+
+        let (holder, mut holder_recv) = test_handle(server.next_id());
+        let holder_id = holder.id;
+        server.send(ToDelivery::NewClient(holder)).await;
+        server
+            .send(ToDelivery::DidDocument(
+                holder_id,
+                sample_document("did:example:alice"),
+            ))
+            .await;
+        let (verifier, mut verifier_recv) = test_handle(server.next_id());
+        let verifier_id = verifier.id;
+        server.send(ToDelivery::NewClient(verifier)).await;
+        server
+            .send(ToDelivery::NewRole(verifier_id, ClientRole::Verifier))
+            .await;
+        server
+            .send(ToDelivery::RequestPresentation(
+                verifier_id,
+                b"did:example:alice".to_vec(),
+            ))
+            .await;
+        drain(&mut holder_recv).await;
+        drain(&mut verifier_recv).await;
+
+        server.send(ToDelivery::Interrupt(verifier_id)).await;
+        assert_eq!(
+            drain(&mut verifier_recv).await,
+            vec!["Interrupted, cancelled 1 pending operation(s)"]
+        );
+
+        // The cancelled request never times out
+        tokio::time::sleep(Duration::from_millis(600)).await;
+        assert!(drain(&mut verifier_recv).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_verifier_is_told_when_the_holder_disconnects() {
+        let config = MainLoopConfig {
+            presentation_timeout: Duration::from_millis(500),
+            ..Default::default()
+        };
+        let (mut server, _join) = spawn_main_loop_for(MethodRouter::new(), config).unwrap();
+
+        let (holder, _holder_recv) = test_handle(server.next_id());
+        let holder_id = holder.id;
+        server.send(ToDelivery::NewClient(holder)).await;
+        server
+            .send(ToDelivery::DidDocument(
+                holder_id,
+                sample_document("did:example:alice"),
+            ))
+            .await;
+        let (verifier, mut verifier_recv) = test_handle(server.next_id());
+        let verifier_id = verifier.id;
+        server.send(ToDelivery::NewClient(verifier)).await;
+        server
+            .send(ToDelivery::NewRole(verifier_id, ClientRole::Verifier))
+            .await;
+        server
+            .send(ToDelivery::RequestPresentation(
+                verifier_id,
+                b"did:example:alice".to_vec(),
+            ))
+            .await;
+        drain(&mut verifier_recv).await;
+
+        server.send(ToDelivery::ClientLeft(holder_id)).await;
+        assert_eq!(
+            drain(&mut verifier_recv).await,
+            vec![format!(
+                "Holder {} disconnected, presentation request cancelled",
+                holder_id
+            )]
+        );
+
+        // The dropped request never times out
+        tokio::time::sleep(Duration::from_millis(600)).await;
+        assert!(drain(&mut verifier_recv).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_interrupted_holder_cancels_what_it_was_asked_to_answer() {
+        let (mut server, _join) = spawn_main_loop();
+        let (holder, mut holder_recv) = test_handle(server.next_id());
+        let holder_id = holder.id;
+        let (verifier, mut verifier_recv) = test_handle(server.next_id());
+        let verifier_id = verifier.id;
+        server.send(ToDelivery::NewClient(holder)).await;
+        server.send(ToDelivery::NewClient(verifier)).await;
+        server
+            .send(ToDelivery::NewRole(verifier_id, ClientRole::Verifier))
+            .await;
+        let did = "did:example:alice";
+        server
+            .send(ToDelivery::DidDocument(holder_id, sample_document(did)))
+            .await;
+        server
+            .send(ToDelivery::RequestPresentation(
+                verifier_id,
+                did.as_bytes().to_vec(),
+            ))
+            .await;
+        let args = format!("{} {}", holder_id.0, did);
+        server
+            .send(ToDelivery::Challenge(verifier_id, args.into_bytes()))
+            .await;
+        drain(&mut holder_recv).await;
+        drain(&mut verifier_recv).await;
+
+        server.send(ToDelivery::Interrupt(holder_id)).await;
+        match holder_recv.recv().await {
+            Some(FromDelivery::Message(msg)) => assert_eq!(
+                msg,
+                b"Interrupted, cancelled 2 pending operation(s)".to_vec()
+            ),
+            _ => panic!("Expected the interrupt notice"),
+        }
+        assert!(matches!(
+            holder_recv.recv().await,
+            Some(FromDelivery::Prompt)
+        ));
+        assert_eq!(
+            drain(&mut verifier_recv).await,
+            vec![format!(
+                "Holder {} interrupted, presentation request cancelled",
+                holder_id
+            )]
+        );
+
+        let args = format!("{} signature", verifier_id.0);
+        server
+            .send(ToDelivery::Respond(holder_id, args.into_bytes()))
+            .await;
+        assert_eq!(
+            drain(&mut holder_recv).await,
+            vec![format!("No challenge from {}", verifier_id)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_server_log_lists_handled_commands() {
+        let (mut server, _join) = spawn_main_loop();
+        let (client, mut client_recv) = test_handle(server.next_id());
+        let client_id = client.id;
+        server.send(ToDelivery::NewClient(client)).await;
+        server
+            .send(ToDelivery::NewRole(client_id, ClientRole::Holder))
+            .await;
+
+        server.send(ToDelivery::ServerLog(client_id)).await;
+        assert_eq!(
+            drain(&mut client_recv).await.last().unwrap(),
+            "Only verifiers can read the server log"
+        );
+
+        server
+            .send(ToDelivery::NewRole(client_id, ClientRole::Verifier))
+            .await;
+        server.send(ToDelivery::MyInfo(client_id)).await;
+        server
+            .send(ToDelivery::ShowDocument(
+                client_id,
+                b"did:example:alice".to_vec(),
+            ))
+            .await;
+        drain(&mut client_recv).await;
+
+        server.send(ToDelivery::ServerLog(client_id)).await;
+        let log = drain(&mut client_recv).await.join("\n");
+        assert!(log.contains("Responding to who you are"), "{}", log);
+        assert!(
+            log.contains("look up document with id: did:example:alice"),
+            "{}",
+            log
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resolution_metadata_lists_operations_in_order() {
+        let (mut server, _join) = spawn_main_loop();
+        let (client, mut client_recv) = test_handle(server.next_id());
+        let client_id = client.id;
+        server.send(ToDelivery::NewClient(client)).await;
+        let (mut document, signer) = create_did_document("example");
+        let did = document.id.clone();
+        server
+            .send(ToDelivery::DidDocument(client_id, document.clone()))
+            .await;
+        drain(&mut client_recv).await;
+
+        document.add_service(Service {
+            id: format!("{}#messaging", did),
+            type_: "MessagingService".to_string(),
+            service_endpoint: "https://example.com/messages".to_string(),
+        });
+        let request = create_signed_update(&document, &signer).unwrap();
+        server
+            .send(ToDelivery::UpdateDocument(
+                client_id,
+                serde_json::to_vec(&request).unwrap(),
+            ))
+            .await;
+        drain(&mut client_recv).await;
+
+        server
+            .send(ToDelivery::Resolve(client_id, did.clone().into_bytes()))
+            .await;
+        let envelope: serde_json::Value =
+            serde_json::from_str(&drain(&mut client_recv).await[0]).unwrap();
+        let operations = envelope["didDocumentMetadata"]["operations"]
+            .as_array()
+            .unwrap();
+        assert_eq!(operations.len(), 2);
+        assert_eq!(operations[0]["operation"], "create");
+        assert_eq!(operations[0]["actor"], client_id.to_string());
+        assert_eq!(operations[1]["operation"], "update");
+        assert_eq!(operations[1]["actor"], format!("{}#key1", did));
+        assert!(operations[0]["timestamp"].as_str() <= operations[1]["timestamp"].as_str());
+    }
+
+    #[tokio::test]
+    async fn test_resolve_returns_result_envelope() {
+        let (mut server, _join) = spawn_main_loop();
+        let (client, mut client_recv) = test_handle(server.next_id());
+        let client_id = client.id;
+        server.send(ToDelivery::NewClient(client)).await;
+        server
+            .send(ToDelivery::DidDocument(
+                client_id,
+                sample_document("did:example:alice"),
+            ))
+            .await;
+        drain(&mut client_recv).await;
+
+        server
+            .send(ToDelivery::Resolve(
+                client_id,
+                b"did:example:alice".to_vec(),
+            ))
+            .await;
+        let envelope: serde_json::Value =
+            serde_json::from_str(&drain(&mut client_recv).await[0]).unwrap();
+        assert_eq!(envelope["didDocument"]["id"], "did:example:alice");
+        assert_eq!(
+            envelope["didResolutionMetadata"]["contentType"],
+            "application/did+ld+json"
+        );
+        assert!(envelope["didDocumentMetadata"]["created"].is_string());
+        assert!(envelope["didDocumentMetadata"]["updated"].is_string());
+
+        server
+            .send(ToDelivery::Resolve(
+                client_id,
+                b"did:example:nobody".to_vec(),
+            ))
+            .await;
+        let envelope: serde_json::Value =
+            serde_json::from_str(&drain(&mut client_recv).await[0]).unwrap();
+        assert!(envelope["didDocument"].is_null());
+        assert_eq!(envelope["didResolutionMetadata"]["error"], "notFound");
+    }
+
+    #[tokio::test]
+    async fn test_configured_did_method_is_used_for_created_dids() {
+        let config = |did_method: &str| MainLoopConfig {
+            did_method: did_method.to_string(),
+            ..Default::default()
+        };
+        assert!(spawn_main_loop_for(MethodRouter::new(), config("Demo")).is_err());
+
+        let (mut server, _join) = spawn_main_loop_for(MethodRouter::new(), config("demo")).unwrap();
+        let (client, mut client_recv) = test_handle(server.next_id());
+        let client_id = client.id;
+        server.send(ToDelivery::NewClient(client)).await;
+        drain(&mut client_recv).await;
+
+        let (document, _) = create_did_document(server.did_method());
+        assert!(document.id.starts_with("did:demo:"));
+        server
+            .send(ToDelivery::DidDocument(client_id, document.clone()))
+            .await;
+        drain(&mut client_recv).await;
+
+        // The configured method is served from the registry too
+        server.send(ToDelivery::Methods(client_id)).await;
+        assert_eq!(
+            drain(&mut client_recv).await,
+            vec!["Supported DID methods: demo, example"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_update_notifies_subscribed_verifier() {
+        let (mut server, _join) = spawn_main_loop();
+
+        let (holder, _holder_recv) = test_handle(server.next_id());
+        let (verifier, mut verifier_recv) = test_handle(server.next_id());
+        let (holder_id, verifier_id) = (holder.id, verifier.id);
+        server.send(ToDelivery::NewClient(holder)).await;
+        server.send(ToDelivery::NewClient(verifier)).await;
+        server
+            .send(ToDelivery::NewRole(verifier_id, ClientRole::Verifier))
+            .await;
+        server.send(ToDelivery::Subscribe(verifier_id)).await;
+
+        let did = "did:example:alice";
+        server
+            .send(ToDelivery::DidDocument(holder_id, sample_document(did)))
+            .await;
+        let mut updated = sample_document(did);
+        updated.service = None;
+        server
+            .send(ToDelivery::DidDocument(holder_id, updated))
+            .await;
+
+        let messages = drain(&mut verifier_recv).await;
+        assert!(messages.contains(&format!("New DID created: {}", did)));
+        assert!(messages.contains(&format!("DID updated, re-verify: {} version 2", did)));
+    }
+
+    #[tokio::test]
+    async fn test_ephemeral_client_dids_are_deleted_on_disconnect() {
+        let (mut server, _join) = spawn_main_loop();
+
+        let (ephemeral, _ephemeral_recv) = test_handle(server.next_id());
+        let (normal, _normal_recv) = test_handle(server.next_id());
+        let (observer, mut observer_recv) = test_handle(server.next_id());
+        let (ephemeral_id, normal_id, observer_id) = (ephemeral.id, normal.id, observer.id);
+        server.send(ToDelivery::NewClient(ephemeral)).await;
+        server.send(ToDelivery::NewClient(normal)).await;
+        server.send(ToDelivery::NewClient(observer)).await;
+
+        server.send(ToDelivery::Ephemeral(ephemeral_id)).await;
+        server
+            .send(ToDelivery::DidDocument(
+                ephemeral_id,
+                sample_document("did:example:alice"),
+            ))
+            .await;
+        server
+            .send(ToDelivery::DidDocument(
+                normal_id,
+                sample_document("did:example:bob"),
+            ))
+            .await;
+        server.send(ToDelivery::ClientLeft(ephemeral_id)).await;
+        server.send(ToDelivery::ClientLeft(normal_id)).await;
+        drain(&mut observer_recv).await;
+
+        for (did, stored) in [("did:example:alice", false), ("did:example:bob", true)] {
+            server
+                .send(ToDelivery::ShowDocument(
+                    observer_id,
+                    did.as_bytes().to_vec(),
+                ))
+                .await;
+            let found = drain(&mut observer_recv).await.join("\n");
+            assert_eq!(found != "no match", stored, "{}", did);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_returned_signing_key_signs_accepted_update() {
+        let (mut server, _join) = spawn_main_loop();
+
+        let (holder, mut holder_recv) = test_handle(server.next_id());
+        let holder_id = holder.id;
+        server.send(ToDelivery::NewClient(holder)).await;
+
+        let (document, signer) = create_did_document(server.did_method());
+        let notice = signing_key_notice(&document.id, &signer);
+        server
+            .send(ToDelivery::DidDocument(holder_id, document.clone()))
+            .await;
+        drain(&mut holder_recv).await;
+
+        // The client only has what it was sent
+        let (_, encoded_key) = notice.rsplit_once(": ").unwrap();
+        let key_bytes: [u8; 32] = encoded_key.from_base58().unwrap().try_into().unwrap();
+        let returned_key = SigningKey::from_bytes(&key_bytes);
+
+        let mut updated = document.clone();
+        updated.add_service(Service {
+            id: format!("{}#vcs", document.id),
+            type_: "VerifiableCredentialService".to_string(),
+            service_endpoint: "https://example.com/vc/".to_string(),
+        });
+        let request = create_signed_update(&updated, &returned_key).unwrap();
+        server
+            .send(ToDelivery::UpdateDocument(
+                holder_id,
+                serde_json::to_vec(&request).unwrap(),
+            ))
+            .await;
+        assert_eq!(
+            drain(&mut holder_recv).await,
+            vec!["Your Did Document is updated to version 2"]
+        );
+
+        // Someone else's key is refused
+        let request = create_signed_update(&updated, &deterministic_keypair(FIXTURE_SEED)).unwrap();
+        server
+            .send(ToDelivery::UpdateDocument(
+                holder_id,
+                serde_json::to_vec(&request).unwrap(),
+            ))
+            .await;
+        assert!(drain(&mut holder_recv).await[0].starts_with("Update rejected"));
+    }
+}
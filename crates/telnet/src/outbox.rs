@@ -0,0 +1,216 @@
+// Bounded queue of messages from the main loop to one client actor.
+//
+// The main loop must never wait on a single client, a slow client would
+// otherwise stall every other client and the registry with it. That's why
+// there's no "await until there is room" policy here: when the queue is full
+// the message is dropped, and the policy only decides which one.
+use std::collections::VecDeque;
+use std::fmt;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::Notify;
+
+use crate::client::FromDelivery;
+
+// Which message to drop when a client's outbox is full.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum SendPolicy {
+    // Reject the new message, the client keeps what it already has queued.
+    #[default]
+    DropNewest,
+    // Make room by dropping the oldest queued message, so the client always
+    // ends up with the most recent messages.
+    DropOldest,
+}
+
+struct State {
+    messages: VecDeque<FromDelivery>,
+    closed: bool,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    notify: Notify,
+    capacity: usize,
+    policy: SendPolicy,
+}
+
+// Sending half, owned by the `ClientHandle` in the main loop.
+pub struct OutboxSender {
+    shared: Arc<Shared>,
+}
+
+// Receiving half, owned by the client actor.
+pub struct OutboxReceiver {
+    shared: Arc<Shared>,
+}
+
+pub fn outbox(capacity: usize, policy: SendPolicy) -> (OutboxSender, OutboxReceiver) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            messages: VecDeque::with_capacity(capacity),
+            closed: false,
+        }),
+        notify: Notify::new(),
+        capacity,
+        policy,
+    });
+
+    (
+        OutboxSender {
+            shared: shared.clone(),
+        },
+        OutboxReceiver { shared },
+    )
+}
+
+impl OutboxSender {
+    // Queue a message without waiting, applying the policy when full.
+    pub fn try_send(&self, msg: FromDelivery) -> Result<(), io::Error> {
+        let mut state = self.shared.state.lock().expect("Outbox lock poisoned");
+        if state.closed {
+            return Err(io::Error::new(io::ErrorKind::BrokenPipe, "Client is dead"));
+        }
+
+        if state.messages.len() >= self.shared.capacity {
+            match self.shared.policy {
+                SendPolicy::DropNewest => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::BrokenPipe,
+                        "Can't keep up or dead",
+                    ));
+                }
+                SendPolicy::DropOldest => {
+                    state.messages.pop_front();
+                }
+            }
+        }
+        state.messages.push_back(msg);
+        drop(state);
+
+        self.shared.notify.notify_one();
+        Ok(())
+    }
+
+    // Whether the receiving actor is gone, so no message can reach it
+    // anymore. A full outbox is not closed.
+    pub fn is_closed(&self) -> bool {
+        self.shared
+            .state
+            .lock()
+            .expect("Outbox lock poisoned")
+            .closed
+    }
+
+    // Number of messages waiting for the client.
+    pub fn len(&self) -> usize {
+        self.shared
+            .state
+            .lock()
+            .expect("Outbox lock poisoned")
+            .messages
+            .len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl fmt::Debug for OutboxSender {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OutboxSender")
+            .field("queued", &self.len())
+            .field("capacity", &self.shared.capacity)
+            .field("policy", &self.shared.policy)
+            .finish()
+    }
+}
+
+impl OutboxReceiver {
+    // Wait for the next message, `None` once the sender is gone and
+    // everything queued has been received.
+    pub async fn recv(&mut self) -> Option<FromDelivery> {
+        loop {
+            {
+                let mut state = self.shared.state.lock().expect("Outbox lock poisoned");
+                if let Some(msg) = state.messages.pop_front() {
+                    return Some(msg);
+                }
+                if state.closed {
+                    return None;
+                }
+            }
+
+            self.shared.notify.notified().await;
+        }
+    }
+}
+
+fn close(shared: &Shared) {
+    shared.state.lock().expect("Outbox lock poisoned").closed = true;
+    shared.notify.notify_one();
+}
+
+impl Drop for OutboxSender {
+    fn drop(&mut self) {
+        close(&self.shared);
+    }
+}
+
+impl Drop for OutboxReceiver {
+    fn drop(&mut self) {
+        close(&self.shared);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(n: u8) -> FromDelivery {
+        FromDelivery::Message(vec![n])
+    }
+
+    async fn received(recv: &mut OutboxReceiver) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        while let Some(FromDelivery::Message(msg)) = recv.recv().await {
+            bytes.extend(msg);
+        }
+
+        bytes
+    }
+
+    #[tokio::test]
+    async fn test_drop_oldest_keeps_most_recent() {
+        let (send, mut recv) = outbox(3, SendPolicy::DropOldest);
+        for n in 0..5 {
+            send.try_send(message(n)).unwrap();
+        }
+        assert_eq!(send.len(), 3);
+        drop(send);
+
+        assert_eq!(received(&mut recv).await, vec![2, 3, 4]);
+    }
+
+    #[tokio::test]
+    async fn test_drop_newest_rejects_when_full() {
+        let (send, mut recv) = outbox(3, SendPolicy::DropNewest);
+        for n in 0..3 {
+            send.try_send(message(n)).unwrap();
+        }
+        assert!(send.try_send(message(3)).is_err());
+        drop(send);
+
+        assert_eq!(received(&mut recv).await, vec![0, 1, 2]);
+    }
+
+    #[tokio::test]
+    async fn test_send_fails_once_client_is_gone() {
+        let (send, recv) = outbox(3, SendPolicy::DropOldest);
+        drop(recv);
+
+        assert!(send.try_send(message(0)).is_err());
+    }
+}
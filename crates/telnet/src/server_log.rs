@@ -0,0 +1,68 @@
+// Recent log lines of the main loop, kept so a client can look at what the
+// server did during a live session with c#serverlog.
+use std::collections::VecDeque;
+
+// Most lines kept, older ones are dropped first
+pub const SERVER_LOG_CAPACITY: usize = 256;
+
+#[derive(Debug)]
+pub struct ServerLog {
+    lines: VecDeque<String>,
+    capacity: usize,
+}
+
+impl ServerLog {
+    pub fn new(capacity: usize) -> Self {
+        ServerLog {
+            lines: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn push(&mut self, line: String) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.lines.len() == self.capacity {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(line);
+    }
+
+    // Kept lines, oldest first
+    pub fn lines(&self) -> impl Iterator<Item = &str> {
+        self.lines.iter().map(String::as_str)
+    }
+}
+
+impl Default for ServerLog {
+    fn default() -> Self {
+        Self::new(SERVER_LOG_CAPACITY)
+    }
+}
+
+// Print a log line like println! and keep it in the given ServerLog
+macro_rules! log_line {
+    ($log:expr, $($arg:tt)*) => {{
+        let line = format!($($arg)*);
+        println!("{}", line);
+        $log.push(line);
+    }};
+}
+
+pub(crate) use log_line;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_oldest_lines_are_dropped() {
+        let mut log = ServerLog::new(2);
+        for n in 0..3 {
+            log_line!(log, "line {}", n);
+        }
+
+        assert_eq!(log.lines().collect::<Vec<_>>(), vec!["line 1", "line 2"]);
+    }
+}
@@ -1,27 +1,142 @@
-use std::io::{self, Read};
-use tokio_util::{bytes::Buf, codec::Decoder};
+use std::io;
+use tokio_util::{
+    bytes::{Buf, BufMut, BytesMut},
+    codec::{Decoder, Encoder},
+};
+
+// Line that ends a multi-line block
+const END_OF_BLOCK: &[u8] = b"c#end";
+// Upper bound of a single line
+const MAX_LINE_SIZE: usize = 64 * 1024;
+// Upper bound of a multi-line block, so a client can't grow it forever
+const MAX_BLOCK_SIZE: usize = 256 * 1024;
+// Upper bound of the data of a telnet subnegotiation
+const MAX_SUBNEGOTIATION_SIZE: usize = 1024;
+
+// Commands whose argument is the block of lines that follows them
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BlockCommand {
+    Import,
+    Validate,
+    Register,
+}
+
+impl BlockCommand {
+    fn from_line(line: &[u8]) -> Option<Self> {
+        match line {
+            b"c#import" => Some(BlockCommand::Import),
+            b"c#validate" => Some(BlockCommand::Validate),
+            b"c#register" => Some(BlockCommand::Register),
+            _ => None,
+        }
+    }
+
+    fn into_item(self, block: Vec<u8>) -> Item {
+        match self {
+            BlockCommand::Import => Item::Import(block),
+            BlockCommand::Validate => Item::Validate(block),
+            BlockCommand::Register => Item::Register(block),
+        }
+    }
+}
 
 pub struct TelnetCodec {
     current_line: Vec<u8>,
+    // Lines collected since a block-mode command, until END_OF_BLOCK
+    block: Option<(BlockCommand, Vec<u8>)>,
+    // Whether every character is also emitted as it arrives, lines are still
+    // collected for the commands
+    character_mode: bool,
 }
 
 impl TelnetCodec {
     pub fn new() -> Self {
         TelnetCodec {
             current_line: Vec::with_capacity(1024),
+            block: None,
+            character_mode: false,
         }
     }
+
+    // Switch between emitting whole lines only and emitting each character
+    pub fn set_character_mode(&mut self, character_mode: bool) {
+        self.character_mode = character_mode;
+    }
+
+    // Collect a line of the current block, the block is complete when the
+    // END_OF_BLOCK line is received
+    fn push_block_line(&mut self, line: Vec<u8>) -> Result<Option<Item>, io::Error> {
+        let Some((command, block)) = self.block.as_mut() else {
+            return Ok(None);
+        };
+        if line == END_OF_BLOCK {
+            let item = command.into_item(std::mem::take(block));
+            self.block = None;
+            return Ok(Some(item));
+        }
+
+        if block.len() + line.len() + 1 > MAX_BLOCK_SIZE {
+            self.block = None;
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Multi-line block is too large",
+            ));
+        }
+        block.extend_from_slice(&line);
+        block.push(b'\n');
+
+        Ok(None)
+    }
 }
 
 #[derive(Debug)]
 pub enum Item {
     ShowDID(Vec<u8>),
+    Resolve(Vec<u8>),
+    QR(Vec<u8>),
     VerifyDID(Vec<u8>),
     AssignRole(Vec<u8>),
     WhoAmI,
-    ShowVP, // Show Verifiable Presentation
-    CreateDID,
+    ShowVP(Vec<u8>), // Show or verify a Verifiable Presentation
+    RequestPresentation(Vec<u8>),
+    Present(Vec<u8>),
+    CreateDID {
+        return_key: bool,
+        schema_endpoint: Option<String>,
+    },
+    UpdateDID(Vec<u8>),
+    Subscribe,
+    Unsubscribe,
+    IssueVC(Vec<u8>),
+    RenewVC(Vec<u8>),
+    Resign(Vec<u8>),
+    RotateIssuer,
+    Snapshot,
+    Import(Vec<u8>),   // Content of a c#import block
+    Validate(Vec<u8>), // Content of a c#validate block
+    Register(Vec<u8>), // Content of a c#register block
+    Methods,
+    Wallet,
+    Forget(Vec<u8>),
+    Challenge(Vec<u8>),
+    BenchVerify(Vec<u8>),
+    HashDocument(Vec<u8>),
+    Expand(Vec<u8>),
+    Relationships(Vec<u8>),
+    Respond(Vec<u8>),
+    DirectMessage(Vec<u8>),
+    ServerLog,
+    Stats,
+    Ephemeral,
+    Hex(Vec<u8>),
+    Schema(Vec<u8>),
+    Options,
+    Trace(Vec<u8>),
+    LineMode(Vec<u8>),
+    VerifySignature(Vec<u8>),
     Line(Vec<u8>),
+    // A character of the current line, in character mode
+    Character(u8),
     SE,
     DataMark,
     Break,
@@ -29,13 +144,20 @@ pub enum Item {
     AbortOutput,
     AreYouThere,
     GoAhead,
-    SB,
+    // IAC SB <option> <data> IAC SE, with escaped IACs already undone
+    Subnegotiation(u8, Vec<u8>),
     Will(u8),
     Wont(u8),
     Do(u8),
     Dont(u8),
 }
 
+impl Default for TelnetCodec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Decoder for TelnetCodec {
     type Item = Item;
     type Error = io::Error;
@@ -59,7 +181,7 @@ impl Decoder for TelnetCodec {
                     }
                     ParseIacResult::NeedMore => return Ok(None),
                     ParseIacResult::Item(item) => return Ok(Some(item)),
-                    ParseIacResult::NOP => { /* go around loop */ }
+                    ParseIacResult::Nop => { /* go around loop */ }
                     ParseIacResult::EraseCharacter => {
                         self.current_line.pop();
                     }
@@ -68,6 +190,9 @@ impl Decoder for TelnetCodec {
                     }
                     ParseIacResult::Escaped => {
                         self.current_line.push(0xff);
+                        if self.character_mode {
+                            return Ok(Some(Item::Character(0xff)));
+                        }
                     }
                 }
             } else {
@@ -77,6 +202,24 @@ impl Decoder for TelnetCodec {
                     10 => {
                         let line = self.current_line.to_vec();
                         self.current_line.clear();
+
+                        // c#import == command: issuers import new DID documents, the JSON
+                        // array follows on the next lines until c#end
+                        // c#validate == command: check a DID document without
+                        // storing it, the JSON follows on the next lines until c#end
+                        // c#register == command: register a DID with my own
+                        // document, the signed create request follows until c#end
+                        if self.block.is_some() {
+                            match self.push_block_line(line)? {
+                                Some(item) => return Ok(Some(item)),
+                                None => continue,
+                            }
+                        }
+                        if let Some(command) = BlockCommand::from_line(&line) {
+                            self.block = Some((command, Vec::new()));
+                            continue;
+                        }
+
                         let item = parse_line(line);
 
                         return Ok(item);
@@ -84,18 +227,94 @@ impl Decoder for TelnetCodec {
                     0..=31 => {
                         // ignore
                     }
-                    _ => self.current_line.push(byte),
+                    _ => {
+                        if self.current_line.len() >= MAX_LINE_SIZE {
+                            self.current_line.clear();
+                            return Err(io::Error::new(
+                                io::ErrorKind::InvalidData,
+                                "Line is too long",
+                            ));
+                        }
+                        self.current_line.push(byte);
+                        if self.character_mode {
+                            return Ok(Some(Item::Character(byte)));
+                        }
+                    }
                 }
             }
         }
     }
+
+    fn decode_eof(
+        &mut self,
+        src: &mut tokio_util::bytes::BytesMut,
+    ) -> Result<Option<Self::Item>, Self::Error> {
+        match self.decode(src)? {
+            Some(item) => Ok(Some(item)),
+            None => {
+                // An IAC sequence cut off by the end of the stream can't be
+                // completed anymore, drop it instead of failing
+                src.clear();
+                Ok(None)
+            }
+        }
+    }
+}
+
+// Frames what the server sends: lines get their CRLF, telnet commands their
+// IAC, and a 255 data byte is escaped as IAC IAC
+impl Encoder<Item> for TelnetCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, item: Item, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        match item {
+            Item::Line(line) => {
+                put_escaped(dst, &line);
+                dst.put_slice(&[13, 10]);
+            }
+            Item::SE => dst.put_slice(&[0xff, 240]),
+            Item::DataMark => dst.put_slice(&[0xff, 242]),
+            Item::Break => dst.put_slice(&[0xff, 243]),
+            Item::InterruptProcess => dst.put_slice(&[0xff, 244]),
+            Item::AbortOutput => dst.put_slice(&[0xff, 245]),
+            Item::AreYouThere => dst.put_slice(&[0xff, 246]),
+            Item::GoAhead => dst.put_slice(&[0xff, 249]),
+            Item::Subnegotiation(option, data) => {
+                dst.put_slice(&[0xff, 250, option]);
+                put_escaped(dst, &data);
+                dst.put_slice(&[0xff, 240]);
+            }
+            Item::Will(option) => dst.put_slice(&[0xff, 251, option]),
+            Item::Wont(option) => dst.put_slice(&[0xff, 252, option]),
+            Item::Do(option) => dst.put_slice(&[0xff, 253, option]),
+            Item::Dont(option) => dst.put_slice(&[0xff, 254, option]),
+            command => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{:?} is only received, it can't be sent", command),
+                ))
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn put_escaped(dst: &mut BytesMut, data: &[u8]) {
+    for &byte in data {
+        if byte == 0xff {
+            dst.put_slice(&[0xff, 0xff]);
+        } else {
+            dst.put_u8(byte);
+        }
+    }
 }
 
 enum ParseIacResult {
     Invalid(String),
     NeedMore,
     Item(Item),
-    NOP,
+    Nop,
     EraseCharacter,
     EraseLine,
     Escaped,
@@ -114,7 +333,7 @@ fn try_parse_iac(bytes: &[u8]) -> (ParseIacResult, usize) {
 
     match bytes[1] {
         240 => (ParseIacResult::Item(Item::SE), 2),
-        241 => (ParseIacResult::NOP, 2),
+        241 => (ParseIacResult::Nop, 2),
         242 => (ParseIacResult::Item(Item::DataMark), 2),
         243 => (ParseIacResult::Item(Item::Break), 2),
         244 => (ParseIacResult::Item(Item::InterruptProcess), 2),
@@ -123,7 +342,7 @@ fn try_parse_iac(bytes: &[u8]) -> (ParseIacResult, usize) {
         247 => (ParseIacResult::EraseCharacter, 2),
         248 => (ParseIacResult::EraseLine, 2),
         249 => (ParseIacResult::Item(Item::GoAhead), 2),
-        250 => (ParseIacResult::Item(Item::SB), 2),
+        250 => parse_subnegotiation(bytes),
         251 => (ParseIacResult::Item(Item::Will(bytes[2])), 3),
         252 => (ParseIacResult::Item(Item::Wont(bytes[2])), 3),
         253 => (ParseIacResult::Item(Item::Do(bytes[2])), 3),
@@ -136,11 +355,55 @@ fn try_parse_iac(bytes: &[u8]) -> (ParseIacResult, usize) {
     }
 }
 
-fn is_three_byte_iac(byte: u8) -> bool {
-    match byte {
-        251..=254 => true,
-        _ => false,
+// Read a whole subnegotiation, up to the IAC SE that ends it
+fn parse_subnegotiation(bytes: &[u8]) -> (ParseIacResult, usize) {
+    if bytes.len() < 3 {
+        return (ParseIacResult::NeedMore, 0);
+    }
+
+    let mut data = Vec::new();
+    let mut i = 3;
+    while i < bytes.len() {
+        if data.len() > MAX_SUBNEGOTIATION_SIZE {
+            return (
+                ParseIacResult::Invalid("Subnegotiation is too large.".to_string()),
+                0,
+            );
+        }
+        if bytes[i] != 0xff {
+            data.push(bytes[i]);
+            i += 1;
+            continue;
+        }
+        match bytes.get(i + 1) {
+            None => break,
+            Some(240) => {
+                return (
+                    ParseIacResult::Item(Item::Subnegotiation(bytes[2], data)),
+                    i + 2,
+                )
+            }
+            Some(255) => {
+                data.push(0xff);
+                i += 2;
+            }
+            Some(cmd) => {
+                return (
+                    ParseIacResult::Invalid(format!(
+                        "Unexpected IAC command {} in subnegotiation.",
+                        cmd
+                    )),
+                    0,
+                )
+            }
+        }
     }
+
+    (ParseIacResult::NeedMore, 0)
+}
+
+fn is_three_byte_iac(byte: u8) -> bool {
+    matches!(byte, 251..=254)
 }
 
 // Mark: Decentralized Identifier v1.0
@@ -151,7 +414,34 @@ fn parse_line(line: Vec<u8>) -> Option<Item> {
     );
     // c#cdid == command: [c]reate did
     if line.to_vec() == b"c#cdid".to_vec() {
-        return Some(Item::CreateDID);
+        return Some(Item::CreateDID {
+            return_key: false,
+            schema_endpoint: None,
+        });
+    }
+
+    // c#cdid key == command: [c]reate did, and send me its signing key
+    if line.to_vec() == b"c#cdid key".to_vec() {
+        return Some(Item::CreateDID {
+            return_key: true,
+            schema_endpoint: None,
+        });
+    }
+
+    // c#cdid schemas == command: [c]reate an issuer did publishing where its
+    // credential schemas live, c#cdid schemas <url>
+    if line.starts_with(b"c#cdid schemas ") {
+        let endpoint = String::from_utf8_lossy(&line[15..]).trim().to_string();
+        return Some(Item::CreateDID {
+            return_key: false,
+            schema_endpoint: Some(endpoint),
+        });
+    }
+
+    // c#udid == command: [u]pdate did with a signed request, c#udid<request>
+    if line.starts_with(b"c#udid") {
+        let request = &line[6..];
+        return Some(Item::UpdateDID(request.to_vec()));
     }
 
     // c#wai== command: [w]ho [a]m [i]
@@ -159,29 +449,492 @@ fn parse_line(line: Vec<u8>) -> Option<Item> {
         return Some(Item::WhoAmI);
     }
 
-    // c#svp == command: [s]how [v]erifiable [p]resenation
-    if line.to_vec() == b"c#svp".to_vec() {
-        return Some(Item::ShowVP);
+    // c#svp == command: [s]how [v]erifiable [p]resenation, verifiers pass the
+    // presentation JSON to verify, c#svp<presentation>
+    if line.starts_with(b"c#svp") {
+        let presentation = &line[5..];
+        return Some(Item::ShowVP(presentation.to_vec()));
+    }
+
+    // c#rvp == command: [r]equest a [v]erifiable [p]resentation from a holder,
+    // c#rvp<holder_did> [challenge]
+    if line.starts_with(b"c#rvp") {
+        let holder_did = &line[5..];
+        return Some(Item::RequestPresentation(holder_did.to_vec()));
+    }
+
+    // c#present == command: present a credential of my wallet to a verifier
+    // who requested it, c#present<verifier_id> <credential_id>
+    if line.starts_with(b"c#present") {
+        let args = &line[9..];
+        return Some(Item::Present(args.to_vec()));
+    }
+
+    // c#qr == command: QR code of the resolver page of a DID, c#qr<did>
+    if line.starts_with(b"c#qr") {
+        let did = &line[4..];
+        return Some(Item::QR(did.to_vec()));
+    }
+
+    // c#hashdoc == command: content hash of a DID's document, c#hashdoc<did>
+    if line.starts_with(b"c#hashdoc") {
+        let did = &line[9..];
+        return Some(Item::HashDocument(did.to_vec()));
+    }
+
+    // c#expand == command: JSON-LD expanded form of a DID's document,
+    // c#expand<did>
+    if line.starts_with(b"c#expand") {
+        let did = &line[8..];
+        return Some(Item::Expand(did.to_vec()));
+    }
+
+    // c#rels == command: method ids under each verification relationship of
+    // a DID's document, c#rels<did>
+    if line.starts_with(b"c#rels") {
+        let did = &line[6..];
+        return Some(Item::Relationships(did.to_vec()));
+    }
+
+    // c#benchverify == command: time resolving a DID and verifying the proof
+    // of its document, c#benchverify<did> [iterations]
+    if line.starts_with(b"c#benchverify") {
+        let args = &line[13..];
+        return Some(Item::BenchVerify(args.to_vec()));
+    }
+
+    // c#methods == command: list the supported DID methods
+    if line.to_vec() == b"c#methods".to_vec() {
+        return Some(Item::Methods);
+    }
+
+    // c#wallet == command: list the credentials issued to me
+    if line.to_vec() == b"c#wallet".to_vec() {
+        return Some(Item::Wallet);
+    }
+
+    // c#forget == command: remove a credential from my wallet, c#forget<credential_id>
+    if line.starts_with(b"c#forget") {
+        let credential_id = &line[8..];
+        return Some(Item::Forget(credential_id.to_vec()));
+    }
+
+    // c#challenge == command: ask a client to prove control of a DID,
+    // c#challenge<client_id> <did>
+    if line.starts_with(b"c#challenge") {
+        let args = &line[11..];
+        return Some(Item::Challenge(args.to_vec()));
+    }
+
+    // c#respond == command: answer a challenge with the nonce signed by the
+    // DID's key, c#respond<verifier_id> <signature>
+    if line.starts_with(b"c#respond") {
+        let args = &line[9..];
+        return Some(Item::Respond(args.to_vec()));
+    }
+
+    // c#dm == command: [d]irect [m]essage to the client owning a DID,
+    // c#dm<did> <text>
+    if line.starts_with(b"c#dm") {
+        let args = &line[4..];
+        return Some(Item::DirectMessage(args.to_vec()));
+    }
+
+    // c#options == command: telnet options negotiated on my connection
+    if line.to_vec() == b"c#options".to_vec() {
+        return Some(Item::Options);
+    }
+
+    // c#trace == command: echo every item decoded on my connection back to
+    // me, c#trace on|off
+    if line.starts_with(b"c#trace") {
+        let switch = &line[7..];
+        return Some(Item::Trace(switch.to_vec()));
+    }
+
+    // c#linemode == command: send whole lines, or a character at a time for
+    // the interactive flows, c#linemode on|off
+    if line.starts_with(b"c#linemode") {
+        let switch = &line[10..];
+        return Some(Item::LineMode(switch.to_vec()));
     }
 
-    // c#sdid == command: [s]show did
-    if line.to_vec()[0..6] == b"c#sdid".to_vec() {
+    // c#schema == command: JSON template of a new credential type for issuers
+    // to fill in, c#schema<type>
+    if line.starts_with(b"c#schema") {
+        let credential_type = &line[8..];
+        return Some(Item::Schema(credential_type.to_vec()));
+    }
+
+    // c#hex == command: show how the server received the text, c#hex<text>
+    if line.starts_with(b"c#hex") {
+        let text = &line[5..];
+        return Some(Item::Hex(text.to_vec()));
+    }
+
+    // c#vsig == command: [v]erify a detached [sig]nature,
+    // c#vsig<multibase_key> <base58_sig> <message>
+    if line.starts_with(b"c#vsig") {
+        let args = &line[6..];
+        return Some(Item::VerifySignature(args.to_vec()));
+    }
+
+    // c#serverlog == command: recent server log lines, for verifiers
+    if line.to_vec() == b"c#serverlog".to_vec() {
+        return Some(Item::ServerLog);
+    }
+
+    // c#stats == command: counts of DIDs, credentials and clients
+    if line.to_vec() == b"c#stats".to_vec() {
+        return Some(Item::Stats);
+    }
+
+    // c#ephemeral == command: delete my DIDs when I disconnect
+    if line.to_vec() == b"c#ephemeral".to_vec() {
+        return Some(Item::Ephemeral);
+    }
+
+    // c#subscribe == command: subscribe to newly created DIDs
+    if line.to_vec() == b"c#subscribe".to_vec() {
+        return Some(Item::Subscribe);
+    }
+
+    // c#unsubscribe == command: unsubscribe from newly created DIDs
+    if line.to_vec() == b"c#unsubscribe".to_vec() {
+        return Some(Item::Unsubscribe);
+    }
+
+    // c#issue == command: issue a verifiable credential, c#issue<subject_did>:<score>
+    // or c#issue<subject_did>:<score>:<range>:<confidence>
+    if line.starts_with(b"c#issue") {
+        let args = &line[7..];
+        return Some(Item::IssueVC(args.to_vec()));
+    }
+
+    // c#rotateissuer == command: rotate the server's issuer signing key
+    if line.to_vec() == b"c#rotateissuer".to_vec() {
+        return Some(Item::RotateIssuer);
+    }
+
+    // c#snapshot == command: export the registry, signed by the server
+    if line.to_vec() == b"c#snapshot".to_vec() {
+        return Some(Item::Snapshot);
+    }
+
+    // c#renew == command: renew a verifiable credential, c#renew<credential_id>
+    if line.starts_with(b"c#renew") {
+        let credential_id = &line[7..];
+        return Some(Item::RenewVC(credential_id.to_vec()));
+    }
+
+    // c#resign == command: sign my stored document again after edits,
+    // c#resign<did>
+    if line.starts_with(b"c#resign") {
+        let did = &line[8..];
+        return Some(Item::Resign(did.to_vec()));
+    }
+
+    // c#resolve == command: full resolution result of a did, c#resolve<did>
+    if line.starts_with(b"c#resolve") {
+        let did = &line[9..];
+        return Some(Item::Resolve(did.to_vec()));
+    }
+
+    // c#sdid == command: [s]show did, c#sdid<did or unambiguous prefix>
+    if line.starts_with(b"c#sdid") {
         let did = &line[6..];
         return Some(Item::ShowDID(did.to_vec()));
     }
 
     // c#ar == command: [a]ssign [r]ole
-    if line.to_vec()[0..4] == b"c#ar".to_vec() {
+    if line.starts_with(b"c#ar") {
         let role = &line[4..];
         return Some(Item::AssignRole(role.to_vec()));
     }
 
     // c#vdid == command: [v]erify did
-    if line.to_vec()[0..6] == b"c#vdid".to_vec() {
+    if line.starts_with(b"c#vdid") {
         let did = &line[6..];
         return Some(Item::VerifyDID(did.to_vec()));
     }
     //Todo: Add command from client
 
-    return Some(Item::Line(line));
+    Some(Item::Line(line))
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn test_import_block_spans_lines() {
+        let mut codec = TelnetCodec::new();
+        let mut src = BytesMut::from(&b"c#import\r\n[\r\n{}\r\n]\r\nc#end\r\nc#wai\r\n"[..]);
+
+        match codec.decode(&mut src).unwrap() {
+            Some(Item::Import(block)) => assert_eq!(block, b"[\n{}\n]\n"),
+            other => panic!("Expected an import block, got {:?}", other),
+        }
+        assert!(matches!(
+            codec.decode(&mut src).unwrap(),
+            Some(Item::WhoAmI)
+        ));
+    }
+
+    // Decode everything the codec can, as the framed reader does until the
+    // stream ends
+    fn decode_all(codec: &mut TelnetCodec, src: &mut BytesMut) -> Result<Vec<Item>, io::Error> {
+        let mut items = Vec::new();
+        while let Some(item) = codec.decode(src)? {
+            items.push(item);
+        }
+        while let Some(item) = codec.decode_eof(src)? {
+            items.push(item);
+        }
+
+        Ok(items)
+    }
+
+    proptest! {
+        #[test]
+        fn prop_decode_never_panics(chunks in vec(vec(any::<u8>(), 0..64), 0..16)) {
+            let mut codec = TelnetCodec::new();
+            let mut src = BytesMut::new();
+            for chunk in chunks {
+                src.extend_from_slice(&chunk);
+                // Errors are fine, the connection is closed on them
+                if decode_all(&mut codec, &mut src).is_err() {
+                    break;
+                }
+            }
+        }
+
+        #[test]
+        fn prop_decode_commands_never_panic(
+            prefix in prop_oneof![
+                Just(b"c#".to_vec()),
+                Just(b"c#s".to_vec()),
+                Just(b"c#sdid".to_vec()),
+                Just(b"c#resolve".to_vec()),
+                Just(b"c#vd".to_vec()),
+                Just(b"c#a".to_vec()),
+                Just(b"c#import".to_vec()),
+                Just(vec![0xff]),
+                Just(vec![0xff, 251]),
+                Just(vec![0xff, 250, 42]),
+            ],
+            rest in vec(any::<u8>(), 0..32),
+        ) {
+            let mut codec = TelnetCodec::new();
+            let mut src = BytesMut::from(&prefix[..]);
+            src.extend_from_slice(&rest);
+            src.extend_from_slice(b"\n");
+            let _ = decode_all(&mut codec, &mut src);
+        }
+
+        #[test]
+        fn prop_plain_line_is_decoded(line in vec(32u8..=254, 0..128)) {
+            let mut codec = TelnetCodec::new();
+            let mut src = BytesMut::from(&line[..]);
+            src.extend_from_slice(b"\r\n");
+
+            let items = decode_all(&mut codec, &mut src).unwrap();
+            prop_assert!(src.is_empty());
+            prop_assert_eq!(items.len(), if BlockCommand::from_line(&line).is_some() { 0 } else { 1 });
+        }
+    }
+
+    #[test]
+    fn test_short_commands_are_lines() {
+        let mut codec = TelnetCodec::new();
+        let mut src = BytesMut::from(&b"c#\nc#s\nc#a\nc#sd\n"[..]);
+
+        let items = decode_all(&mut codec, &mut src).unwrap();
+        assert_eq!(items.len(), 4);
+        assert!(items.iter().all(|item| matches!(item, Item::Line(_))));
+    }
+
+    #[test]
+    fn test_multi_byte_utf8_lines_keep_their_bytes() {
+        let mut codec = TelnetCodec::new();
+        let line = "hello 👋 世界";
+        let did = "did:example:日本語🙂";
+        let mut src = BytesMut::new();
+
+        // Split inside the emoji, the rest arrives with the next read
+        let bytes = format!("{}\r\nc#sdid{}\r\n", line, did).into_bytes();
+        let split = line.find('👋').unwrap() + 2;
+        src.extend_from_slice(&bytes[..split]);
+        assert!(codec.decode(&mut src).unwrap().is_none());
+        src.extend_from_slice(&bytes[split..]);
+
+        match codec.decode(&mut src).unwrap() {
+            Some(Item::Line(bytes)) => assert_eq!(String::from_utf8(bytes).unwrap(), line),
+            other => panic!("Expected a line, got {:?}", other),
+        }
+        match codec.decode(&mut src).unwrap() {
+            Some(Item::ShowDID(bytes)) => assert_eq!(String::from_utf8(bytes).unwrap(), did),
+            other => panic!("Expected c#sdid, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_subnegotiation_is_one_item() {
+        let mut codec = TelnetCodec::new();
+        let mut src = BytesMut::from(&[0xff, 250, 42, 1, b';'][..]);
+        assert!(codec.decode(&mut src).unwrap().is_none());
+
+        // Rest of the subnegotiation, with an escaped IAC, then a line
+        src.extend_from_slice(b"UTF-8");
+        src.extend_from_slice(&[0xff, 0xff, 0xff, 240]);
+        src.extend_from_slice(b"c#wai\r\n");
+        match codec.decode(&mut src).unwrap() {
+            Some(Item::Subnegotiation(42, data)) => assert_eq!(data, b"\x01;UTF-8\xff"),
+            other => panic!("Expected a subnegotiation, got {:?}", other),
+        }
+        assert!(matches!(
+            codec.decode(&mut src).unwrap(),
+            Some(Item::WhoAmI)
+        ));
+    }
+
+    #[test]
+    fn test_naws_subnegotiation_across_reads() {
+        let mut codec = TelnetCodec::new();
+        // IAC SB NAWS 0 80 0 24 IAC SE, cut between the IAC and the SE
+        let mut src = BytesMut::from(&[0xff, 250, 31, 0, 80, 0, 24, 0xff][..]);
+        assert!(codec.decode(&mut src).unwrap().is_none());
+
+        src.extend_from_slice(&[240]);
+        match codec.decode(&mut src).unwrap() {
+            Some(Item::Subnegotiation(31, data)) => assert_eq!(data, [0, 80, 0, 24]),
+            other => panic!("Expected a NAWS subnegotiation, got {:?}", other),
+        }
+        assert!(src.is_empty());
+    }
+
+    #[test]
+    fn test_incomplete_iac_at_eof_is_dropped() {
+        let mut codec = TelnetCodec::new();
+        let mut src = BytesMut::from(&[0xff, 251][..]);
+
+        assert!(codec.decode(&mut src).unwrap().is_none());
+        assert!(codec.decode_eof(&mut src).unwrap().is_none());
+        assert!(src.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_lone_iac_waits_for_next_read() {
+        use futures::StreamExt;
+        use std::time::Duration;
+        use tokio::io::AsyncWriteExt;
+        use tokio_util::codec::FramedRead;
+
+        let (mut client, server) = tokio::io::duplex(64);
+        let mut framed = FramedRead::new(server, TelnetCodec::new());
+
+        // The IAC arrives alone at the end of a read
+        client.write_all(&[b'a', 0xff]).await.unwrap();
+        let next = tokio::time::timeout(Duration::from_millis(100), framed.next()).await;
+        assert!(next.is_err(), "Nothing should be decoded yet");
+        assert_eq!(&framed.read_buffer()[..], &[0xff]);
+        assert_eq!(framed.decoder().current_line, b"a");
+
+        client.write_all(&[0xfb, 0x03, b'b', b'\n']).await.unwrap();
+        assert!(matches!(
+            framed.next().await.unwrap().unwrap(),
+            Item::Will(3)
+        ));
+        match framed.next().await.unwrap().unwrap() {
+            Item::Line(line) => assert_eq!(line, b"ab"),
+            other => panic!("Expected a line, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_validate_block_spans_lines() {
+        let mut codec = TelnetCodec::new();
+        let mut src = BytesMut::from(&b"c#validate\r\n{\r\n}\r\nc#end\r\n"[..]);
+
+        match codec.decode(&mut src).unwrap() {
+            Some(Item::Validate(block)) => assert_eq!(block, b"{\n}\n"),
+            other => panic!("Expected a validate block, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_import_block_is_bounded() {
+        let mut codec = TelnetCodec::new();
+        let mut src = BytesMut::from(&b"c#import\n"[..]);
+        let line = vec![b'a'; 1024];
+        for _ in 0..(MAX_BLOCK_SIZE / line.len()) {
+            src.extend_from_slice(&line);
+            src.extend_from_slice(b"\n");
+        }
+
+        assert!(codec.decode(&mut src).is_err());
+    }
+
+    #[test]
+    fn test_character_mode_emits_each_character() {
+        let mut codec = TelnetCodec::new();
+        let mut src = BytesMut::from(&b"ab\r\n"[..]);
+        let items = decode_all(&mut codec, &mut src).unwrap();
+        assert!(matches!(items[..], [Item::Line(_)]));
+
+        codec.set_character_mode(true);
+        let mut src = BytesMut::from(&b"ab\r\n"[..]);
+        match &decode_all(&mut codec, &mut src).unwrap()[..] {
+            [Item::Character(b'a'), Item::Character(b'b'), Item::Line(line)] => {
+                assert_eq!(line, b"ab")
+            }
+            other => panic!("Expected characters then the line, got {:?}", other),
+        }
+    }
+
+    fn encode(item: Item) -> Vec<u8> {
+        let mut dst = BytesMut::new();
+        TelnetCodec::new().encode(item, &mut dst).unwrap();
+        dst.to_vec()
+    }
+
+    #[test]
+    fn test_encode_commands() {
+        assert_eq!(encode(Item::Will(3)), [0xff, 251, 3]);
+        assert_eq!(encode(Item::Wont(3)), [0xff, 252, 3]);
+        assert_eq!(encode(Item::Do(24)), [0xff, 253, 24]);
+        assert_eq!(encode(Item::Dont(24)), [0xff, 254, 24]);
+        assert_eq!(encode(Item::GoAhead), [0xff, 249]);
+        assert_eq!(encode(Item::AreYouThere), [0xff, 246]);
+        assert_eq!(
+            encode(Item::Subnegotiation(24, vec![1])),
+            [0xff, 250, 24, 1, 0xff, 240]
+        );
+        assert_eq!(encode(Item::Line(b"Yes.".to_vec())), b"Yes.\r\n");
+
+        let mut dst = BytesMut::new();
+        assert!(TelnetCodec::new().encode(Item::WhoAmI, &mut dst).is_err());
+    }
+
+    #[test]
+    fn test_encode_escapes_iac_in_data() {
+        assert_eq!(
+            encode(Item::Line(vec![b'a', 0xff, b'b'])),
+            [b'a', 0xff, 0xff, b'b', 13, 10]
+        );
+        assert_eq!(
+            encode(Item::Subnegotiation(42, vec![0xff])),
+            [0xff, 250, 42, 0xff, 0xff, 0xff, 240]
+        );
+
+        // What is sent decodes back to the same bytes
+        let mut src = BytesMut::from(&encode(Item::Line(vec![b'a', 0xff, b'b']))[..]);
+        match TelnetCodec::new().decode(&mut src).unwrap() {
+            Some(Item::Line(line)) => assert_eq!(line, [b'a', 0xff, b'b']),
+            other => panic!("Expected a line, got {:?}", other),
+        }
+    }
 }
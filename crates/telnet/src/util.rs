@@ -1,6 +1,10 @@
 use network_interface::{NetworkInterface, NetworkInterfaceConfig};
 use std::error::Error;
 use std::net::{IpAddr, Ipv4Addr};
+use std::sync::OnceLock;
+
+// Port of the web server the QR codes point at
+pub static WEB_PORT: u16 = 8000;
 
 // Struct to hold network details
 #[derive(Debug, PartialEq)]
@@ -8,6 +12,28 @@ pub struct NetworkInfo {
     pub ip: Ipv4Addr,
     pub subnet_mask: Option<Ipv4Addr>,
     pub gateway: Option<Ipv4Addr>,
+    // Whether other hosts on the LAN can likely reach this address
+    pub lan_reachable: bool,
+}
+
+// The default gateway is system-wide and slow to look up on some systems, so
+// it's only looked up once
+pub fn default_gateway() -> Option<Ipv4Addr> {
+    static GATEWAY: OnceLock<Option<Ipv4Addr>> = OnceLock::new();
+
+    *GATEWAY.get_or_init(|| {
+        default_net::get_default_gateway()
+            .ok()
+            .and_then(|g| match g.ip_addr {
+                IpAddr::V4(gw) => Some(gw),
+                _ => None,
+            })
+    })
+}
+
+// Loopback, link-local and unspecified addresses can't be reached from other hosts
+pub fn is_lan_reachable(ip: Ipv4Addr) -> bool {
+    !(ip.is_loopback() || ip.is_link_local() || ip.is_unspecified() || ip.is_broadcast())
 }
 
 // Function to get IPv4 address, subnet mask, and gateway for the first active interface
@@ -27,19 +53,11 @@ pub fn get_ipv4_info() -> Result<Vec<NetworkInfo>, Box<dyn Error>> {
                     IpAddr::V4(mask) => Some(mask),
                     _ => None,
                 });
-                // Get gateway (only once, as it's system-wide)
-                let gateway =
-                    default_net::get_default_gateway()
-                        .ok()
-                        .and_then(|g| match g.ip_addr {
-                            IpAddr::V4(gw) => Some(gw),
-                            _ => None,
-                        });
-
                 result.push(NetworkInfo {
                     ip,
                     subnet_mask,
-                    gateway,
+                    gateway: default_gateway(),
+                    lan_reachable: is_lan_reachable(ip),
                 });
             }
         }
@@ -48,6 +66,38 @@ pub fn get_ipv4_info() -> Result<Vec<NetworkInfo>, Box<dyn Error>> {
     Ok(result)
 }
 
+// Address other hosts reach the server at, loopback when there's no LAN
+// address to offer
+pub fn server_ip() -> Ipv4Addr {
+    get_ipv4_info()
+        .ok()
+        .and_then(|infos| infos.into_iter().find(|info| info.lan_reachable))
+        .map_or(Ipv4Addr::LOCALHOST, |info| info.ip)
+}
+
+// URL of a page of the web server, e.g. "qr" or "1.0/identifiers/<did>"
+pub fn build_server_url(ip: Ipv4Addr, path: &str) -> String {
+    format!(
+        "http://{}:{}/{}",
+        ip,
+        WEB_PORT,
+        path.trim_start_matches('/')
+    )
+}
+
+// Bytes of a hex string like "00ff", None unless it's an even number of hex
+// digits
+pub fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    let pairs = hex.as_bytes().chunks_exact(2);
+    if !pairs.remainder().is_empty() {
+        return None;
+    }
+
+    pairs
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -62,7 +112,37 @@ mod tests {
                 ip: Ipv4Addr::new(192, 168, 1, 100),
                 subnet_mask: Some(Ipv4Addr::new(255, 255, 255, 0)),
                 gateway: Some(Ipv4Addr::new(192, 168, 1, 1)),
+                lan_reachable: true,
             }]
         });
     }
+
+    #[test]
+    fn test_lan_reachability() {
+        assert!(!is_lan_reachable(Ipv4Addr::LOCALHOST));
+        assert!(!is_lan_reachable(Ipv4Addr::new(127, 0, 1, 1)));
+        assert!(!is_lan_reachable(Ipv4Addr::new(169, 254, 10, 20)));
+        assert!(!is_lan_reachable(Ipv4Addr::UNSPECIFIED));
+        assert!(is_lan_reachable(Ipv4Addr::new(192, 168, 1, 100)));
+        assert!(is_lan_reachable(Ipv4Addr::new(10, 0, 0, 5)));
+    }
+
+    #[test]
+    fn test_decode_hex() {
+        assert_eq!(decode_hex("00ffA0"), Some(vec![0x00, 0xff, 0xa0]));
+        assert_eq!(decode_hex(""), Some(vec![]));
+        assert_eq!(decode_hex("abc"), None);
+        assert_eq!(decode_hex("zz"), None);
+        assert_eq!(decode_hex("é0"), None);
+    }
+
+    #[test]
+    fn test_build_server_url() {
+        let ip = Ipv4Addr::new(192, 168, 1, 100);
+        assert_eq!(build_server_url(ip, "qr"), "http://192.168.1.100:8000/qr");
+        assert_eq!(
+            build_server_url(ip, "/1.0/identifiers/did:example:123"),
+            "http://192.168.1.100:8000/1.0/identifiers/did:example:123"
+        );
+    }
 }
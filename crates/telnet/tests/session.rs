@@ -0,0 +1,171 @@
+//! Full telnet sessions against a running server: a real TCP client talks to
+//! the accept loop, so the codec, the client actor and the main loop are all
+//! exercised together.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use telnet::accept::{run_accept_loop, AcceptLimits};
+use telnet::main_loop::spawn_main_loop;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::timeout;
+
+// A client connected to a fresh server on an ephemeral port
+struct Session {
+    addr: SocketAddr,
+    tcp: TcpStream,
+}
+
+impl Session {
+    async fn start() -> Self {
+        let (handle, _join) = spawn_main_loop();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(run_accept_loop(listener, handle, AcceptLimits::default()));
+
+        Session::connect(addr).await
+    }
+
+    // Another client of the same server
+    async fn join(&self) -> Self {
+        Session::connect(self.addr).await
+    }
+
+    async fn connect(addr: SocketAddr) -> Self {
+        let mut session = Session {
+            addr,
+            tcp: TcpStream::connect(addr).await.unwrap(),
+        };
+        session.read_until("Welcome!").await;
+        session
+    }
+
+    async fn send(&mut self, bytes: &[u8]) {
+        self.tcp.write_all(bytes).await.unwrap();
+    }
+
+    // Everything received until the expected text shows up
+    async fn read_until(&mut self, expected: &str) -> String {
+        let mut received = Vec::new();
+        let mut buf = [0u8; 256];
+        while !String::from_utf8_lossy(&received).contains(expected) {
+            let n = timeout(Duration::from_secs(2), self.tcp.read(&mut buf))
+                .await
+                .unwrap_or_else(|_| {
+                    panic!(
+                        "Timed out waiting for {:?}, got {:?}",
+                        expected,
+                        String::from_utf8_lossy(&received)
+                    )
+                })
+                .unwrap();
+            assert!(n > 0, "Connection closed before {:?}", expected);
+            received.extend_from_slice(&buf[..n]);
+        }
+
+        String::from_utf8_lossy(&received).to_string()
+    }
+}
+
+#[tokio::test]
+async fn test_create_then_show_round_trip() {
+    let mut session = Session::start().await;
+
+    session.send(b"c#cdid key\r\n").await;
+    let received = session.read_until("Your Did Document is saved!").await;
+    let did = received
+        .split("Signing key of ")
+        .nth(1)
+        .and_then(|rest| rest.split(' ').next())
+        .expect("No DID in the key notice")
+        .to_string();
+    assert!(did.starts_with("did:"));
+
+    session.send(format!("c#sdid{}\r\n", did).as_bytes()).await;
+    let received = session.read_until(&format!("{}#key1", did)).await;
+    assert!(received.contains(&format!("\"id\": \"{}\"", did)));
+}
+
+#[tokio::test]
+async fn test_who_am_i_before_and_after_assigning_a_role() {
+    let mut session = Session::start().await;
+
+    session.send(b"c#wai\r\n").await;
+    session.read_until("Hello \"Anonymous\"").await;
+
+    session.send(b"c#arholder\r\nc#wai\r\n").await;
+    session.read_until("Hello Holder").await;
+}
+
+#[tokio::test]
+async fn test_show_unknown_did() {
+    let mut session = Session::start().await;
+
+    session.send(b"c#sdiddid:example:nobody\r\n").await;
+    session.read_until("no match").await;
+}
+
+#[tokio::test]
+async fn test_linemode_off_frames_input_by_character() {
+    let mut session = Session::start().await;
+
+    session.send(b"c#trace on\r\nc#linemode off\r\n").await;
+    session.read_until("Line mode off").await;
+
+    session.send(b"c#wai\r\n").await;
+    let received = session.read_until("Hello").await;
+    assert!(received.contains("[trace] character 'w'"));
+    assert!(received.contains("[trace] command WhoAmI"));
+
+    session.send(b"c#linemode on\r\n").await;
+    session.read_until("Line mode on").await;
+    session.send(b"c#wai\r\n").await;
+    let received = session.read_until("Hello").await;
+    assert!(!received.contains("[trace] character"));
+}
+
+// The word following `marker` in a received message
+fn word_after(received: &str, marker: &str) -> String {
+    received
+        .split(marker)
+        .nth(1)
+        .and_then(|rest| rest.split_whitespace().next())
+        .unwrap_or_else(|| panic!("No {:?} in {:?}", marker, received))
+        .trim_end_matches(',')
+        .to_string()
+}
+
+#[tokio::test]
+async fn test_holder_presents_a_credential_signed_by_its_client() {
+    let mut holder = Session::start().await;
+    let mut issuer = holder.join().await;
+    let mut verifier = issuer.join().await;
+
+    holder.send(b"c#cdid key\r\n").await;
+    let received = holder.read_until("Your Did Document is saved!").await;
+    let did = word_after(&received, "Signing key of ");
+
+    issuer.send(b"c#arissuer\r\n").await;
+    issuer.read_until("Hello Issuer").await;
+    issuer
+        .send(format!("c#issue{}:750\r\n", did).as_bytes())
+        .await;
+    let received = holder.read_until("from did:").await;
+    let credential_id = word_after(&received, "You received credential ");
+
+    verifier.send(b"c#arverifier\r\n").await;
+    verifier.read_until("Hello Verifier").await;
+    verifier
+        .send(format!("c#rvp{} challenge-1\r\n", did).as_bytes())
+        .await;
+    let received = holder.read_until("<credential_id>").await;
+    let verifier_id = word_after(&received, "or c#present");
+
+    holder
+        .send(format!("c#present{} {}\r\n", verifier_id, credential_id).as_bytes())
+        .await;
+    holder.read_until("Presentation sent to").await;
+    let received = verifier.read_until("Presentation is valid").await;
+    assert!(received.contains("\"nonce\": \"challenge-1\""));
+}
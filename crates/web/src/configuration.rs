@@ -6,6 +6,7 @@ use serde_aux::field_attributes::deserialize_number_from_string;
 #[derive(serde::Deserialize, Clone)]
 pub struct Settings {
     pub application: ApplicationSettings,
+    pub resolver: ResolverSettings,
 }
 
 #[derive(serde::Deserialize, Clone)]
@@ -17,6 +18,19 @@ pub struct ApplicationSettings {
     pub hmac_secret: Secret<String>,
 }
 
+// Registry the DIDs of submitted credentials and presentations are resolved
+// with
+#[derive(serde::Deserialize, Clone)]
+pub struct ResolverSettings {
+    // Base URL of a registry speaking the Universal Resolver HTTP API
+    pub base_url: String,
+    // DID methods resolved with it
+    pub methods: Vec<String>,
+    // How long a resolved document is reused before resolving it again
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub cache_ttl_seconds: u64,
+}
+
 pub fn get_configuration() -> Result<Settings, config::ConfigError> {
     let manifest_dir = env!("CARGO_MANIFEST_DIR");
     let base_path = Path::new(manifest_dir);
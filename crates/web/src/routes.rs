@@ -1,4 +1,102 @@
-use actix_web::{get, HttpResponse};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use actix_web::{
+    get, post,
+    web::{self, Data, Json},
+    HttpResponse,
+};
+use did::{
+    generate_nonce, verify_vcs_resolved, DidDocument, DidResolver, MethodRouter,
+    VerifiableCredential, VerifiablePresentation, VerificationOptions,
+};
+use futures::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
+
+// Issuers resolved at the same time by POST /verify/batch
+const BATCH_CONCURRENCY: usize = 8;
+
+// How long a challenge from GET /challenge can be answered
+const CHALLENGE_TTL: Duration = Duration::from_secs(300);
+// Anyone can ask for challenges, past this many live ones the oldest is
+// dropped to make room
+const MAX_LIVE_CHALLENGES: usize = 10_000;
+
+// Result of verifying one credential of a batch
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct VerificationOutcome {
+    pub valid: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl VerificationOutcome {
+    fn invalid(error: String) -> Self {
+        VerificationOutcome {
+            valid: false,
+            error: Some(error),
+        }
+    }
+}
+
+// Body of POST /present: a holder's presentation answering a challenge
+// issued by GET /challenge. The challenge is checked against the issued ones
+// and accepted once, so the presentation is known to be fresh.
+#[derive(Deserialize)]
+pub struct PresentationSubmission {
+    pub presentation: VerifiablePresentation,
+    pub challenge: String,
+}
+
+// Body of the answer to GET /challenge
+#[derive(Serialize, Deserialize)]
+pub struct ChallengeResponse {
+    pub challenge: String,
+}
+
+// Challenges issued by GET /challenge and not answered yet, by challenge
+#[derive(Default)]
+pub struct IssuedChallenges(Mutex<HashMap<String, Instant>>);
+
+impl IssuedChallenges {
+    fn issue(&self) -> String {
+        let challenge = generate_nonce();
+        let mut issued = self.0.lock().unwrap();
+        issued.retain(|_, issued_at| issued_at.elapsed() < CHALLENGE_TTL);
+        if issued.len() >= MAX_LIVE_CHALLENGES {
+            let oldest = issued
+                .iter()
+                .min_by_key(|(_, issued_at)| **issued_at)
+                .map(|(challenge, _)| challenge.clone());
+            if let Some(oldest) = oldest {
+                issued.remove(&oldest);
+            }
+        }
+        issued.insert(challenge.clone(), Instant::now());
+
+        challenge
+    }
+
+    // Whether the challenge was issued and hasn't expired, it can't be used
+    // again either way
+    fn take(&self, challenge: &str) -> bool {
+        self.0
+            .lock()
+            .unwrap()
+            .remove(challenge)
+            .is_some_and(|issued_at| issued_at.elapsed() < CHALLENGE_TTL)
+    }
+}
+
+// Issuer documents resolved once for a whole batch
+struct ResolvedIssuers(HashMap<String, DidDocument>);
+
+impl DidResolver for ResolvedIssuers {
+    fn resolve(&self, did: &str) -> Option<DidDocument> {
+        self.0.get(did).cloned()
+    }
+}
 
 #[get("/health_check")]
 pub async fn health_check() -> Result<HttpResponse, actix_web::Error> {
@@ -10,6 +108,110 @@ pub async fn index() -> Result<HttpResponse, actix_web::Error> {
     Ok(HttpResponse::Ok().finish())
 }
 
+// Verify a JSON array of credentials, answering with one outcome per
+// credential, in the same order
+#[post("/verify/batch")]
+pub async fn verify_batch(
+    router: Data<MethodRouter>,
+    credentials: Json<Vec<serde_json::Value>>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let credentials: Vec<Result<VerifiableCredential, String>> = credentials
+        .into_inner()
+        .into_iter()
+        .map(|credential| {
+            serde_json::from_value(credential).map_err(|err| format!("Invalid credential: {}", err))
+        })
+        .collect();
+
+    // Every issuer is resolved once, however many credentials it signed
+    let mut issuers: Vec<String> = credentials
+        .iter()
+        .flatten()
+        .map(|vc| vc.issuer.clone())
+        .collect();
+    issuers.sort();
+    issuers.dedup();
+    let resolved = stream::iter(issuers)
+        .map(|did| {
+            let router = router.clone();
+            web::block(move || {
+                let document = router.resolve(&did);
+                (did, document)
+            })
+        })
+        .buffer_unordered(BATCH_CONCURRENCY)
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()?;
+    let issuers = ResolvedIssuers(
+        resolved
+            .into_iter()
+            .filter_map(|(did, document)| Some((did, document?)))
+            .collect(),
+    );
+
+    // The signatures of all credentials are checked in a single batch
+    let outcomes = web::block(move || {
+        let vcs: Vec<VerifiableCredential> = credentials.iter().flatten().cloned().collect();
+        let mut verified = verify_vcs_resolved(&vcs, &issuers).into_iter();
+        credentials
+            .into_iter()
+            .map(|credential| match credential {
+                Ok(_) => match verified.next().expect("One result per credential") {
+                    Ok(true) => VerificationOutcome {
+                        valid: true,
+                        error: None,
+                    },
+                    Ok(false) => VerificationOutcome::invalid(
+                        "Signature doesn't match the credential".to_string(),
+                    ),
+                    Err(err) => VerificationOutcome::invalid(err.to_string()),
+                },
+                Err(err) => VerificationOutcome::invalid(err),
+            })
+            .collect::<Vec<_>>()
+    })
+    .await?;
+
+    Ok(HttpResponse::Ok().json(outcomes))
+}
+
+// Issue a challenge for a holder to put in the presentation it sends to
+// POST /present
+#[get("/challenge")]
+pub async fn issue_challenge(
+    challenges: Data<IssuedChallenges>,
+) -> Result<HttpResponse, actix_web::Error> {
+    Ok(HttpResponse::Ok().json(ChallengeResponse {
+        challenge: challenges.issue(),
+    }))
+}
+
+// Verify a presentation as the telnet verifiers do, answering with the
+// verification report, which tells whether the presentation is valid
+#[post("/present")]
+pub async fn present(
+    router: Data<MethodRouter>,
+    challenges: Data<IssuedChallenges>,
+    submission: Json<PresentationSubmission>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let PresentationSubmission {
+        presentation,
+        challenge,
+    } = submission.into_inner();
+    if !challenges.take(&challenge) {
+        return Ok(HttpResponse::BadRequest().body("Unknown, expired or already used challenge"));
+    }
+    let options = VerificationOptions {
+        challenge: Some(challenge),
+        ..Default::default()
+    };
+    let report = web::block(move || presentation.verify_full_with(&**router, &options)).await?;
+
+    Ok(HttpResponse::Ok().json(report))
+}
+
 #[get("/qr")]
 pub async fn qr() -> Result<HttpResponse, actix_web::Error> {
     let name = "Alice";
@@ -91,3 +293,184 @@ pub async fn qr() -> Result<HttpResponse, actix_web::Error> {
         .content_type("text/html; charset=utf-8")
         .body(html))
 }
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, test, App};
+    use did::fixtures::{deterministic_keypair, sample_credential, sample_document, FIXTURE_SEED};
+    use did::{DidStorage, PresentationVerificationReport, ProofOutcome, SharedStorage};
+
+    use super::*;
+
+    // Router resolving the example DIDs from a registry holding the sample
+    // document of each DID, keyed with the fixture keypair
+    fn router_with(dids: &[&str]) -> MethodRouter {
+        let storage = SharedStorage::default();
+        for did in dids {
+            storage
+                .write()
+                .unwrap()
+                .store(did.to_string(), sample_document(did))
+                .unwrap();
+        }
+        let mut router = MethodRouter::new();
+        router.register("example", Box::new(storage));
+
+        router
+    }
+
+    #[actix_web::test]
+    async fn test_verify_batch_reports_each_credential() {
+        let issuer = "did:example:issuer";
+        let mut router = router_with(&[issuer]);
+        router.register("web", Box::new(DidStorage::new()));
+        let app =
+            test::init_service(App::new().app_data(Data::new(router)).service(verify_batch)).await;
+
+        let valid = sample_credential(issuer, "did:example:alice", 750);
+        let mut tampered = sample_credential(issuer, "did:example:bob", 700);
+        tampered.credential_subject.credit_score = 850;
+        let unknown_issuer = sample_credential("did:web:unknown.example", "did:example:carol", 600);
+        let batch = serde_json::json!([valid, tampered, { "id": "not a credential" }, unknown_issuer, valid]);
+
+        let request = test::TestRequest::post()
+            .uri("/verify/batch")
+            .set_json(&batch)
+            .to_request();
+        let outcomes: Vec<VerificationOutcome> = test::call_and_read_body_json(&app, request).await;
+
+        assert_eq!(outcomes.len(), 5);
+        assert!(outcomes[0].valid);
+        assert_eq!(
+            outcomes[1],
+            VerificationOutcome::invalid("Signature doesn't match the credential".to_string())
+        );
+        assert!(outcomes[2]
+            .error
+            .as_ref()
+            .unwrap()
+            .starts_with("Invalid credential: "));
+        assert_eq!(
+            outcomes[3],
+            VerificationOutcome::invalid(
+                "VC Error: Issuer DID is not resolvable: did:web:unknown.example".to_string()
+            )
+        );
+        assert!(outcomes[4].valid);
+    }
+
+    // Submit the presentation `build` makes for a challenge issued by the
+    // application, answering with the challenge it's given
+    async fn submit(
+        build: impl Fn(&str) -> serde_json::Value,
+        answer: impl Fn(&str) -> String,
+    ) -> (StatusCode, Vec<u8>) {
+        let issuer = "did:example:issuer";
+        let holder = "did:example:alice";
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(router_with(&[issuer, holder])))
+                .app_data(Data::new(IssuedChallenges::default()))
+                .service(issue_challenge)
+                .service(present),
+        )
+        .await;
+        let request = test::TestRequest::get().uri("/challenge").to_request();
+        let issued: ChallengeResponse = test::call_and_read_body_json(&app, request).await;
+        let body = serde_json::json!({
+            "presentation": build(&issued.challenge),
+            "challenge": answer(&issued.challenge),
+        });
+        let request = test::TestRequest::post()
+            .uri("/present")
+            .set_json(&body)
+            .to_request();
+        let response = test::call_service(&app, request).await;
+
+        (response.status(), test::read_body(response).await.to_vec())
+    }
+
+    fn sample_presentation(score: u32, challenge: &str) -> VerifiablePresentation {
+        let vc = sample_credential("did:example:issuer", "did:example:alice", score);
+        VerifiablePresentation::with_challenge(
+            "did:example:alice",
+            vec![vc],
+            &deterministic_keypair(FIXTURE_SEED),
+            Some(challenge),
+        )
+        .unwrap()
+    }
+
+    #[actix_web::test]
+    async fn test_present_reports_a_valid_presentation() {
+        let (status, report) = submit(
+            |challenge| serde_json::json!(sample_presentation(750, challenge)),
+            str::to_string,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let report: PresentationVerificationReport = serde_json::from_slice(&report).unwrap();
+        assert!(report.valid);
+        assert_eq!(report.holder_outcome, ProofOutcome::Valid);
+    }
+
+    #[actix_web::test]
+    async fn test_present_reports_a_tampered_presentation() {
+        let tampered = |challenge: &str| {
+            let mut presentation = sample_presentation(600, challenge);
+            presentation.verifiable_credential[0]
+                .credential_subject
+                .credit_score = 850;
+            serde_json::json!(presentation)
+        };
+
+        let (status, report) = submit(tampered, str::to_string).await;
+        assert_eq!(status, StatusCode::OK);
+        let report: PresentationVerificationReport = serde_json::from_slice(&report).unwrap();
+        assert!(!report.valid);
+        assert_eq!(report.holder_outcome, ProofOutcome::InvalidProof);
+        assert_eq!(report.credentials[0].outcome, ProofOutcome::InvalidProof);
+    }
+
+    #[actix_web::test]
+    async fn test_present_rejects_malformed_input() {
+        let (status, _) = submit(
+            |_| serde_json::json!({ "holder": "did:example:alice" }),
+            str::to_string,
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn test_present_accepts_only_issued_challenges() {
+        let (status, body) = submit(
+            |_| serde_json::json!(sample_presentation(750, "chosen-by-the-holder")),
+            |_| "chosen-by-the-holder".to_string(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body, b"Unknown, expired or already used challenge");
+
+        // An issued challenge is answered once
+        let challenges = IssuedChallenges::default();
+        let challenge = challenges.issue();
+        assert!(challenges.take(&challenge));
+        assert!(!challenges.take(&challenge));
+    }
+
+    #[actix_web::test]
+    async fn test_live_challenges_are_capped() {
+        let challenges = IssuedChallenges::default();
+        let first = challenges.issue();
+        let last = (0..MAX_LIVE_CHALLENGES)
+            .map(|_| challenges.issue())
+            .last()
+            .unwrap();
+        assert_eq!(challenges.0.lock().unwrap().len(), MAX_LIVE_CHALLENGES);
+
+        // The oldest made room for the newest
+        assert!(!challenges.take(&first));
+        assert!(challenges.take(&last));
+    }
+}
@@ -1,11 +1,12 @@
 use actix_cors::Cors;
 use actix_web::{dev::Server, web::Data, App, HttpServer};
-use std::{io::Error, net::TcpListener};
+use did::{CachingResolver, HttpResolver, MethodRouter};
+use std::{io::Error, net::TcpListener, time::Duration};
 use tracing_actix_web::TracingLogger;
 
 use crate::{
     configuration::Settings,
-    routes::{health_check, index, qr},
+    routes::{health_check, index, issue_challenge, present, qr, verify_batch, IssuedChallenges},
 };
 
 pub struct ApplicationBaseUrl(pub String);
@@ -16,6 +17,30 @@ pub struct Application {
 
 impl Application {
     pub async fn build(configuration: Settings) -> Result<Self, anyhow::Error> {
+        // DIDs are resolved with the configured registry, each document
+        // being reused for the cache TTL across requests. The telnet server
+        // keeps its registry in memory and doesn't serve it over HTTP, so its
+        // DIDs only resolve here once published to that registry.
+        let resolver = &configuration.resolver;
+        let mut router = MethodRouter::new();
+        for method in &resolver.methods {
+            router.register(
+                method,
+                Box::new(CachingResolver::new(
+                    HttpResolver::new(&resolver.base_url),
+                    Duration::from_secs(resolver.cache_ttl_seconds),
+                )),
+            );
+        }
+
+        Self::build_with_router(configuration, router).await
+    }
+
+    // Build the application resolving DIDs with the given router
+    pub async fn build_with_router(
+        configuration: Settings,
+        router: MethodRouter,
+    ) -> Result<Self, anyhow::Error> {
         let address = format!(
             "{}:{}",
             configuration.application.host, configuration.application.port
@@ -26,7 +51,7 @@ impl Application {
         ));
         let port = listener.local_addr().unwrap().port();
 
-        let server = run(listener, configuration.application.base_url).await?;
+        let server = run(listener, configuration.application.base_url, router).await?;
 
         Ok(Self { port, server })
     }
@@ -40,8 +65,14 @@ impl Application {
     }
 }
 
-async fn run(listener: TcpListener, base_url: String) -> Result<Server, anyhow::Error> {
+async fn run(
+    listener: TcpListener,
+    base_url: String,
+    router: MethodRouter,
+) -> Result<Server, anyhow::Error> {
     let base_url = Data::new(ApplicationBaseUrl(base_url));
+    let router = Data::new(router);
+    let challenges = Data::new(IssuedChallenges::default());
     let server = HttpServer::new(move || {
         let cors = Cors::default()
             .allow_any_origin()
@@ -55,7 +86,12 @@ async fn run(listener: TcpListener, base_url: String) -> Result<Server, anyhow::
             .service(index)
             .service(health_check)
             .service(qr)
+            .service(verify_batch)
+            .service(issue_challenge)
+            .service(present)
             .app_data(base_url.clone())
+            .app_data(router.clone())
+            .app_data(challenges.clone())
     })
     .listen(listener)?
     .run();